@@ -0,0 +1,200 @@
+//! Throughput and latency benchmark: publishers share the messages to send,
+//! every subscriber receives all of them.
+
+use client::{ConnectOptions, NatsClientError};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a subscriber waits for the next message before giving up.
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Messages carry their send time when they are at least this large.
+const TIMESTAMP_LEN: usize = 8;
+
+#[derive(Debug)]
+pub struct BenchOptions {
+    pub servers: Vec<String>,
+    pub connect: ConnectOptions,
+    pub subject: String,
+    pub msgs: usize,
+    pub size: usize,
+    pub pubs: usize,
+    pub subs: usize,
+}
+
+/// Outcome of a worker.
+struct Sample {
+    msgs: usize,
+    bytes: usize,
+    elapsed: Duration,
+    latencies: Vec<Duration>,
+}
+
+impl Sample {
+    fn rate(&self) -> f64 {
+        self.msgs as f64 / self.elapsed.as_secs_f64().max(1e-9)
+    }
+
+    fn throughput(&self) -> f64 {
+        self.bytes as f64 / self.elapsed.as_secs_f64().max(1e-9) / (1024.0 * 1024.0)
+    }
+}
+
+pub fn run(options: BenchOptions) -> Result<(), NatsClientError> {
+    println!(
+        "Starting benchmark [msgs={}, size={}, pubs={}, subs={}]",
+        options.msgs, options.size, options.pubs, options.subs
+    );
+    let options = Arc::new(options);
+    // Publishers start once every subscriber is ready.
+    let ready = Arc::new(Barrier::new(options.pubs + options.subs));
+    let start = Instant::now();
+    let mut subs = Vec::new();
+    for _ in 0..options.subs {
+        let (options, ready) = (options.clone(), ready.clone());
+        subs.push(thread::spawn(move || subscriber(&options, &ready, start)));
+    }
+    let mut pubs = Vec::new();
+    for i in 0..options.pubs {
+        // The first publishers send the remainder.
+        let mut msgs = options.msgs / options.pubs;
+        if i < options.msgs % options.pubs {
+            msgs += 1;
+        }
+        let (options, ready) = (options.clone(), ready.clone());
+        pubs.push(thread::spawn(move || {
+            publisher(&options, &ready, start, msgs)
+        }));
+    }
+    let pubs = join(pubs)?;
+    let subs = join(subs)?;
+    report("Pub", &pubs);
+    report("Sub", &subs);
+    let mut latencies: Vec<_> = subs.into_iter().flat_map(|s| s.latencies).collect();
+    if !latencies.is_empty() {
+        latencies.sort();
+        let percentile = |p: usize| latencies[(latencies.len() - 1) * p / 100];
+        println!(
+            "Latency p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+            percentile(50),
+            percentile(90),
+            percentile(99),
+            percentile(100)
+        );
+    }
+    Ok(())
+}
+
+fn publisher(
+    options: &BenchOptions,
+    ready: &Barrier,
+    start: Instant,
+    msgs: usize,
+) -> Result<Sample, NatsClientError> {
+    let mut nc = options
+        .connect
+        .clone()
+        .verbose(false)
+        .connect(&options.servers[..])?;
+    let mut payload = vec![0; options.size];
+    ready.wait();
+    let begin = Instant::now();
+    for _ in 0..msgs {
+        if payload.len() >= TIMESTAMP_LEN {
+            let sent = start.elapsed().as_nanos() as u64;
+            payload[..TIMESTAMP_LEN].copy_from_slice(&sent.to_le_bytes());
+        }
+        nc.publish(&options.subject, &payload, None)?;
+    }
+    nc.flush()?;
+    Ok(Sample {
+        msgs,
+        bytes: msgs * options.size,
+        elapsed: begin.elapsed(),
+        latencies: Vec::new(),
+    })
+}
+
+fn subscriber(
+    options: &BenchOptions,
+    ready: &Barrier,
+    start: Instant,
+) -> Result<Sample, NatsClientError> {
+    // In verbose mode the subscription is acknowledged before returning.
+    let mut nc = options.connect.clone().connect(&options.servers[..])?;
+    let sub = nc.subscribe(&options.subject, None)?;
+    ready.wait();
+    let mut sample = Sample {
+        msgs: 0,
+        bytes: 0,
+        elapsed: Duration::default(),
+        latencies: Vec::with_capacity(options.msgs),
+    };
+    let mut begin = None;
+    while sample.msgs < options.msgs {
+        let event = match nc.next_msg(sub.channel(), RECEIVE_TIMEOUT)? {
+            Some(event) => event,
+            None => {
+                eprintln!(
+                    "Subscriber timed out after {} of {} messages",
+                    sample.msgs, options.msgs
+                );
+                break;
+            }
+        };
+        let begin = *begin.get_or_insert_with(Instant::now);
+        sample.msgs += 1;
+        sample.bytes += event.msg.len();
+        sample.elapsed = begin.elapsed();
+        if event.msg.len() >= TIMESTAMP_LEN {
+            let mut sent = [0; TIMESTAMP_LEN];
+            sent.copy_from_slice(&event.msg[..TIMESTAMP_LEN]);
+            let sent = Duration::from_nanos(u64::from_le_bytes(sent));
+            sample.latencies.push(start.elapsed().saturating_sub(sent));
+        }
+    }
+    Ok(sample)
+}
+
+fn join(
+    workers: Vec<thread::JoinHandle<Result<Sample, NatsClientError>>>,
+) -> Result<Vec<Sample>, NatsClientError> {
+    workers
+        .into_iter()
+        .map(|worker| worker.join().expect("benchmark worker panicked"))
+        .collect()
+}
+
+fn report(name: &str, samples: &[Sample]) {
+    if samples.is_empty() {
+        return;
+    }
+    let total = Sample {
+        msgs: samples.iter().map(|s| s.msgs).sum(),
+        bytes: samples.iter().map(|s| s.bytes).sum(),
+        elapsed: samples.iter().map(|s| s.elapsed).max().unwrap_or_default(),
+        latencies: Vec::new(),
+    };
+    println!(
+        "{:<8} {:>12} {:>14} {:>10} {:>12}",
+        name, "msgs", "msgs/sec", "MB/sec", "elapsed"
+    );
+    for (i, sample) in samples.iter().enumerate() {
+        row(&format!("[{}]", i + 1), sample);
+    }
+    if samples.len() > 1 {
+        row("total", &total);
+    }
+}
+
+fn row(label: &str, sample: &Sample) {
+    println!(
+        "{:<8} {:>12} {:>14.0} {:>10.2} {:>12.3?}",
+        label,
+        sample.msgs,
+        sample.rate(),
+        sample.throughput(),
+        sample.elapsed
+    );
+}
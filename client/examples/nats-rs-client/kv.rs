@@ -0,0 +1,79 @@
+//! Inspection of key-value buckets from the shell.
+
+use crate::{interrupted, text, POLL_INTERVAL};
+use client::kv::{Entry, Operation};
+use client::{Client, NatsClientError};
+use std::process;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug, Clone)]
+pub enum KvCommand {
+    #[structopt(name = "put", about = "Sets the value of a key")]
+    Put {
+        bucket: String,
+        key: String,
+        value: String,
+    },
+    #[structopt(name = "get", about = "Prints the value of a key")]
+    Get { bucket: String, key: String },
+    #[structopt(name = "del", about = "Deletes a key, keeping its history")]
+    Del { bucket: String, key: String },
+    #[structopt(name = "watch", about = "Prints the values of keys as they change")]
+    Watch {
+        bucket: String,
+        /// Keys to watch, may contain wildcards
+        #[structopt(default_value = ">")]
+        keys: String,
+    },
+    #[structopt(name = "ls", about = "Lists the keys having a value")]
+    Ls { bucket: String },
+}
+
+pub fn run(nc: &mut Client, cmd: KvCommand) -> Result<(), NatsClientError> {
+    match cmd {
+        KvCommand::Put { bucket, key, value } => {
+            let revision = nc.jetstream().bucket(&bucket)?.put(&key, &value)?;
+            println!("Put {} > {} at revision {}", bucket, key, revision);
+        }
+        KvCommand::Get { bucket, key } => match nc.jetstream().bucket(&bucket)?.get(&key)? {
+            Some(entry) => println!("{}", text(&entry.value)),
+            None => {
+                eprintln!("No value for {} > {}", bucket, key);
+                process::exit(1);
+            }
+        },
+        KvCommand::Del { bucket, key } => {
+            nc.jetstream().bucket(&bucket)?.delete(&key)?;
+            println!("Deleted {} > {}", bucket, key);
+        }
+        KvCommand::Watch { bucket, keys } => {
+            let mut kv = nc.jetstream().bucket(&bucket)?;
+            let mut watch = kv.watch(&keys)?;
+            eprintln!("Watching {} > {}", bucket, keys);
+            while !interrupted() {
+                if let Some(entry) = watch.next(POLL_INTERVAL)? {
+                    print_entry(&entry);
+                }
+            }
+        }
+        KvCommand::Ls { bucket } => {
+            for key in nc.jetstream().bucket(&bucket)?.keys()? {
+                println!("{}", key);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn print_entry(entry: &Entry) {
+    match entry.operation {
+        Operation::Put => println!(
+            "[#{}] {} : '{}'",
+            entry.revision,
+            entry.key,
+            text(&entry.value)
+        ),
+        Operation::Delete => println!("[#{}] {} deleted", entry.revision, entry.key),
+        Operation::Purge => println!("[#{}] {} purged", entry.revision, entry.key),
+    }
+}
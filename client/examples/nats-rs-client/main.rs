@@ -1,14 +1,63 @@
-use client;
+mod bench;
+mod kv;
+mod output;
+mod record;
+mod repl;
+
+use bench::BenchOptions;
+use client::{Client, ConnectOptions, ErrorKind, Event, NatsClientError, TlsConfig};
+use kv::KvCommand;
+use output::{Format, Output};
 use quicli::prelude::*;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
 use structopt::StructOpt;
 
+/// How often the receive loops check whether Ctrl-C was pressed.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long to wait for the messages in flight when draining.
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Set by the SIGINT handler, the commands then drain and exit.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
 #[derive(Debug, StructOpt)]
 struct Cli {
-    /// NAT server, provider default demo server
-    #[structopt(long, short, default_value = "nats://demo.nats.io")]
-    server: String,
+    /// NATS servers, repeated or comma separated, the client failing over
+    /// between them. Defaults to the demo server
+    #[structopt(
+        long = "server",
+        short,
+        number_of_values = 1,
+        default_value = "nats://demo.nats.io"
+    )]
+    servers: Vec<String>,
+
+    /// Client certificate presented to the server
+    #[structopt(long, parse(from_os_str), requires = "tlskey")]
+    tlscert: Option<PathBuf>,
+
+    /// Private key of the client certificate
+    #[structopt(long, parse(from_os_str), requires = "tlscert")]
+    tlskey: Option<PathBuf>,
+
+    /// CA certificate used to verify the server
+    #[structopt(long, parse(from_os_str))]
+    tlsca: Option<PathBuf>,
 
-    /// Command: pub, sub, request, reply
+    /// Start the TLS handshake before the server sends INFO
+    #[structopt(long)]
+    tls_first: bool,
+
+    /// Command: pub, sub, qsub, request, reply, bench, kv, rtt, record, replay,
+    /// repl
     #[structopt(subcommand)]
     cmd: Command,
 }
@@ -17,37 +66,447 @@ struct Cli {
 enum Command {
     /// The type of operation, can be one of pub, sub, qsub, req, reply.
     #[structopt(name = "pub", about = "Publishes a message to a given subject")]
-    Pub { subject: String, msg: String },
+    Pub {
+        subject: String,
+        /// Message, where {{index}} is replaced by the number of the message,
+        /// or - to read it from stdin
+        #[structopt(required_unless = "file")]
+        msg: Option<String>,
+        /// Publish the contents of a file
+        #[structopt(long, parse(from_os_str), conflicts_with = "msg")]
+        file: Option<PathBuf>,
+        /// Subject the receivers should reply to
+        #[structopt(long)]
+        reply_to: Option<String>,
+        /// Number of messages to publish
+        #[structopt(long, default_value = "1")]
+        count: usize,
+        /// Pause between messages, e.g. 100ms or 1s
+        #[structopt(long, parse(try_from_str = parse_duration))]
+        sleep: Option<Duration>,
+    },
     #[structopt(name = "sub", about = "Subscribes to a given subject")]
-    Sub { subject: String },
+    Sub {
+        /// Subjects to subscribe to
+        subjects: Vec<String>,
+        /// Additional subject to subscribe to, may be repeated
+        #[structopt(long = "subject", number_of_values = 1)]
+        extra_subjects: Vec<String>,
+        /// How to print the messages: json, raw or pretty
+        #[structopt(long, default_value = "pretty")]
+        output: Output,
+        /// Prefix the messages with the time they were received
+        #[structopt(long)]
+        timestamp: bool,
+        /// Prefix the raw payloads with their subject
+        #[structopt(long)]
+        show_subject: bool,
+        /// Exit after receiving this many messages
+        #[structopt(long)]
+        max_msgs: Option<u64>,
+    },
+    #[structopt(
+        name = "qsub",
+        about = "Subscribes to a given subject as a member of a queue group"
+    )]
+    QSub { subject: String, queue: String },
     #[structopt(name = "request", about = "Sends a request and waits on reply")]
-    Request { subject: String, msg: String },
+    Request {
+        subject: String,
+        msg: String,
+        /// How long to wait for the reply, e.g. 500ms, 2s or 1m
+        #[structopt(long, default_value = "2s", parse(try_from_str = parse_duration))]
+        timeout: Duration,
+    },
     #[structopt(name = "reply", about = "Listens for requests and sends the reply")]
-    Reply { subject: String, resp: String },
+    Reply {
+        subject: String,
+        /// Response, where {{count}} is replaced by the number of the request
+        /// and {{subject}} by its subject
+        resp: String,
+        /// Queue group to join, balancing the requests between responders
+        #[structopt(long)]
+        queue: Option<String>,
+    },
+    #[structopt(
+        name = "bench",
+        about = "Measures the throughput and latency of publishers and subscribers"
+    )]
+    Bench {
+        subject: String,
+        /// Number of messages to publish
+        #[structopt(long, default_value = "100000")]
+        msgs: usize,
+        /// Size of the messages in bytes
+        #[structopt(long, default_value = "128")]
+        size: usize,
+        /// Number of concurrent publishers
+        #[structopt(long = "pub", default_value = "1")]
+        pubs: usize,
+        /// Number of concurrent subscribers
+        #[structopt(long = "sub", default_value = "0")]
+        subs: usize,
+    },
+    #[structopt(
+        name = "kv",
+        about = "Reads and watches the keys of a key-value bucket"
+    )]
+    Kv(KvCommand),
+    #[structopt(
+        name = "rtt",
+        about = "Measures the round-trip time to each of the servers"
+    )]
+    Rtt {
+        /// Number of round trips per server
+        #[structopt(long, default_value = "5")]
+        count: u32,
+    },
+    #[structopt(name = "record", about = "Records the messages of a subject to a file")]
+    Record {
+        subject: String,
+        /// File receiving one JSON message per line
+        #[structopt(long, parse(from_os_str))]
+        out: PathBuf,
+    },
+    #[structopt(
+        name = "replay",
+        about = "Publishes recorded messages with their original timing"
+    )]
+    Replay {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+        /// Replay speed, e.g. 2x for twice as fast
+        #[structopt(long, default_value = "1x", parse(try_from_str = record::parse_speed))]
+        speed: f64,
+    },
+    #[structopt(
+        name = "repl",
+        about = "Runs commands typed interactively over a single connection"
+    )]
+    Repl,
 }
 
 fn main() -> CliResult {
     let args = Cli::from_args();
-    let mut nc = client::Client::new(args.server).unwrap();
+    let options = connect_options(&args);
+    // Every server is measured with a connection of its own.
+    if let Command::Rtt { count } = args.cmd {
+        rtt(&args.servers, &options, count);
+        return Ok(());
+    }
+    let mut nc = options.clone().connect(&args.servers[..])?;
+    // The long running commands drain on Ctrl-C, a second one exits at once.
+    if !matches!(args.cmd, Command::Bench { .. } | Command::Request { .. }) {
+        ctrlc::set_handler(|| {
+            if INTERRUPTED.swap(true, Ordering::SeqCst) {
+                process::exit(130);
+            }
+        })?;
+    }
 
     match args.cmd {
-        Command::Pub { subject, msg } => {
-            unimplemented!() // TODO
+        Command::Pub {
+            subject,
+            msg,
+            file,
+            reply_to,
+            count,
+            sleep,
+        } => {
+            // Binary payloads are published as is, without templating.
+            let data = match (file, msg.as_deref()) {
+                (Some(path), _) => Some(fs::read(path)?),
+                (None, Some("-")) => {
+                    let mut data = Vec::new();
+                    io::stdin().read_to_end(&mut data)?;
+                    Some(data)
+                }
+                _ => None,
+            };
+            let msg = msg.unwrap_or_default();
+            for index in 1..=count {
+                if interrupted() {
+                    break;
+                }
+                if index > 1 {
+                    if let Some(sleep) = sleep {
+                        thread::sleep(sleep);
+                    }
+                }
+                match data {
+                    Some(ref data) => {
+                        nc.publish(&subject, data, reply_to.as_deref())?;
+                        println!("Published [{}] : {} bytes", subject, data.len());
+                    }
+                    None => {
+                        let msg = msg.replace("{{index}}", &index.to_string());
+                        nc.publish(&subject, &msg, reply_to.as_deref())?;
+                        println!("Published [{}] : '{}'", subject, msg);
+                    }
+                }
+                // Messages are buffered until flushed.
+                if sleep.is_some() {
+                    nc.flush()?;
+                }
+            }
+            nc.flush()?;
         }
-        Command::Sub { subject } => {
-            let sub = nc.subscribe(&subject, None).unwrap();
-            println!("Listening on {}", subject);
-            for event in nc.events() {
-                println!(
-                    "Received {}",
-                    String::from_utf8(event.msg).expect("Not utf8 encoded")
-                );
+        Command::Sub {
+            mut subjects,
+            extra_subjects,
+            output,
+            timestamp,
+            show_subject,
+            max_msgs,
+        } => {
+            subjects.extend(extra_subjects);
+            if subjects.is_empty() {
+                eprintln!("At least one subject is required");
+                process::exit(2);
             }
+            let mut subs = HashMap::new();
+            for subject in &subjects {
+                let sub = nc.subscribe(subject, None)?;
+                // Every subscription may deliver all of them.
+                if let Some(max_msgs) = max_msgs {
+                    nc.auto_unsubscribe(sub.channel(), max_msgs)?;
+                }
+                subs.insert(sub.channel().sid, (subject.clone(), sub));
+            }
+            // Only the messages go to stdout in machine readable modes.
+            eprintln!("Listening on {}", subjects.join(", "));
+            let format = Format {
+                output,
+                timestamp,
+                show_subject,
+            };
+            let print = |i: usize, event: &Event| {
+                // The subscription is only told apart when there are several.
+                let subscription = match subs.get(&event.channel.sid) {
+                    Some((subject, _)) if subs.len() > 1 => Some(subject.as_str()),
+                    _ => None,
+                };
+                output::print_event(format, i, subscription, event)
+            };
+            let mut count = 0;
+            while let Some(event) = next_event(&mut nc)? {
+                count += 1;
+                print(count, &event)?;
+                if max_msgs == Some(count as u64) {
+                    return Ok(());
+                }
+            }
+            for event in nc.drain(DRAIN_TIMEOUT)? {
+                count += 1;
+                print(count, &event)?;
+            }
+        }
+        Command::QSub { subject, queue } => {
+            let _sub = nc.queue_subscribe(&subject, &queue)?;
+            println!("Listening on {} in queue group {}", subject, queue);
+            while let Some(event) = next_event(&mut nc)? {
+                println!("Received [{}] : '{}'", event.subject, text(&event.msg));
+            }
+            for event in nc.drain(DRAIN_TIMEOUT)? {
+                println!("Received [{}] : '{}'", event.subject, text(&event.msg));
+            }
+        }
+        Command::Bench {
+            subject,
+            msgs,
+            size,
+            pubs,
+            subs,
+        } => {
+            bench::run(BenchOptions {
+                servers: args.servers,
+                connect: options,
+                subject,
+                msgs,
+                size,
+                pubs,
+                subs,
+            })?;
         }
-        _ => {
-            unimplemented!() // TODO
+        Command::Kv(cmd) => kv::run(&mut nc, cmd)?,
+        Command::Rtt { .. } => unreachable!(),
+        Command::Record { subject, out } => {
+            let count = record::record(&mut nc, &subject, &out)?;
+            eprintln!("Recorded {} messages", count);
+        }
+        Command::Repl => repl::run(&mut nc)?,
+        Command::Replay { file, speed } => {
+            let count = record::replay(&mut nc, &file, speed)?;
+            println!("Replayed {} messages", count);
+        }
+        Command::Request {
+            subject,
+            msg,
+            timeout,
+        } => {
+            println!("Published [{}] : '{}'", subject, msg);
+            match nc.request_timeout(&subject, &msg, timeout) {
+                Ok(reply) => {
+                    println!("Received [{}] : '{}'", reply.subject, text(&reply.msg));
+                    if let Some(headers) = reply.headers {
+                        for (name, values) in headers.iter() {
+                            for value in values {
+                                println!("  {}: {}", name, value);
+                            }
+                        }
+                    }
+                }
+                Err(ref e) if e.kind() == ErrorKind::NoResponders => {
+                    eprintln!("No responders are available on {}", subject);
+                    process::exit(1);
+                }
+                Err(ref e) if e.kind() == ErrorKind::Timeout => {
+                    eprintln!("No reply received within {:?}", timeout);
+                    process::exit(1);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Command::Reply {
+            subject,
+            resp,
+            queue,
+        } => {
+            let sub = nc.subscribe(&subject, queue.as_deref())?;
+            println!("Listening on {}", subject);
+            let mut count = 0;
+            while !interrupted() {
+                if let Some(event) = nc.next_msg(sub.channel(), POLL_INTERVAL)? {
+                    count += 1;
+                    respond(&mut nc, &resp, count, &event)?;
+                }
+            }
+            // The requests still in flight are answered before exiting.
+            for event in nc.drain(DRAIN_TIMEOUT)? {
+                count += 1;
+                respond(&mut nc, &resp, count, &event)?;
+            }
         }
     }
 
+    if interrupted() {
+        let stats = nc.stats();
+        eprintln!(
+            "Interrupted: received {} msgs ({} bytes), sent {} msgs ({} bytes)",
+            stats.in_msgs, stats.in_bytes, stats.out_msgs, stats.out_bytes
+        );
+    }
     Ok(())
 }
+
+/// Connect options for the TLS flags, any of them securing the connection.
+/// Failovers between the servers are reported on stderr.
+fn connect_options(args: &Cli) -> ConnectOptions {
+    let options = ConnectOptions::new()
+        .disconnect_callback(|| eprintln!("Disconnected, trying the other servers"))
+        .reconnect_callback(|| eprintln!("Reconnected"));
+    if args.tlscert.is_none() && args.tlsca.is_none() && !args.tls_first {
+        return options;
+    }
+    let mut tls_config = TlsConfig::new();
+    if let Some(ref ca) = args.tlsca {
+        tls_config = tls_config.add_root_certificate(ca);
+    }
+    if let (Some(cert), Some(key)) = (&args.tlscert, &args.tlskey) {
+        tls_config = tls_config.client_certificate(cert, key);
+    }
+    options
+        .tls_required(true)
+        .tls_first(args.tls_first)
+        .tls_config(tls_config)
+}
+
+/// Print the min/avg/max round-trip time to every server of `servers`, each
+/// of them possibly a comma separated list.
+fn rtt(servers: &[String], options: &ConnectOptions, count: u32) {
+    for server in servers.iter().flat_map(|s| s.split(',')).map(str::trim) {
+        let mut nc = match options.clone().connect(server) {
+            Ok(nc) => nc,
+            Err(e) => {
+                println!("{}: {}", server, e);
+                continue;
+            }
+        };
+        let rtts: Result<Vec<_>, _> = (0..count.max(1)).map(|_| nc.rtt()).collect();
+        match rtts {
+            Ok(rtts) => println!(
+                "{}: min {:?}, avg {:?}, max {:?}",
+                server,
+                rtts.iter().min().unwrap(),
+                rtts.iter().sum::<Duration>() / rtts.len() as u32,
+                rtts.iter().max().unwrap()
+            ),
+            Err(e) => println!("{}: {}", server, e),
+        }
+    }
+}
+
+fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Next message of any subscription, `None` once Ctrl-C was pressed.
+fn next_event(nc: &mut Client) -> Result<Option<Event>, NatsClientError> {
+    while !interrupted() {
+        if let Some(event) = nc.next_event_timeout(POLL_INTERVAL)? {
+            return Ok(Some(event));
+        }
+    }
+    Ok(None)
+}
+
+/// Answer a request received by the reply command.
+fn respond(
+    nc: &mut Client,
+    resp: &str,
+    count: usize,
+    event: &Event,
+) -> Result<(), NatsClientError> {
+    println!(
+        "[#{}] Received on [{}]: '{}'",
+        count,
+        event.subject,
+        text(&event.msg)
+    );
+    let reply = match event.inbox {
+        Some(ref reply) => reply,
+        None => return Ok(()),
+    };
+    let resp = resp
+        .replace("{{count}}", &count.to_string())
+        .replace("{{subject}}", &event.subject);
+    nc.publish(reply, &resp, None)?;
+    nc.flush()
+}
+
+/// Parse a duration such as `250ms`, `2s` or `1m`, in seconds by default.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (value, unit) = match s.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(pos) => s.split_at(pos),
+        None => (s, "s"),
+    };
+    let value: f64 = value
+        .parse()
+        .map_err(|_| format!("Invalid duration: {}", s))?;
+    let secs = match unit {
+        "ms" => value / 1000.0,
+        "s" => value,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        _ => return Err(format!("Invalid duration unit: {}", unit)),
+    };
+    if !secs.is_finite() || secs < 0.0 {
+        return Err(format!("Invalid duration: {}", s));
+    }
+    Ok(Duration::from_secs_f64(secs))
+}
+
+/// Payload as text, with invalid UTF-8 sequences replaced.
+fn text(msg: &[u8]) -> String {
+    String::from_utf8_lossy(msg).into_owned()
+}
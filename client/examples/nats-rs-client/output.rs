@@ -0,0 +1,149 @@
+//! Formatting of the received messages.
+
+use client::Event;
+use serde_json::json;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Output {
+    /// One JSON object per line, with the payload in base64.
+    Json,
+    /// Payloads written as is, without separators.
+    Raw,
+    /// Headers and payload under a line describing the message.
+    Pretty,
+}
+
+/// How the received messages are printed.
+#[derive(Clone, Copy, Debug)]
+pub struct Format {
+    pub output: Output,
+    /// Prefix the messages with the time they were received.
+    pub timestamp: bool,
+    /// Prefix the raw payloads with their subject, the other outputs always
+    /// show it.
+    pub show_subject: bool,
+}
+
+impl FromStr for Output {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Output, String> {
+        match s {
+            "json" => Ok(Output::Json),
+            "raw" => Ok(Output::Raw),
+            "pretty" => Ok(Output::Pretty),
+            _ => Err(format!(
+                "Unknown output {}, expected json, raw or pretty",
+                s
+            )),
+        }
+    }
+}
+
+/// Write the `count`th received message to stdout, naming the subject of
+/// the `subscription` it was received on if given.
+pub fn print_event(
+    format: Format,
+    count: usize,
+    subscription: Option<&str>,
+    event: &Event,
+) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let timestamp = if format.timestamp {
+        Some(rfc3339(SystemTime::now()))
+    } else {
+        None
+    };
+    match format.output {
+        Output::Json => {
+            let headers = event.headers.as_ref().map(|headers| {
+                headers
+                    .iter()
+                    .map(|(name, values)| (name.clone(), json!(values)))
+                    .collect::<serde_json::Map<_, _>>()
+            });
+            let mut line = json!({
+                "subject": event.subject,
+                "reply": event.inbox,
+                "headers": headers,
+                "data_base64": base64::encode(&event.msg),
+            });
+            if let Some(subscription) = subscription {
+                line["subscription"] = json!(subscription);
+            }
+            if let Some(timestamp) = timestamp {
+                line["timestamp"] = json!(timestamp);
+            }
+            writeln!(out, "{}", line)?;
+        }
+        Output::Raw => {
+            // Payloads are only separated when prefixed.
+            if let Some(ref timestamp) = timestamp {
+                write!(out, "{} ", timestamp)?;
+            }
+            if format.show_subject {
+                write!(out, "{} ", event.subject)?;
+            }
+            out.write_all(&event.msg)?;
+            if timestamp.is_some() || format.show_subject {
+                writeln!(out)?;
+            }
+            out.flush()?;
+        }
+        Output::Pretty => {
+            if let Some(timestamp) = timestamp {
+                write!(out, "{} ", timestamp)?;
+            }
+            write!(out, "[#{}] Received on [{}]", count, event.subject)?;
+            if let Some(subscription) = subscription {
+                write!(out, " via [{}]", subscription)?;
+            }
+            if let Some(ref reply) = event.inbox {
+                write!(out, " with reply [{}]", reply)?;
+            }
+            writeln!(out)?;
+            if let Some(ref headers) = event.headers {
+                for (name, values) in headers.iter() {
+                    for value in values {
+                        writeln!(out, "{}: {}", name, value)?;
+                    }
+                }
+            }
+            writeln!(out, "{}", String::from_utf8_lossy(&event.msg))?;
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
+
+/// Format `time` as an RFC 3339 UTC timestamp with milliseconds, e.g.
+/// `2022-01-31T12:00:00.250Z`.
+fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // Civil date from the days since the epoch, after Howard Hinnant.
+    let z = days as i64 + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        since_epoch.subsec_millis()
+    )
+}
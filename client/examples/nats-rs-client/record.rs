@@ -0,0 +1,118 @@
+//! Capture of messages to a newline delimited JSON file, and their replay
+//! with the same relative timing.
+
+use crate::{interrupted, next_event, DRAIN_TIMEOUT};
+use client::{Client, Event, Headers, NatsClientError};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A captured message, one per line of the file.
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    /// Milliseconds since the first message.
+    offset_ms: u64,
+    subject: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reply: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    headers: BTreeMap<String, Vec<String>>,
+    data_base64: String,
+}
+
+impl Record {
+    fn new(offset: Duration, event: &Event) -> Record {
+        let headers = event.headers.as_ref().map_or_else(BTreeMap::new, |h| {
+            h.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+        });
+        Record {
+            offset_ms: offset.as_millis() as u64,
+            subject: event.subject.clone(),
+            reply: event.inbox.clone(),
+            headers,
+            data_base64: base64::encode(&event.msg),
+        }
+    }
+}
+
+/// Write the messages received on `subject` to `out` until Ctrl-C is
+/// pressed, returning their number.
+pub fn record(nc: &mut Client, subject: &str, out: &Path) -> Result<usize, NatsClientError> {
+    let mut file = BufWriter::new(File::create(out)?);
+    let _sub = nc.subscribe(subject, None)?;
+    eprintln!("Recording {} to {}", subject, out.display());
+    let mut start = None;
+    let mut count = 0;
+    let mut write = |event: &Event| -> Result<(), NatsClientError> {
+        let start = *start.get_or_insert_with(Instant::now);
+        let line =
+            serde_json::to_string(&Record::new(start.elapsed(), event)).map_err(io::Error::from)?;
+        writeln!(file, "{}", line)?;
+        count += 1;
+        Ok(())
+    };
+    while let Some(event) = next_event(nc)? {
+        write(&event)?;
+    }
+    for event in nc.drain(DRAIN_TIMEOUT)? {
+        write(&event)?;
+    }
+    file.flush()?;
+    Ok(count)
+}
+
+/// Publish the messages recorded in `input`, `speed` times faster than they
+/// were received. Returns their number.
+pub fn replay(nc: &mut Client, input: &Path, speed: f64) -> Result<usize, NatsClientError> {
+    let start = Instant::now();
+    let mut count = 0;
+    for line in BufReader::new(File::open(input)?).lines() {
+        let line = line?;
+        if interrupted() {
+            break;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Record = serde_json::from_str(&line).map_err(io::Error::from)?;
+        let due = Duration::from_millis(record.offset_ms).div_f64(speed);
+        if let Some(wait) = due.checked_sub(start.elapsed()) {
+            // Messages are sent in bursts otherwise.
+            nc.flush()?;
+            thread::sleep(wait);
+        }
+        let data = base64::decode(&record.data_base64)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let reply = record.reply.as_deref();
+        if record.headers.is_empty() {
+            nc.publish(&record.subject, &data, reply)?;
+        } else {
+            let mut headers = Headers::new();
+            for (name, values) in &record.headers {
+                for value in values {
+                    headers.append(name, value);
+                }
+            }
+            nc.publish_with_headers(&record.subject, &headers, &data, reply)?;
+        }
+        count += 1;
+    }
+    nc.flush()?;
+    Ok(count)
+}
+
+/// Parse a replay speed such as `2x` or `0.5`.
+pub fn parse_speed(s: &str) -> Result<f64, String> {
+    let speed: f64 = s
+        .trim_end_matches('x')
+        .parse()
+        .map_err(|_| format!("Invalid speed: {}", s))?;
+    if !speed.is_finite() || speed <= 0.0 {
+        return Err(format!("Invalid speed: {}", s));
+    }
+    Ok(speed)
+}
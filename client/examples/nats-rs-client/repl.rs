@@ -0,0 +1,107 @@
+//! Interactive prompt sharing a single connection between commands.
+
+use crate::{interrupted, text, POLL_INTERVAL};
+use client::{Client, NatsClientError, Subscription};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+const HELP: &str = "\
+Commands:
+  sub <subject> [queue]    Subscribe, printing the messages as they arrive
+  unsub <subject>          Cancel a subscription
+  pub <subject> [msg]      Publish a message
+  req <subject> [msg]      Send a request and print its reply
+  help                     Show this help
+  quit                     Exit";
+
+/// How long `req` waits for the reply.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub fn run(nc: &mut Client) -> Result<(), NatsClientError> {
+    // Stdin is read on its own thread so that messages are printed while
+    // waiting for the next command.
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines().map_while(Result::ok) {
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    let mut subs: HashMap<String, Subscription> = HashMap::new();
+    println!("Type help for the list of commands");
+    prompt()?;
+    while !interrupted() {
+        match rx.try_recv() {
+            Ok(line) => {
+                let mut words = line.trim().splitn(2, ' ');
+                let cmd = words.next().unwrap_or_default();
+                let args = words.next().unwrap_or_default().trim();
+                match execute(nc, &mut subs, cmd, args) {
+                    Ok(true) => {}
+                    Ok(false) => break,
+                    // The connection is kept after a failed command.
+                    Err(e) => println!("Error: {}", e),
+                }
+                prompt()?;
+                continue;
+            }
+            Err(TryRecvError::Disconnected) => break,
+            Err(TryRecvError::Empty) => {}
+        }
+        if let Some(event) = nc.next_event_timeout(POLL_INTERVAL)? {
+            println!("\n[{}] '{}'", event.subject, text(&event.msg));
+            prompt()?;
+        }
+    }
+    Ok(())
+}
+
+/// Execute a command, returning `false` to exit.
+fn execute(
+    nc: &mut Client,
+    subs: &mut HashMap<String, Subscription>,
+    cmd: &str,
+    args: &str,
+) -> Result<bool, NatsClientError> {
+    let mut words = args.splitn(2, ' ');
+    let subject = words.next().unwrap_or_default();
+    let rest = words.next().unwrap_or_default().trim();
+    match cmd {
+        "" => {}
+        "sub" | "unsub" | "pub" | "req" if subject.is_empty() => {
+            println!("Usage: {} <subject>", cmd);
+        }
+        "sub" => {
+            let queue = Some(rest).filter(|q| !q.is_empty());
+            let sub = nc.subscribe(subject, queue)?;
+            subs.insert(subject.to_owned(), sub);
+            println!("Subscribed to {}", subject);
+        }
+        "unsub" => match subs.remove(subject) {
+            Some(_) => println!("Unsubscribed from {}", subject),
+            None => println!("Not subscribed to {}", subject),
+        },
+        "pub" => {
+            nc.publish(subject, rest, None)?;
+            nc.flush()?;
+            println!("Published [{}] : '{}'", subject, rest);
+        }
+        "req" => {
+            let reply = nc.request_timeout(subject, rest, REQUEST_TIMEOUT)?;
+            println!("Received [{}] : '{}'", reply.subject, text(&reply.msg));
+        }
+        "help" => println!("{}", HELP),
+        "quit" | "exit" => return Ok(false),
+        _ => println!("Unknown command {}, type help for the list", cmd),
+    }
+    Ok(true)
+}
+
+fn prompt() -> io::Result<()> {
+    print!("> ");
+    io::stdout().flush()
+}
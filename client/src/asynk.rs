@@ -0,0 +1,784 @@
+//! Asynchronous client running on tokio, enabled by the `async` feature.
+//!
+//! A task reads from the server and dispatches the messages to the
+//! subscriptions, another one writes the commands queued by the client and
+//! flushes whenever the queue is empty. SUB and UNSUB go through a separate,
+//! unbounded queue written first, so dropping a subscription never loses its
+//! UNSUB to a full queue. The connection is not re-established
+//! when it is lost: subscriptions end and further calls fail.
+
+use crate::client::{
+  check_inbox, check_queue, check_subject, hpub_frame, new_inbox, pub_frame, server_error, Channel,
+  ConnectInfo, Event, Info, MsgArgs, ServerInfo, ToStringVec, NO_RESPONDERS_STATUS,
+};
+use crate::errors::{ErrorKind::*, *};
+use crate::headers::Headers;
+use crate::options::ConnectOptions;
+use crate::telemetry;
+use futures_core::Stream;
+use std::{
+  collections::HashMap,
+  future::Future,
+  io,
+  pin::Pin,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+  },
+  task::{Context, Poll},
+  time::{Duration, Instant},
+};
+use tokio::{
+  io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, BufWriter},
+  net::{
+    tcp::{OwnedReadHalf, OwnedWriteHalf},
+    TcpStream,
+  },
+  sync::{mpsc, oneshot},
+  task::JoinHandle,
+  time,
+};
+
+const COMMAND_QUEUE_SIZE: usize = 1024;
+const SUBSCRIPTION_QUEUE_SIZE: usize = 64 * 1024;
+// Replies to every request are received on this subscription.
+const RESP_MUX_SID: u64 = 1;
+
+#[derive(Debug)]
+enum Command {
+  Frame(Vec<u8>),
+  Flush(oneshot::Sender<()>),
+}
+
+/// Where the read loop delivers messages.
+#[derive(Debug, Default)]
+struct Registry {
+  subscriptions: HashMap<u64, mpsc::Sender<Event>>,
+  requests: HashMap<String, oneshot::Sender<Event>>,
+  closed: bool,
+}
+
+impl Registry {
+  /// End every subscription and pending request.
+  fn close(&mut self) {
+    self.closed = true;
+    self.subscriptions.clear();
+    self.requests.clear();
+  }
+}
+
+#[derive(Debug)]
+struct Inner {
+  commands: mpsc::Sender<Command>,
+  /// SUB and UNSUB frames.
+  control: mpsc::UnboundedSender<Vec<u8>>,
+  registry: Arc<Mutex<Registry>>,
+  reader: JoinHandle<()>,
+  next_sid: AtomicU64,
+  next_token: AtomicU64,
+  resp_prefix: String,
+  headers: bool,
+}
+
+impl Drop for Inner {
+  fn drop(&mut self) {
+    // The writer stops once the queued commands are sent, closing the
+    // connection.
+    self.reader.abort();
+  }
+}
+
+/// Asynchronous NATS client. Clones share the same connection.
+///
+/// ```no_run
+/// # async fn run() -> Result<(), client::NatsClientError> {
+/// let nc = client::asynk::Client::connect("nats://127.0.0.1:4222").await?;
+/// let mut sub = nc.subscribe("greetings", None).await?;
+/// nc.publish("greetings", "hello", None).await?;
+/// let event = sub.next().await;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Client {
+  inner: Arc<Inner>,
+}
+
+impl Client {
+  pub async fn connect<T: ToStringVec>(uris: T) -> Result<Client, NatsClientError> {
+    Client::connect_with_options(uris, ConnectOptions::default()).await
+  }
+
+  /// Connect to the first of `uris` accepting the connection.
+  ///
+  /// TLS and reconnection are not supported yet, the related options are
+  /// ignored.
+  pub async fn connect_with_options<T: ToStringVec>(
+    uris: T,
+    options: ConnectOptions,
+  ) -> Result<Client, NatsClientError> {
+    let mut last_err = NatsClientError::from((InvalidClientConfig, "No server URL was given"));
+    for uri in uris.to_string_vec() {
+      let server = ServerInfo::parse(&uri)?;
+      match Client::try_connect(&server, &options).await {
+        Ok(client) => return Ok(client),
+        Err(e) => last_err = e,
+      }
+    }
+    Err(last_err)
+  }
+
+  async fn try_connect(
+    server: &ServerInfo,
+    options: &ConnectOptions,
+  ) -> Result<Client, NatsClientError> {
+    let tcp = with_timeout(options.connect_timeout, async {
+      Ok(TcpStream::connect(server.addr()).await?)
+    })
+    .await?;
+    tcp.set_nodelay(options.no_delay)?;
+    let (read_half, write_half) = tcp.into_split();
+    let mut reader = BufReader::new(read_half);
+    let mut writer = BufWriter::with_capacity(options.max_pending_bytes, write_half);
+    let info = with_timeout(
+      options.read_timeout,
+      handshake(&mut reader, &mut writer, server, options),
+    )
+    .await?;
+    let (host, port) = server.addr();
+    info!("Connected to {}:{}", host, port);
+    let resp_prefix = format!("{}.", new_inbox(&options.inbox_prefix));
+    let sub = format!("SUB {}* {}\r\n", resp_prefix, RESP_MUX_SID);
+    writer.write_all(sub.as_bytes()).await?;
+    writer.flush().await?;
+
+    let (commands, queue) = mpsc::channel(COMMAND_QUEUE_SIZE);
+    let (control, control_queue) = mpsc::unbounded_channel();
+    let registry = Arc::new(Mutex::new(Registry::default()));
+    tokio::spawn(write_loop(writer, control_queue, queue));
+    let reader = tokio::spawn(read_loop(
+      reader,
+      registry.clone(),
+      commands.clone(),
+      options.clone(),
+    ));
+    Ok(Client {
+      inner: Arc::new(Inner {
+        commands,
+        control,
+        registry,
+        reader,
+        next_sid: AtomicU64::new(RESP_MUX_SID + 1),
+        next_token: AtomicU64::new(0),
+        resp_prefix,
+        headers: info.headers,
+      }),
+    })
+  }
+
+  /// Publish `msg` on `subject`, with an optional `inbox` for replies.
+  pub async fn publish<M: AsRef<[u8]>>(
+    &self,
+    subject: &str,
+    msg: M,
+    inbox: Option<&str>,
+  ) -> Result<(), NatsClientError> {
+    check_subject(subject, false)?;
+    if let Some(inbox) = inbox {
+      check_inbox(inbox)?;
+    }
+    let frame = pub_frame(subject, inbox, msg.as_ref()).to_vec();
+    self.publish_frame(frame).await
+  }
+
+  /// Publish `msg` with `headers` on `subject`, with an optional `inbox` for
+  /// replies. Requires a server supporting headers (NATS 2.2+).
+  pub async fn publish_with_headers<M: AsRef<[u8]>>(
+    &self,
+    subject: &str,
+    headers: &Headers,
+    msg: M,
+    inbox: Option<&str>,
+  ) -> Result<(), NatsClientError> {
+    check_subject(subject, false)?;
+    if let Some(inbox) = inbox {
+      check_inbox(inbox)?;
+    }
+    if !self.inner.headers {
+      return Err(NatsClientError::from((
+        ClientProtocolError,
+        "Server does not support headers",
+      )));
+    }
+    let frame = hpub_frame(subject, inbox, headers, msg.as_ref()).to_vec();
+    self.publish_frame(frame).await
+  }
+
+  async fn publish_frame(&self, frame: Vec<u8>) -> Result<(), NatsClientError> {
+    let start = Instant::now();
+    self.send(Command::Frame(frame)).await?;
+    telemetry::published(start.elapsed());
+    Ok(())
+  }
+
+  /// Wait until every command queued so far has been written to the server.
+  pub async fn flush(&self) -> Result<(), NatsClientError> {
+    let (done, flushed) = oneshot::channel();
+    self.send(Command::Flush(done)).await?;
+    flushed.await.map_err(|_| connection_closed())
+  }
+
+  /// Subscribe to `subject`, optionally as a member of the `queue` group.
+  ///
+  /// The subscription lasts until the returned handle is dropped.
+  pub async fn subscribe(
+    &self,
+    subject: &str,
+    queue: Option<&str>,
+  ) -> Result<Subscription, NatsClientError> {
+    check_subject(subject, true)?;
+    if let Some(queue) = queue {
+      check_queue(queue)?;
+    }
+    let sid = self.inner.next_sid.fetch_add(1, Ordering::Relaxed);
+    let (sender, receiver) = mpsc::channel(SUBSCRIPTION_QUEUE_SIZE);
+    {
+      let mut registry = self.inner.registry.lock().unwrap();
+      if registry.closed {
+        return Err(connection_closed());
+      }
+      registry.subscriptions.insert(sid, sender);
+    }
+    let cmd = match queue {
+      None => format!("SUB {} {}\r\n", subject, sid),
+      Some(queue) => format!("SUB {} {} {}\r\n", subject, queue, sid),
+    };
+    let subscription = Subscription {
+      channel: Channel { sid },
+      receiver,
+      inner: self.inner.clone(),
+    };
+    self
+      .inner
+      .control
+      .send(cmd.into_bytes())
+      .map_err(|_| connection_closed())?;
+    Ok(subscription)
+  }
+
+  /// Subscribe to `subject` as a member of the `queue` group, each message
+  /// being delivered to a single member of the group.
+  pub async fn queue_subscribe(
+    &self,
+    subject: &str,
+    queue: &str,
+  ) -> Result<Subscription, NatsClientError> {
+    self.subscribe(subject, Some(queue)).await
+  }
+
+  /// Publish `msg` on `subject` and wait for the first reply.
+  pub async fn request<M: AsRef<[u8]>>(
+    &self,
+    subject: &str,
+    msg: M,
+  ) -> Result<Event, NatsClientError> {
+    check_subject(subject, false)?;
+    let start = Instant::now();
+    let token = self.inner.next_token.fetch_add(1, Ordering::Relaxed);
+    let reply = format!("{}{}", self.inner.resp_prefix, token);
+    let (sender, receiver) = oneshot::channel();
+    let pending = PendingRequest {
+      registry: &self.inner.registry,
+      reply: &reply,
+    };
+    {
+      let mut registry = self.inner.registry.lock().unwrap();
+      if registry.closed {
+        return Err(connection_closed());
+      }
+      registry.requests.insert(reply.clone(), sender);
+    }
+    self.publish(subject, msg, Some(&reply)).await?;
+    let event = receiver.await.map_err(|_| connection_closed())?;
+    drop(pending);
+    let status = event.headers.as_ref().and_then(|h| h.status());
+    if status == Some(NO_RESPONDERS_STATUS) {
+      return Err(NatsClientError::from((
+        NoResponders,
+        "No responders are available for the request",
+      )));
+    }
+    telemetry::replied(start.elapsed());
+    Ok(event)
+  }
+
+  async fn send(&self, command: Command) -> Result<(), NatsClientError> {
+    self
+      .inner
+      .commands
+      .send(command)
+      .await
+      .map_err(|_| connection_closed())
+  }
+}
+
+/// Forgets the reply subject of a request that completed or was cancelled.
+struct PendingRequest<'a> {
+  registry: &'a Mutex<Registry>,
+  reply: &'a str,
+}
+
+impl Drop for PendingRequest<'_> {
+  fn drop(&mut self) {
+    self.registry.lock().unwrap().requests.remove(self.reply);
+  }
+}
+
+/// Stream of the messages received by a subscription, unsubscribed when
+/// dropped.
+#[derive(Debug)]
+#[must_use = "the subscription is unsubscribed when the handle is dropped"]
+pub struct Subscription {
+  channel: Channel,
+  receiver: mpsc::Receiver<Event>,
+  inner: Arc<Inner>,
+}
+
+impl Subscription {
+  pub fn channel(&self) -> Channel {
+    self.channel
+  }
+
+  /// Wait for the next message, `None` once the connection is closed.
+  pub async fn next(&mut self) -> Option<Event> {
+    self.receiver.recv().await
+  }
+}
+
+impl Stream for Subscription {
+  type Item = Event;
+
+  fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+    self.receiver.poll_recv(cx)
+  }
+}
+
+impl Drop for Subscription {
+  fn drop(&mut self) {
+    let sid = self.channel.sid;
+    self
+      .inner
+      .registry
+      .lock()
+      .unwrap()
+      .subscriptions
+      .remove(&sid);
+    let cmd = format!("UNSUB {}\r\n", sid);
+    // Only fails once the connection is closed.
+    let _ = self.inner.control.send(cmd.into_bytes());
+  }
+}
+
+fn connection_closed() -> NatsClientError {
+  NatsClientError::from((IoError, "Connection closed"))
+}
+
+/// Run `fut` for at most `duration`, zero meaning no limit.
+async fn with_timeout<T, F>(duration: Duration, fut: F) -> Result<T, NatsClientError>
+where
+  F: Future<Output = Result<T, NatsClientError>>,
+{
+  if duration.is_zero() {
+    return fut.await;
+  }
+  match time::timeout(duration, fut).await {
+    Ok(res) => res,
+    Err(_) => Err(NatsClientError::from(io::Error::from(
+      io::ErrorKind::TimedOut,
+    ))),
+  }
+}
+
+/// Read INFO, send CONNECT and wait for the PONG answering the PING sent
+/// along.
+async fn handshake(
+  reader: &mut BufReader<OwnedReadHalf>,
+  writer: &mut BufWriter<OwnedWriteHalf>,
+  server: &ServerInfo,
+  options: &ConnectOptions,
+) -> Result<Info, NatsClientError> {
+  let mut line = String::new();
+  reader.read_line(&mut line).await?;
+  let info = Info::parse(&line)?;
+  if options.tls_first || options.tls_required || info.tls_required {
+    return Err(NatsClientError::from((
+      TlsError,
+      "TLS is not supported by the asynchronous client",
+    )));
+  }
+  let connect = ConnectInfo::new(options, &info, server, false)?;
+  let cmd = format!("{}PING\r\n", connect.command());
+  writer.write_all(cmd.as_bytes()).await?;
+  writer.flush().await?;
+  loop {
+    line.clear();
+    if reader.read_line(&mut line).await? == 0 {
+      return Err(NatsClientError::from(io::Error::from(
+        io::ErrorKind::UnexpectedEof,
+      )));
+    }
+    match line.as_str() {
+      "PONG\r\n" => return Ok(info),
+      "+OK\r\n" => continue,
+      _ if line.starts_with("-ERR ") => return Err(server_error(line)),
+      _ => {
+        return Err(NatsClientError::from((
+          ServerProtocolError,
+          "Server PONG not received",
+          line.trim_end().to_owned(),
+        )))
+      }
+    }
+  }
+}
+
+/// Write the queued commands, the SUB and UNSUB frames of `control` first,
+/// flushing once both queues are empty.
+async fn write_loop(
+  mut writer: BufWriter<OwnedWriteHalf>,
+  mut control: mpsc::UnboundedReceiver<Vec<u8>>,
+  mut queue: mpsc::Receiver<Command>,
+) {
+  let mut flushed = Vec::new();
+  loop {
+    let mut command = tokio::select! {
+      biased;
+      Some(frame) = control.recv() => Command::Frame(frame),
+      command = queue.recv() => match command {
+        Some(command) => command,
+        None => return,
+      },
+    };
+    loop {
+      match command {
+        Command::Frame(frame) => {
+          if writer.write_all(&frame).await.is_err() {
+            return;
+          }
+        }
+        Command::Flush(done) => flushed.push(done),
+      }
+      command = match control.try_recv() {
+        Ok(frame) => Command::Frame(frame),
+        Err(_) => match queue.try_recv() {
+          Ok(command) => command,
+          Err(_) => break,
+        },
+      };
+    }
+    if writer.flush().await.is_err() {
+      return;
+    }
+    for done in flushed.drain(..) {
+      let _ = done.send(());
+    }
+  }
+}
+
+async fn read_loop(
+  reader: BufReader<OwnedReadHalf>,
+  registry: Arc<Mutex<Registry>>,
+  commands: mpsc::Sender<Command>,
+  options: ConnectOptions,
+) {
+  if let Err(e) = read_messages(reader, &registry, &commands, &options).await {
+    warn!("Connection lost: {}", e);
+    options.error_callback.call(&e);
+  }
+  registry.lock().unwrap().close();
+  options.disconnect_callback.call();
+}
+
+async fn read_messages(
+  mut reader: BufReader<OwnedReadHalf>,
+  registry: &Mutex<Registry>,
+  commands: &mpsc::Sender<Command>,
+  options: &ConnectOptions,
+) -> Result<(), NatsClientError> {
+  let ping_interval = options.ping_interval;
+  let mut pings_out = 0;
+  let mut line = Vec::new();
+  loop {
+    // `read_until` keeps the partial line when the PING timer fires first.
+    let keepalive = async {
+      if ping_interval.is_zero() {
+        std::future::pending::<()>().await;
+      }
+      time::sleep(ping_interval).await
+    };
+    let len = tokio::select! {
+      len = reader.read_until(b'\n', &mut line) => len?,
+      _ = keepalive => {
+        if pings_out >= options.max_pings_out {
+          return Err(NatsClientError::from((
+            IoError,
+            "Stale connection, too many outstanding pings",
+          )));
+        }
+        pings_out += 1;
+        send(commands, b"PING\r\n".to_vec()).await?;
+        continue;
+      }
+    };
+    if len == 0 {
+      return Err(NatsClientError::from(io::Error::from(
+        io::ErrorKind::UnexpectedEof,
+      )));
+    }
+    let text = String::from_utf8(std::mem::take(&mut line)).map_err(|_| {
+      NatsClientError::from((ServerProtocolError, "Invalid UTF-8 sent by the server"))
+    })?;
+    if text.starts_with("MSG ") || text.starts_with("HMSG ") {
+      let args = MsgArgs::parse(&text)?;
+      let mut msg = vec![0; args.len + 2];
+      reader.read_exact(&mut msg).await?;
+      dispatch(registry, args.into_event(msg)?, options);
+    } else if text == "PING\r\n" {
+      send(commands, b"PONG\r\n".to_vec()).await?;
+    } else if text == "PONG\r\n" {
+      pings_out = 0;
+    } else if text.starts_with("INFO ") {
+      if Info::parse(&text)?.ldm {
+        options.lame_duck_callback.call();
+      }
+    } else if text.starts_with("-ERR ") {
+      let err = server_error(text);
+      warn!("{}", err);
+      options.error_callback.call(&err);
+    } else if text != "+OK\r\n" {
+      return Err(NatsClientError::from((
+        ServerProtocolError,
+        "Server sent an unexpected response",
+        text,
+      )));
+    }
+  }
+}
+
+async fn send(commands: &mpsc::Sender<Command>, frame: Vec<u8>) -> Result<(), NatsClientError> {
+  commands
+    .send(Command::Frame(frame))
+    .await
+    .map_err(|_| connection_closed())
+}
+
+/// Hand `event` to its subscription or pending request.
+fn dispatch(registry: &Mutex<Registry>, event: Event, options: &ConnectOptions) {
+  telemetry::received();
+  let mut registry = registry.lock().unwrap();
+  if event.channel.sid == RESP_MUX_SID {
+    if let Some(reply) = registry.requests.remove(&event.subject) {
+      let _ = reply.send(event);
+    }
+    return;
+  }
+  let sid = event.channel.sid;
+  let full = match registry.subscriptions.get(&sid) {
+    Some(sender) => match sender.try_send(event) {
+      Ok(()) => false,
+      Err(mpsc::error::TrySendError::Full(_)) => {
+        telemetry::dropped();
+        true
+      }
+      Err(mpsc::error::TrySendError::Closed(_)) => {
+        registry.subscriptions.remove(&sid);
+        false
+      }
+    },
+    None => false,
+  };
+  if full {
+    warn!("Slow consumer on sid {}, messages dropped", sid);
+    options.error_callback.call(&NatsClientError::from((
+      SlowConsumer,
+      "Slow consumer, messages dropped",
+      format!("sid {}", sid),
+    )));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tokio::net::TcpListener;
+
+  /// Minimal server delivering every PUB to the matching subscriptions of
+  /// its single client.
+  async fn serve(listener: TcpListener) {
+    let (tcp, _) = listener.accept().await.unwrap();
+    let (read_half, mut writer) = tcp.into_split();
+    let mut reader = BufReader::new(read_half);
+    writer
+      .write_all(b"INFO {\"headers\":true,\"proto\":1}\r\n")
+      .await
+      .unwrap();
+    let mut subs: Vec<(String, String)> = Vec::new();
+    let mut line = String::new();
+    while reader.read_line(&mut line).await.unwrap() > 0 {
+      let args: Vec<String> = line.split_whitespace().map(str::to_owned).collect();
+      line.clear();
+      match args[0].as_str() {
+        "PING" => writer.write_all(b"PONG\r\n").await.unwrap(),
+        "SUB" => subs.push((args[1].clone(), args[args.len() - 1].clone())),
+        "PUB" => {
+          let len: usize = args[args.len() - 1].parse().unwrap();
+          let mut payload = vec![0; len + 2];
+          reader.read_exact(&mut payload).await.unwrap();
+          let reply = if args.len() == 4 { &args[2][..] } else { "" };
+          for (subject, sid) in &subs {
+            let matches = match subject.strip_suffix('*') {
+              Some(prefix) => args[1].starts_with(prefix),
+              None => *subject == args[1],
+            };
+            if matches {
+              let msg = format!("MSG {} {} {} {}\r\n", args[1], sid, reply, len);
+              writer.write_all(msg.as_bytes()).await.unwrap();
+              writer.write_all(&payload).await.unwrap();
+            }
+          }
+        }
+        _ => {}
+      }
+    }
+  }
+
+  /// Accept a connection and answer the handshake of the client.
+  async fn accept(listener: &TcpListener) -> (BufReader<OwnedReadHalf>, OwnedWriteHalf) {
+    let (tcp, _) = listener.accept().await.unwrap();
+    let (read_half, mut writer) = tcp.into_split();
+    let mut reader = BufReader::new(read_half);
+    writer.write_all(b"INFO {}\r\n").await.unwrap();
+    let mut line = String::new();
+    while line != "PING\r\n" {
+      line.clear();
+      reader.read_line(&mut line).await.unwrap();
+    }
+    writer.write_all(b"PONG\r\n").await.unwrap();
+    (reader, writer)
+  }
+
+  #[tokio::test]
+  async fn test_publish_subscribe_request() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("nats://{}", listener.local_addr().unwrap());
+    tokio::spawn(serve(listener));
+    let options = ConnectOptions::new().verbose(false);
+    let nc = Client::connect_with_options(url, options).await.unwrap();
+
+    let mut sub = nc.subscribe("echo", None).await.unwrap();
+    nc.publish("echo", "hello", None).await.unwrap();
+    let event = sub.next().await.unwrap();
+    assert_eq!(event.subject, "echo");
+    assert_eq!(&event.msg[..], b"hello");
+
+    let responder = nc.clone();
+    tokio::spawn(async move {
+      let event = sub.next().await.unwrap();
+      let inbox = event.inbox.unwrap();
+      responder.publish(&inbox, &event.msg, None).await.unwrap();
+    });
+    let reply = nc.request("echo", "ping").await.unwrap();
+    assert_eq!(&reply.msg[..], b"ping");
+    nc.flush().await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_unsubscribe_on_drop() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("nats://{}", listener.local_addr().unwrap());
+    let (resume, resumed) = oneshot::channel::<()>();
+    let server = tokio::spawn(async move {
+      let (mut reader, _writer) = accept(&listener).await;
+      // Nothing is read until the command queue of the client is full.
+      resumed.await.unwrap();
+      let mut line = String::new();
+      loop {
+        line.clear();
+        assert!(reader.read_line(&mut line).await.unwrap() > 0);
+        let args: Vec<&str> = line.split_whitespace().collect();
+        match args[0] {
+          "PUB" => {
+            let len: usize = args[args.len() - 1].parse().unwrap();
+            let mut payload = vec![0; len + 2];
+            reader.read_exact(&mut payload).await.unwrap();
+          }
+          "UNSUB" => return args[1].to_owned(),
+          _ => {}
+        }
+      }
+    });
+    let options = ConnectOptions::new().verbose(false);
+    let nc = Client::connect_with_options(url, options).await.unwrap();
+    let sub = nc.subscribe("jobs", None).await.unwrap();
+    let payload = vec![b'x'; 4096];
+    let timeout = Duration::from_millis(100);
+    while let Ok(res) = time::timeout(timeout, nc.publish("jobs", &payload, None)).await {
+      res.unwrap();
+    }
+    let sid = sub.channel().sid;
+    drop(sub);
+    resume.send(()).unwrap();
+    let unsub = time::timeout(Duration::from_secs(10), server).await;
+    assert_eq!(unsub.unwrap().unwrap(), sid.to_string());
+  }
+
+  #[tokio::test]
+  async fn test_request_timeout() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("nats://{}", listener.local_addr().unwrap());
+    // Requests are never answered.
+    tokio::spawn(async move {
+      let (mut reader, _writer) = accept(&listener).await;
+      let mut line = String::new();
+      while reader.read_line(&mut line).await.unwrap() > 0 {
+        line.clear();
+      }
+    });
+    let options = ConnectOptions::new().verbose(false);
+    let nc = Client::connect_with_options(url, options).await.unwrap();
+    let request = nc.request("service", "ping");
+    assert!(time::timeout(Duration::from_millis(100), request)
+      .await
+      .is_err());
+    // The cancelled request forgot its reply subject.
+    assert!(nc.inner.registry.lock().unwrap().requests.is_empty());
+  }
+
+  #[tokio::test]
+  async fn test_server_error() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("nats://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+      let (mut reader, mut writer) = accept(&listener).await;
+      writer
+        .write_all(b"-ERR 'Unknown Protocol Operation'\r\n")
+        .await
+        .unwrap();
+      let mut line = String::new();
+      while reader.read_line(&mut line).await.unwrap() > 0 {
+        line.clear();
+      }
+    });
+    let (errors, mut reported) = mpsc::unbounded_channel();
+    let options = ConnectOptions::new()
+      .verbose(false)
+      .error_callback(move |e| errors.send(e.kind()).unwrap());
+    let nc = Client::connect_with_options(url, options).await.unwrap();
+    let kind = time::timeout(Duration::from_secs(2), reported.recv()).await;
+    assert_eq!(kind.unwrap(), Some(ServerProtocolError));
+    // The connection is kept.
+    let _sub = nc.subscribe("jobs", None).await.unwrap();
+    nc.flush().await.unwrap();
+    assert!(!nc.inner.registry.lock().unwrap().closed);
+  }
+}
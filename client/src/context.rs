@@ -0,0 +1,165 @@
+use crate::client::Client;
+use crate::errors::{ErrorKind::*, *};
+use crate::options::ConnectOptions;
+use crate::tls_config::TlsConfig;
+use serde::Deserialize;
+use std::{
+  env, fs,
+  path::{Path, PathBuf},
+};
+
+/// Connection profile saved by the `nats` CLI, e.g. with `nats context save`.
+///
+/// Empty fields are unset. Other settings found in the file are ignored.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Context {
+  url: String,
+  token: String,
+  user: String,
+  password: String,
+  creds: String,
+  cert: String,
+  key: String,
+  ca: String,
+}
+
+impl Context {
+  fn parse(json: &str) -> Result<Context, NatsClientError> {
+    serde_json::from_str(json).map_err(|e| {
+      NatsClientError::from((InvalidClientConfig, "Invalid context file", e.to_string()))
+    })
+  }
+
+  /// Server URLs and options described by the context.
+  fn into_options(self) -> Result<(String, ConnectOptions), NatsClientError> {
+    if self.url.is_empty() {
+      return Err(NatsClientError::from((
+        InvalidClientConfig,
+        "Context file does not set a server URL",
+      )));
+    }
+    let mut options = ConnectOptions::new();
+    if !self.user.is_empty() {
+      options = options.user_and_password(&self.user, &self.password);
+    }
+    if !self.token.is_empty() {
+      options = options.token(&self.token);
+    }
+    if !self.creds.is_empty() {
+      options = options.credentials(expand_home(&self.creds));
+    }
+    if !self.ca.is_empty() || !self.cert.is_empty() {
+      let mut tls_config = TlsConfig::new();
+      if !self.ca.is_empty() {
+        tls_config = tls_config.add_root_certificate(expand_home(&self.ca));
+      }
+      if !self.cert.is_empty() {
+        tls_config = tls_config.client_certificate(expand_home(&self.cert), expand_home(&self.key));
+      }
+      options = options.tls_required(true).tls_config(tls_config);
+    }
+    Ok((self.url, options))
+  }
+}
+
+impl Client {
+  /// Create a `Client` from the context `name` saved by the `nats` CLI in
+  /// `~/.config/nats/context/<name>.json` (`$XDG_CONFIG_HOME` is honored).
+  ///
+  /// Server URLs, user and password, token, credentials file and TLS
+  /// certificates are taken from the context. As with `Client::new()`, the
+  /// connection is established on first use.
+  pub fn from_context(name: &str) -> Result<Client, NatsClientError> {
+    let path = context_path(&config_dir()?, name)?;
+    let json = fs::read_to_string(&path).map_err(|e| {
+      NatsClientError::from((
+        InvalidClientConfig,
+        "Failed to read context file",
+        format!("{}: {}", path.display(), e),
+      ))
+    })?;
+    let (urls, options) = Context::parse(&json)?.into_options()?;
+    Client::with_options(urls, options)
+  }
+}
+
+fn config_dir() -> Result<PathBuf, NatsClientError> {
+  match env::var_os("XDG_CONFIG_HOME") {
+    Some(dir) if !dir.is_empty() => Ok(PathBuf::from(dir)),
+    _ => home_dir().map(|home| home.join(".config")).ok_or_else(|| {
+      NatsClientError::from((InvalidClientConfig, "Cannot locate the home directory"))
+    }),
+  }
+}
+
+fn home_dir() -> Option<PathBuf> {
+  env::var_os("HOME")
+    .filter(|home| !home.is_empty())
+    .map(PathBuf::from)
+}
+
+fn context_path(config_dir: &Path, name: &str) -> Result<PathBuf, NatsClientError> {
+  if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+    return Err(NatsClientError::from((
+      InvalidClientConfig,
+      "Invalid context name",
+      name.to_owned(),
+    )));
+  }
+  Ok(
+    config_dir
+      .join("nats")
+      .join("context")
+      .join(format!("{}.json", name)),
+  )
+}
+
+/// Replace a leading `~` with the home directory, as the `nats` CLI does.
+fn expand_home(path: &str) -> PathBuf {
+  match (path.strip_prefix("~/"), home_dir()) {
+    (Some(rest), Some(home)) => home.join(rest),
+    _ => PathBuf::from(path),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_context() {
+    let json = r#"{
+      "description": "production cluster",
+      "url": "nats://a.example.com:4222,nats://b.example.com:4222",
+      "user": "alice",
+      "password": "s3cret",
+      "ca": "/etc/nats/ca.pem",
+      "nsc": ""
+    }"#;
+    let (urls, options) = Context::parse(json).unwrap().into_options().unwrap();
+    assert_eq!(urls, "nats://a.example.com:4222,nats://b.example.com:4222");
+    assert_eq!(options.user.as_deref(), Some("alice"));
+    assert_eq!(options.pass.as_deref(), Some("s3cret"));
+    assert!(options.tls_required);
+    assert!(options.credentials.is_none());
+
+    let err = Context::parse(r#"{"creds": "~/user.creds"}"#)
+      .unwrap()
+      .into_options()
+      .unwrap_err();
+    assert_eq!(err.kind(), InvalidClientConfig);
+    assert!(Context::parse("not json").is_err());
+  }
+
+  #[test]
+  fn test_context_path() {
+    let path = context_path(Path::new("/home/alice/.config"), "prod").unwrap();
+    assert_eq!(
+      path,
+      Path::new("/home/alice/.config/nats/context/prod.json")
+    );
+    assert!(context_path(Path::new("/tmp"), "../prod").is_err());
+    assert!(context_path(Path::new("/tmp"), "").is_err());
+  }
+}
@@ -0,0 +1,96 @@
+use crate::errors::{ErrorKind::*, *};
+use nkeys::KeyPair;
+use std::{fs, path::Path};
+
+/// User JWT and NKey seed read from a `.creds` file.
+#[derive(Debug)]
+pub(crate) struct Credentials {
+  pub jwt: String,
+  seed: String,
+}
+
+impl Credentials {
+  pub fn load(path: &Path) -> Result<Credentials, NatsClientError> {
+    let contents = fs::read_to_string(path)?;
+    parse_creds(&contents)
+  }
+
+  /// Sign the nonce sent by the server in INFO, returning the base64url
+  /// encoded signature expected in the `sig` field of CONNECT.
+  pub fn sign(&self, nonce: &str) -> Result<String, NatsClientError> {
+    let key_pair = KeyPair::from_seed(&self.seed).map_err(|e| {
+      NatsClientError::from((InvalidClientConfig, "Invalid NKey seed", e.to_string()))
+    })?;
+    let sig = key_pair.sign(nonce.as_bytes()).map_err(|e| {
+      NatsClientError::from((InvalidClientConfig, "Failed to sign nonce", e.to_string()))
+    })?;
+    Ok(base64::encode_config(&sig, base64::URL_SAFE_NO_PAD))
+  }
+}
+
+/// Extract the JWT and the seed from the contents of a credentials file.
+///
+/// Each of them is the first non-empty line following a `-----BEGIN ...-----`
+/// marker, the JWT coming first.
+fn parse_creds(contents: &str) -> Result<Credentials, NatsClientError> {
+  let mut blocks = Vec::new();
+  let mut lines = contents.lines().map(str::trim);
+  while let Some(line) = lines.next() {
+    if line.starts_with("---") && line.contains("BEGIN") {
+      if let Some(block) = lines.by_ref().find(|line| !line.is_empty()) {
+        blocks.push(block.to_owned());
+      }
+    }
+  }
+  if blocks.len() < 2 {
+    return Err(NatsClientError::from((
+      InvalidClientConfig,
+      "Credentials file must contain a user JWT and an NKey seed",
+    )));
+  }
+  let seed = blocks.remove(1);
+  let jwt = blocks.remove(0);
+  Ok(Credentials { jwt, seed })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const CREDS: &str = "-----BEGIN NATS USER JWT-----
+eyJ0eXAiOiJqd3QiLCJhbGciOiJlZDI1NTE5In0.e30.c2ln
+------END NATS USER JWT------
+
+************************* IMPORTANT *************************
+NKEY Seed printed below can be used to sign and prove identity.
+
+-----BEGIN USER NKEY SEED-----
+SUAOMVHCDMM7DYKUMBBFPEVHVNEYQY3UYRPT4DEGTVFHQEEG3GTIYWOERY
+------END USER NKEY SEED------
+
+*************************************************************
+";
+
+  #[test]
+  fn test_parse_creds() {
+    let creds = parse_creds(CREDS).unwrap();
+    assert_eq!(
+      creds.jwt,
+      "eyJ0eXAiOiJqd3QiLCJhbGciOiJlZDI1NTE5In0.e30.c2ln"
+    );
+    assert_eq!(
+      creds.seed,
+      "SUAOMVHCDMM7DYKUMBBFPEVHVNEYQY3UYRPT4DEGTVFHQEEG3GTIYWOERY"
+    );
+    assert!(parse_creds("-----BEGIN NATS USER JWT-----\njwt\n").is_err());
+  }
+
+  #[test]
+  fn test_sign_nonce() {
+    let creds = parse_creds(CREDS).unwrap();
+    let sig = creds.sign("PXoWU7zWAMt75FY").unwrap();
+    let sig = base64::decode_config(&sig, base64::URL_SAFE_NO_PAD).unwrap();
+    let key_pair = KeyPair::from_seed(&creds.seed).unwrap();
+    assert!(key_pair.verify(b"PXoWU7zWAMt75FY", &sig).is_ok());
+  }
+}
@@ -0,0 +1,146 @@
+use crate::client::{Channel, Client, Event};
+use crate::errors::*;
+use std::{
+  collections::HashMap,
+  fmt,
+  time::{Duration, Instant},
+};
+
+type Handler = Box<dyn FnMut(Event) + Send>;
+
+/// Table routing incoming messages to a handler per subscription.
+///
+/// The server delivers a copy of a message for every matching subscription,
+/// each with its own sid, so overlapping subscriptions such as `foo.*` and
+/// `foo.bar` each get their copy through their own handler.
+#[derive(Default)]
+pub struct Dispatcher {
+  handlers: HashMap<u64, Handler>,
+}
+
+impl Dispatcher {
+  pub fn new() -> Dispatcher {
+    Dispatcher::default()
+  }
+
+  /// Call `handler` for every message delivered to `channel`, replacing its
+  /// previous handler.
+  pub fn add<F>(&mut self, channel: Channel, handler: F)
+  where
+    F: FnMut(Event) + Send + 'static,
+  {
+    self.handlers.insert(channel.sid, Box::new(handler));
+  }
+
+  /// Stop handling the messages of `channel`. Returns whether it had a
+  /// handler.
+  pub fn remove(&mut self, channel: Channel) -> bool {
+    self.handlers.remove(&channel.sid).is_some()
+  }
+
+  pub fn handles(&self, channel: Channel) -> bool {
+    self.handlers.contains_key(&channel.sid)
+  }
+
+  /// Pass `event` to the handler of its subscription, or give it back when
+  /// there is none.
+  pub fn dispatch(&mut self, event: Event) -> Option<Event> {
+    match self.handlers.get_mut(&event.channel.sid) {
+      Some(handler) => {
+        handler(event);
+        None
+      }
+      None => Some(event),
+    }
+  }
+}
+
+impl fmt::Debug for Dispatcher {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+    let mut sids: Vec<_> = self.handlers.keys().collect();
+    sids.sort();
+    f.debug_struct("Dispatcher").field("sids", &sids).finish()
+  }
+}
+
+impl Client {
+  /// Deliver the messages received during `timeout` to the handlers of
+  /// `dispatcher`, returning how many were handled. Messages of subscriptions
+  /// without a handler are kept for `next_msg()` and `events()`.
+  pub fn dispatch(
+    &mut self,
+    dispatcher: &mut Dispatcher,
+    timeout: Duration,
+  ) -> Result<usize, NatsClientError> {
+    let deadline = Instant::now() + timeout;
+    let mut handled = 0;
+    // Messages received earlier, e.g. while waiting for a reply, come first.
+    while let Some(event) = self.dequeue_matching(|sid| dispatcher.handlers.contains_key(&sid)) {
+      dispatcher.dispatch(event);
+      handled += 1;
+    }
+    while let Some(event) = self.read_event(Some(deadline))? {
+      match dispatcher.dispatch(event) {
+        Some(event) => self.queue_event(event),
+        None => handled += 1,
+      }
+    }
+    Ok(handled)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::mock::MockServer;
+  use std::sync::{Arc, Mutex};
+
+  #[test]
+  fn test_dispatch_overlapping_subscriptions() {
+    let server = MockServer::new(|mut conn| {
+      conn.handshake("{}");
+      let wildcard = conn.expect("SUB");
+      conn.ack();
+      let literal = conn.expect("SUB");
+      conn.ack();
+      let other = conn.expect("SUB");
+      conn.ack();
+      conn.send(&format!("MSG foo.bar {} 1\r\n1\r\n", wildcard[1]));
+      conn.send(&format!("MSG foo.bar {} 1\r\n1\r\n", literal[1]));
+      conn.send(&format!("MSG foo.baz {} 1\r\n2\r\n", wildcard[1]));
+      conn.send(&format!("MSG bar {} 1\r\n3\r\n", other[1]));
+      while conn.read_line().is_some() {}
+    });
+    let mut nc = Client::new("nats://localhost").unwrap();
+    nc.mock_server = Some(server);
+    let wildcard = nc.subscribe("foo.*", None).unwrap();
+    let literal = nc.subscribe("foo.bar", None).unwrap();
+    let other = nc.subscribe("bar", None).unwrap();
+
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let mut dispatcher = Dispatcher::new();
+    let record = |name: &'static str| {
+      let received = received.clone();
+      move |event: Event| received.lock().unwrap().push((name, event.subject))
+    };
+    dispatcher.add(wildcard.channel(), record("wildcard"));
+    dispatcher.add(literal.channel(), record("literal"));
+    let handled = nc
+      .dispatch(&mut dispatcher, Duration::from_millis(200))
+      .unwrap();
+    assert_eq!(handled, 3);
+    assert_eq!(
+      *received.lock().unwrap(),
+      vec![
+        ("wildcard", "foo.bar".to_owned()),
+        ("literal", "foo.bar".to_owned()),
+        ("wildcard", "foo.baz".to_owned()),
+      ]
+    );
+    // Without a handler, the message is left for the subscription.
+    let event = nc
+      .next_msg(other.channel(), Duration::from_secs(1))
+      .unwrap();
+    assert_eq!(event.unwrap().msg, crate::Bytes::from_static(b"3"));
+  }
+}
@@ -1,13 +1,25 @@
 use std::{error::Error, fmt, io};
-use url;
 
+/// Class of a `NatsClientError`. New kinds may be added, so matches need a
+/// wildcard arm.
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
+#[non_exhaustive]
 pub enum ErrorKind {
+  AuthFailed,
+  CircuitOpen,
   ClientProtocolError,
+  DecodeError,
   InvalidClientConfig,
   IoError,
   InvalidSchemeError,
+  JetStreamError,
+  MaxPayloadExceeded,
+  NoResponders,
+  OutboundOverflow,
   ServerProtocolError,
+  SlowConsumer,
+  Timeout,
+  TlsError,
   TypeError,
 }
 
@@ -15,6 +27,8 @@ pub enum ErrorKind {
 enum ErrorRepr {
   WithDescription(ErrorKind, &'static str),
   WithDescriptionAndDetail(ErrorKind, &'static str, String),
+  // Error line sent by the server, `-ERR` included.
+  ServerError(ErrorKind, String),
   IoError(io::Error),
   UrlParseError(url::ParseError),
 }
@@ -24,19 +38,43 @@ pub struct NatsClientError {
   repr: ErrorRepr,
 }
 
-impl Error for NatsClientError {
-  fn description(&self) -> &str {
+impl NatsClientError {
+  /// Class of the failure, e.g. `Timeout` or `NoResponders`.
+  pub fn kind(&self) -> ErrorKind {
+    match self.repr {
+      ErrorRepr::WithDescription(kind, _)
+      | ErrorRepr::WithDescriptionAndDetail(kind, _, _)
+      | ErrorRepr::ServerError(kind, _) => kind,
+      ErrorRepr::IoError(_) => ErrorKind::IoError,
+      ErrorRepr::UrlParseError(_) => ErrorKind::InvalidClientConfig,
+    }
+  }
+
+  /// Whether the connection cannot be used after this error. Only some of
+  /// the errors sent by the server, e.g. a permissions violation or an
+  /// invalid subject, leave it open.
+  pub fn closes_connection(&self) -> bool {
     match self.repr {
-      ErrorRepr::WithDescription(_, description)
-      | ErrorRepr::WithDescriptionAndDetail(_, description, _) => description,
-      ErrorRepr::IoError(ref e) => e.description(),
-      ErrorRepr::UrlParseError(ref e) => e.description(),
+      ErrorRepr::ServerError(_, ref line) => {
+        let lower = line.to_lowercase();
+        !(lower.contains("permissions violation") || lower.contains("invalid subject"))
+      }
+      _ => true,
     }
   }
 
-  fn cause(&self) -> Option<&dyn Error> {
+  pub(crate) fn server(kind: ErrorKind, line: String) -> NatsClientError {
+    NatsClientError {
+      repr: ErrorRepr::ServerError(kind, line),
+    }
+  }
+}
+
+impl Error for NatsClientError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
     match self.repr {
-      ErrorRepr::IoError(ref e) => Some(e as &dyn Error),
+      ErrorRepr::IoError(ref e) => Some(e),
+      ErrorRepr::UrlParseError(ref e) => Some(e),
       _ => None,
     }
   }
@@ -51,6 +89,7 @@ impl fmt::Display for NatsClientError {
         f.write_str(": ")?;
         detail.fmt(f)
       }
+      ErrorRepr::ServerError(_, ref line) => write!(f, "Server responded with an error: {}", line),
       ErrorRepr::IoError(ref e) => e.fmt(f),
       ErrorRepr::UrlParseError(ref e) => e.fmt(f),
     }
@@ -73,7 +112,7 @@ impl From<(ErrorKind, &'static str, String)> for NatsClientError {
   }
 }
 
-impl From<(io::Error)> for NatsClientError {
+impl From<io::Error> for NatsClientError {
   fn from(e: io::Error) -> Self {
     NatsClientError {
       repr: ErrorRepr::IoError(e),
@@ -81,7 +120,7 @@ impl From<(io::Error)> for NatsClientError {
   }
 }
 
-impl From<(url::ParseError)> for NatsClientError {
+impl From<url::ParseError> for NatsClientError {
   fn from(e: url::ParseError) -> Self {
     NatsClientError {
       repr: ErrorRepr::UrlParseError(e),
@@ -0,0 +1,151 @@
+use crate::errors::*;
+use std::collections::{btree_map, BTreeMap};
+
+const VERSION_LINE: &str = "NATS/1.0";
+
+/// Message headers sent with HPUB and received with HMSG.
+///
+/// A header name may hold several values. Messages generated by the server
+/// carry a status code, e.g. 503 when a request has no responders.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Headers {
+  status: Option<u16>,
+  description: Option<String>,
+  inner: BTreeMap<String, Vec<String>>,
+}
+
+impl Headers {
+  pub fn new() -> Headers {
+    Headers::default()
+  }
+
+  /// Set `name` to `value`, replacing any previous values.
+  pub fn insert(&mut self, name: &str, value: &str) {
+    self.inner.insert(name.to_owned(), vec![value.to_owned()]);
+  }
+
+  /// Add `value` to the values of `name`.
+  pub fn append(&mut self, name: &str, value: &str) {
+    self
+      .inner
+      .entry(name.to_owned())
+      .or_default()
+      .push(value.to_owned());
+  }
+
+  /// First value of `name`.
+  pub fn get(&self, name: &str) -> Option<&str> {
+    self
+      .inner
+      .get(name)
+      .and_then(|values| values.first())
+      .map(|value| value.as_str())
+  }
+
+  pub fn get_all(&self, name: &str) -> &[String] {
+    self.inner.get(name).map(|v| v.as_slice()).unwrap_or(&[])
+  }
+
+  pub fn remove(&mut self, name: &str) -> Option<Vec<String>> {
+    self.inner.remove(name)
+  }
+
+  pub fn iter(&self) -> btree_map::Iter<'_, String, Vec<String>> {
+    self.inner.iter()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.inner.is_empty()
+  }
+
+  /// Status code set by the server, if any.
+  pub fn status(&self) -> Option<u16> {
+    self.status
+  }
+
+  /// Description following the status code, e.g. `No Responders`.
+  pub fn description(&self) -> Option<&str> {
+    self.description.as_deref()
+  }
+
+  /// Encode the header block, including the trailing empty line.
+  pub(crate) fn to_bytes(&self) -> Vec<u8> {
+    let mut buf = String::from(VERSION_LINE);
+    buf.push_str("\r\n");
+    for (name, values) in &self.inner {
+      for value in values {
+        buf.push_str(name);
+        buf.push_str(": ");
+        buf.push_str(value);
+        buf.push_str("\r\n");
+      }
+    }
+    buf.push_str("\r\n");
+    buf.into_bytes()
+  }
+
+  /// Parse the header block of an HMSG.
+  pub(crate) fn parse(buf: &[u8]) -> Result<Headers, NatsClientError> {
+    let invalid =
+      || NatsClientError::from((ErrorKind::ServerProtocolError, "Invalid message headers"));
+    let buf = std::str::from_utf8(buf).map_err(|_| invalid())?;
+    let mut lines = buf.split("\r\n");
+    let version_line = lines.next().ok_or_else(invalid)?;
+    if !version_line.starts_with(VERSION_LINE) {
+      return Err(invalid());
+    }
+    let mut headers = Headers::new();
+    let mut status_line = version_line[VERSION_LINE.len()..].trim().splitn(2, ' ');
+    if let Some(status) = status_line.next().filter(|s| !s.is_empty()) {
+      headers.status = Some(status.parse().map_err(|_| invalid())?);
+      headers.description = status_line.next().map(|d| d.trim().to_owned());
+    }
+    for line in lines.take_while(|line| !line.is_empty()) {
+      let mut parts = line.splitn(2, ':');
+      let name = parts.next().unwrap().trim();
+      let value = parts.next().ok_or_else(invalid)?.trim();
+      if name.is_empty() {
+        return Err(invalid());
+      }
+      headers.append(name, value);
+    }
+    Ok(headers)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_round_trip() {
+    let mut headers = Headers::new();
+    headers.insert("Nats-Msg-Id", "1");
+    headers.append("X-Trace", "a");
+    headers.append("X-Trace", "b");
+    let buf = headers.to_bytes();
+    assert_eq!(
+      buf,
+      b"NATS/1.0\r\nNats-Msg-Id: 1\r\nX-Trace: a\r\nX-Trace: b\r\n\r\n".to_vec()
+    );
+    let parsed = Headers::parse(&buf).unwrap();
+    assert_eq!(parsed, headers);
+    assert_eq!(parsed.get("X-Trace"), Some("a"));
+    assert_eq!(parsed.get_all("X-Trace").len(), 2);
+    assert_eq!(parsed.status(), None);
+  }
+
+  #[test]
+  fn test_parse_status() {
+    let headers = Headers::parse(b"NATS/1.0 503\r\n\r\n").unwrap();
+    assert_eq!(headers.status(), Some(503));
+    assert!(headers.is_empty());
+
+    let headers = Headers::parse(b"NATS/1.0 408 Request Timeout\r\n\r\n").unwrap();
+    assert_eq!(headers.status(), Some(408));
+    assert_eq!(headers.description(), Some("Request Timeout"));
+
+    assert!(Headers::parse(b"HTTP/1.1 200\r\n\r\n").is_err());
+    assert!(Headers::parse(b"NATS/1.0\r\nno-colon\r\n\r\n").is_err());
+  }
+}
@@ -0,0 +1,1526 @@
+//! JetStream, the persistence layer of NATS.
+//!
+//! Messages published through `JetStream::publish()` are stored by the stream
+//! whose subjects match, and acknowledged with a `PubAck` once persisted.
+//!
+//! ```no_run
+//! let mut nc = client::Client::new("nats://127.0.0.1:4222").unwrap();
+//! let ack = nc.jetstream().publish("orders.new", "42").unwrap();
+//! println!("stored as {} in {}", ack.sequence, ack.stream);
+//! ```
+//!
+//! Stored messages are read through consumers, which track what was
+//! delivered and acknowledged:
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! let mut nc = client::Client::new("nats://127.0.0.1:4222").unwrap();
+//! let mut sub = nc.jetstream().pull_subscribe("ORDERS", "worker").unwrap();
+//! for msg in sub.fetch(10, Duration::from_secs(1)).unwrap() {
+//!   println!("{:?}", msg.msg);
+//!   sub.ack(&msg).unwrap();
+//! }
+//! ```
+
+use crate::client::{check_subject, Client, Event, Subscription};
+use crate::errors::{ErrorKind::*, *};
+use crate::headers::Headers;
+use bytes::Bytes;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+  thread,
+  time::{Duration, Instant},
+};
+
+const DEFAULT_API_PREFIX: &str = "$JS.API";
+const DEFAULT_TIMEOUT_MS: u64 = 5000;
+const DEFAULT_PUBLISH_RETRIES: u32 = 2;
+const DEFAULT_RETRY_WAIT_MS: u64 = 250;
+// How long to keep waiting for a pull request to end after it expired.
+const FETCH_GRACE_MS: u64 = 500;
+
+const IDLE_HEARTBEAT_STATUS: u16 = 100;
+const NO_MESSAGES_STATUS: u16 = 404;
+const REQUEST_TIMEOUT_STATUS: u16 = 408;
+const CONSUMER_STALLED_HEADER: &str = "Nats-Consumer-Stalled";
+
+/// Options of a `JetStream` context.
+#[derive(Clone, Debug)]
+pub struct JetStreamOptions {
+  pub(crate) api_prefix: String,
+  pub(crate) timeout: Duration,
+  pub(crate) publish_retries: u32,
+  pub(crate) retry_wait: Duration,
+}
+
+impl Default for JetStreamOptions {
+  fn default() -> Self {
+    JetStreamOptions {
+      api_prefix: DEFAULT_API_PREFIX.to_owned(),
+      timeout: Duration::from_millis(DEFAULT_TIMEOUT_MS),
+      publish_retries: DEFAULT_PUBLISH_RETRIES,
+      retry_wait: Duration::from_millis(DEFAULT_RETRY_WAIT_MS),
+    }
+  }
+}
+
+impl JetStreamOptions {
+  pub fn new() -> JetStreamOptions {
+    JetStreamOptions::default()
+  }
+
+  /// Prefix of the API subjects, `$JS.API` by default. Set it to use the
+  /// JetStream of another domain, e.g. `$JS.hub.API`.
+  pub fn api_prefix(mut self, prefix: &str) -> JetStreamOptions {
+    self.api_prefix = prefix.trim_end_matches('.').to_owned();
+    self
+  }
+
+  /// How long to wait for acknowledgments and API responses.
+  pub fn timeout(mut self, timeout: Duration) -> JetStreamOptions {
+    self.timeout = timeout;
+    self
+  }
+
+  /// How many times a publish is retried when no stream is listening, which
+  /// happens while a stream leader is being elected.
+  pub fn publish_retries(mut self, retries: u32, wait: Duration) -> JetStreamOptions {
+    self.publish_retries = retries;
+    self.retry_wait = wait;
+    self
+  }
+}
+
+/// Expectations and deduplication ID of a published message.
+#[derive(Clone, Debug, Default)]
+pub struct PublishOptions {
+  msg_id: Option<String>,
+  expected_stream: Option<String>,
+  expected_last_msg_id: Option<String>,
+  expected_last_sequence: Option<u64>,
+  expected_last_subject_sequence: Option<u64>,
+}
+
+impl PublishOptions {
+  pub fn new() -> PublishOptions {
+    PublishOptions::default()
+  }
+
+  /// ID used by the stream to discard duplicates within its deduplication
+  /// window, so a publish can be retried safely.
+  pub fn msg_id(mut self, id: &str) -> PublishOptions {
+    self.msg_id = Some(id.to_owned());
+    self
+  }
+
+  /// Fail unless the message is stored by `stream`.
+  pub fn expected_stream(mut self, stream: &str) -> PublishOptions {
+    self.expected_stream = Some(stream.to_owned());
+    self
+  }
+
+  /// Fail unless `id` is the ID of the last message of the stream.
+  pub fn expected_last_msg_id(mut self, id: &str) -> PublishOptions {
+    self.expected_last_msg_id = Some(id.to_owned());
+    self
+  }
+
+  /// Fail unless `sequence` is the sequence of the last message of the
+  /// stream.
+  pub fn expected_last_sequence(mut self, sequence: u64) -> PublishOptions {
+    self.expected_last_sequence = Some(sequence);
+    self
+  }
+
+  /// Fail unless `sequence` is the sequence of the last message stored on
+  /// the subject.
+  pub fn expected_last_subject_sequence(mut self, sequence: u64) -> PublishOptions {
+    self.expected_last_subject_sequence = Some(sequence);
+    self
+  }
+
+  fn headers(&self) -> Headers {
+    let mut headers = Headers::new();
+    if let Some(ref id) = self.msg_id {
+      headers.insert("Nats-Msg-Id", id);
+    }
+    if let Some(ref stream) = self.expected_stream {
+      headers.insert("Nats-Expected-Stream", stream);
+    }
+    if let Some(ref id) = self.expected_last_msg_id {
+      headers.insert("Nats-Expected-Last-Msg-Id", id);
+    }
+    if let Some(sequence) = self.expected_last_sequence {
+      headers.insert("Nats-Expected-Last-Sequence", &sequence.to_string());
+    }
+    if let Some(sequence) = self.expected_last_subject_sequence {
+      headers.insert("Nats-Expected-Last-Subject-Sequence", &sequence.to_string());
+    }
+    headers
+  }
+}
+
+/// Acknowledgment of a message stored by a stream.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct PubAck {
+  pub stream: String,
+  #[serde(rename = "seq")]
+  pub sequence: u64,
+  /// The message was discarded as a duplicate of an earlier one with the
+  /// same ID.
+  #[serde(default)]
+  pub duplicate: bool,
+  pub domain: Option<String>,
+}
+
+/// JetStream context, borrowing the client it sends requests through.
+#[derive(Debug)]
+pub struct JetStream<'a> {
+  pub(crate) client: &'a mut Client,
+  pub(crate) options: JetStreamOptions,
+}
+
+impl<'a> JetStream<'a> {
+  /// Another context for the same client and options, borrowing this one.
+  pub(crate) fn reborrow(&mut self) -> JetStream<'_> {
+    JetStream {
+      client: &mut *self.client,
+      options: self.options.clone(),
+    }
+  }
+}
+
+impl Client {
+  pub fn jetstream(&mut self) -> JetStream<'_> {
+    self.jetstream_with_options(JetStreamOptions::default())
+  }
+
+  pub fn jetstream_with_options(&mut self, options: JetStreamOptions) -> JetStream<'_> {
+    JetStream {
+      client: self,
+      options,
+    }
+  }
+}
+
+impl<'a> JetStream<'a> {
+  /// Publish `msg` on `subject` and wait until a stream stored it.
+  pub fn publish<M: AsRef<[u8]>>(
+    &mut self,
+    subject: &str,
+    msg: M,
+  ) -> Result<PubAck, NatsClientError> {
+    self.publish_with_options(subject, msg, &PublishOptions::default())
+  }
+
+  /// Like `publish()`, with a deduplication ID or expectations checked by
+  /// the stream.
+  pub fn publish_with_options<M: AsRef<[u8]>>(
+    &mut self,
+    subject: &str,
+    msg: M,
+    options: &PublishOptions,
+  ) -> Result<PubAck, NatsClientError> {
+    self.publish_with_headers(subject, &options.headers(), msg.as_ref())
+  }
+
+  /// Publish `msg` with `headers`, which may be empty, retrying while no
+  /// stream listens on `subject`.
+  pub(crate) fn publish_with_headers(
+    &mut self,
+    subject: &str,
+    headers: &Headers,
+    msg: &[u8],
+  ) -> Result<PubAck, NatsClientError> {
+    let headers = Some(headers).filter(|h| !h.is_empty());
+    let mut retries = 0;
+    loop {
+      let deadline = Instant::now() + self.options.timeout;
+      let res = self
+        .client
+        .request_until(subject, headers, msg, Some(deadline));
+      match res {
+        Err(ref e) if e.kind() == NoResponders && retries < self.options.publish_retries => {
+          retries += 1;
+          thread::sleep(self.options.retry_wait);
+        }
+        res => return api_response(&res?.msg),
+      }
+    }
+  }
+}
+
+impl<'a> JetStream<'a> {
+  /// Bind to the pull consumer `consumer` of `stream`, which delivers
+  /// messages on demand through `PullSubscription::fetch()`.
+  pub fn pull_subscribe(
+    self,
+    stream: &str,
+    consumer: &str,
+  ) -> Result<PullSubscription<'a>, NatsClientError> {
+    check_name(stream)?;
+    check_name(consumer)?;
+    let inbox = self.client.new_inbox();
+    let subscription = self.client.subscribe(&inbox, None)?;
+    Ok(PullSubscription {
+      client: self.client,
+      next_subject: format!(
+        "{}.CONSUMER.MSG.NEXT.{}.{}",
+        self.options.api_prefix, stream, consumer
+      ),
+      inbox,
+      subscription,
+    })
+  }
+}
+
+/// Message delivered by a consumer, to be acknowledged once processed.
+#[derive(Debug)]
+pub struct Message {
+  pub subject: String,
+  pub msg: Bytes,
+  pub headers: Option<Headers>,
+  // Subject receiving the acknowledgments, which encodes the metadata.
+  reply: String,
+}
+
+impl Message {
+  fn from_event(event: Event) -> Result<Message, NatsClientError> {
+    let reply = event.inbox.ok_or((
+      ServerProtocolError,
+      "JetStream message without an acknowledgment subject",
+    ))?;
+    Ok(Message {
+      subject: event.subject,
+      msg: event.msg,
+      headers: event.headers,
+      reply,
+    })
+  }
+
+  /// Delivery metadata, parsed from the acknowledgment subject.
+  pub fn info(&self) -> Result<MessageInfo, NatsClientError> {
+    MessageInfo::parse(&self.reply)
+  }
+}
+
+/// Position of a message in its stream and consumer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MessageInfo {
+  pub stream: String,
+  pub consumer: String,
+  /// Number of times the message was delivered, 1 on the first delivery.
+  pub delivered: u64,
+  pub stream_sequence: u64,
+  pub consumer_sequence: u64,
+  /// When the message was stored, in nanoseconds since the Unix epoch.
+  pub timestamp: u64,
+  /// Messages left for the consumer after this one.
+  pub pending: u64,
+}
+
+impl MessageInfo {
+  /// Parse `$JS.ACK.<stream>.<consumer>.<delivered>.<stream seq>.<consumer
+  /// seq>.<timestamp>.<pending>`, also accepting the newer form with the
+  /// domain and account hash after `$JS.ACK`.
+  fn parse(reply: &str) -> Result<MessageInfo, NatsClientError> {
+    let invalid = || {
+      NatsClientError::from((
+        ServerProtocolError,
+        "Invalid acknowledgment subject",
+        reply.to_owned(),
+      ))
+    };
+    let tokens: Vec<&str> = reply.split('.').collect();
+    if !reply.starts_with("$JS.ACK.") {
+      return Err(invalid());
+    }
+    let tokens = match tokens.len() {
+      9 => &tokens[2..],
+      n if n >= 12 => &tokens[4..11],
+      _ => return Err(invalid()),
+    };
+    let number = |i: usize| tokens[i].parse::<u64>().map_err(|_| invalid());
+    Ok(MessageInfo {
+      stream: tokens[0].to_owned(),
+      consumer: tokens[1].to_owned(),
+      delivered: number(2)?,
+      stream_sequence: number(3)?,
+      consumer_sequence: number(4)?,
+      timestamp: number(5)?,
+      pending: number(6)?,
+    })
+  }
+}
+
+#[derive(Serialize)]
+struct NextRequest {
+  batch: usize,
+  // In nanoseconds.
+  expires: u64,
+}
+
+/// Subscription to a pull consumer, borrowing the client.
+#[derive(Debug)]
+pub struct PullSubscription<'a> {
+  client: &'a mut Client,
+  next_subject: String,
+  inbox: String,
+  subscription: Subscription,
+}
+
+impl<'a> PullSubscription<'a> {
+  /// Request up to `batch` messages, returning those delivered before the
+  /// request `expires`. Fewer messages are returned when the consumer has no
+  /// more pending.
+  pub fn fetch(
+    &mut self,
+    batch: usize,
+    expires: Duration,
+  ) -> Result<Vec<Message>, NatsClientError> {
+    let request = NextRequest {
+      batch,
+      expires: expires.as_nanos() as u64,
+    };
+    let request = serde_json::to_vec(&request).map_err(|e| {
+      NatsClientError::from((TypeError, "Failed to encode pull request", e.to_string()))
+    })?;
+    self
+      .client
+      .publish(&self.next_subject, &request, Some(&self.inbox))?;
+    let deadline = Instant::now() + expires + Duration::from_millis(FETCH_GRACE_MS);
+    let channel = self.subscription.channel();
+    let mut messages = Vec::new();
+    while messages.len() < batch {
+      let timeout = deadline.saturating_duration_since(Instant::now());
+      let event = match self.client.next_msg(channel, timeout)? {
+        Some(event) => event,
+        None => break,
+      };
+      match event.headers.as_ref().and_then(|h| h.status()) {
+        None => messages.push(Message::from_event(event)?),
+        Some(IDLE_HEARTBEAT_STATUS) => {}
+        // The request ended before the batch was complete.
+        Some(NO_MESSAGES_STATUS) | Some(REQUEST_TIMEOUT_STATUS) => break,
+        Some(status) => {
+          if !messages.is_empty() {
+            break;
+          }
+          let description = event.headers.as_ref().and_then(|h| h.description());
+          return Err(NatsClientError::from((
+            JetStreamError,
+            "Pull request failed",
+            format!("{} {}", status, description.unwrap_or_default()),
+          )));
+        }
+      }
+    }
+    Ok(messages)
+  }
+
+  /// Acknowledge that `msg` was processed.
+  pub fn ack(&mut self, msg: &Message) -> Result<(), NatsClientError> {
+    acknowledge(self.client, msg, AckKind::Ack)
+  }
+
+  /// Ask for `msg` to be redelivered.
+  pub fn nak(&mut self, msg: &Message) -> Result<(), NatsClientError> {
+    acknowledge(self.client, msg, AckKind::Nak)
+  }
+
+  /// Stop redelivering `msg`, which cannot be processed.
+  pub fn term(&mut self, msg: &Message) -> Result<(), NatsClientError> {
+    acknowledge(self.client, msg, AckKind::Term)
+  }
+
+  /// Signal that `msg` is still being processed, resetting its redelivery
+  /// timer.
+  pub fn in_progress(&mut self, msg: &Message) -> Result<(), NatsClientError> {
+    acknowledge(self.client, msg, AckKind::Progress)
+  }
+}
+
+/// Subscription to a push consumer, which delivers its messages on its
+/// delivery subject as they become available.
+///
+/// Flow control requests of the consumer are answered as messages are read,
+/// and a consumer configured with idle heartbeats is detected as lost when
+/// they stop.
+#[derive(Debug)]
+pub struct PushSubscription<'a> {
+  client: &'a mut Client,
+  subscription: Subscription,
+  idle_heartbeat: Option<Duration>,
+  // Last time a message or a heartbeat was received.
+  last_activity: Instant,
+}
+
+impl<'a> JetStream<'a> {
+  /// Subscribe to `deliver_subject`, the delivery subject of a push consumer,
+  /// optionally as a member of its delivery `queue` group.
+  pub fn push_subscribe(
+    self,
+    deliver_subject: &str,
+    queue: Option<&str>,
+  ) -> Result<PushSubscription<'a>, NatsClientError> {
+    let subscription = self.client.subscribe(deliver_subject, queue)?;
+    Ok(PushSubscription {
+      client: self.client,
+      subscription,
+      idle_heartbeat: None,
+      last_activity: Instant::now(),
+    })
+  }
+}
+
+impl<'a> PushSubscription<'a> {
+  /// Heartbeat interval configured on the consumer. When neither a message
+  /// nor a heartbeat arrives for two intervals, `next_msg()` fails with a
+  /// `Timeout` error.
+  pub fn set_idle_heartbeat(&mut self, interval: Duration) {
+    self.idle_heartbeat = Some(interval).filter(|i| !i.is_zero());
+    self.last_activity = Instant::now();
+  }
+
+  /// Wait at most `timeout` for the next message. Returns `Ok(None)` when no
+  /// message arrived in time.
+  pub fn next_msg(&mut self, timeout: Duration) -> Result<Option<Message>, NatsClientError> {
+    let deadline = Instant::now() + timeout;
+    let channel = self.subscription.channel();
+    loop {
+      let mut until = deadline;
+      if let Some(interval) = self.idle_heartbeat {
+        until = until.min(self.last_activity + interval * 2);
+      }
+      let wait = until.saturating_duration_since(Instant::now());
+      let event = match self.client.next_msg(channel, wait)? {
+        Some(event) => event,
+        None if Instant::now() >= deadline => return Ok(None),
+        None => {
+          return Err(NatsClientError::from((
+            Timeout,
+            "Missed idle heartbeats from the consumer",
+          )))
+        }
+      };
+      self.last_activity = Instant::now();
+      let headers = match event.headers {
+        Some(ref headers) if headers.status() == Some(IDLE_HEARTBEAT_STATUS) => headers,
+        _ => return Message::from_event(event).map(Some),
+      };
+      // A flow control request is answered on its reply subject, and a
+      // heartbeat names the pending request of a stalled consumer.
+      let reply = match event.inbox {
+        Some(ref inbox) => Some(inbox.as_str()),
+        None => headers.get(CONSUMER_STALLED_HEADER),
+      };
+      if let Some(reply) = reply {
+        self.client.publish(reply, b"", None)?;
+      }
+    }
+  }
+
+  /// Acknowledge that `msg` was processed.
+  pub fn ack(&mut self, msg: &Message) -> Result<(), NatsClientError> {
+    acknowledge(self.client, msg, AckKind::Ack)
+  }
+
+  /// Ask for `msg` to be redelivered.
+  pub fn nak(&mut self, msg: &Message) -> Result<(), NatsClientError> {
+    acknowledge(self.client, msg, AckKind::Nak)
+  }
+
+  /// Stop redelivering `msg`, which cannot be processed.
+  pub fn term(&mut self, msg: &Message) -> Result<(), NatsClientError> {
+    acknowledge(self.client, msg, AckKind::Term)
+  }
+
+  /// Signal that `msg` is still being processed, resetting its redelivery
+  /// timer.
+  pub fn in_progress(&mut self, msg: &Message) -> Result<(), NatsClientError> {
+    acknowledge(self.client, msg, AckKind::Progress)
+  }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum AckKind {
+  Ack,
+  Nak,
+  Progress,
+  Term,
+}
+
+fn acknowledge(client: &mut Client, msg: &Message, kind: AckKind) -> Result<(), NatsClientError> {
+  let payload: &[u8] = match kind {
+    AckKind::Ack => b"+ACK",
+    AckKind::Nak => b"-NAK",
+    AckKind::Progress => b"+WPI",
+    AckKind::Term => b"+TERM",
+  };
+  client.publish(&msg.reply, payload, None)
+}
+
+/// Stream and consumer names are single subject tokens.
+fn check_name(name: &str) -> Result<(), NatsClientError> {
+  check_subject(name, false)?;
+  if name.contains('.') {
+    return Err(NatsClientError::from((
+      InvalidClientConfig,
+      "Stream and consumer names cannot contain dots",
+      name.to_owned(),
+    )));
+  }
+  Ok(())
+}
+
+/// How a stream decides which messages to keep.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RetentionPolicy {
+  /// Until the limits of the stream are reached.
+  Limits,
+  /// While consumers have not acknowledged them.
+  Interest,
+  /// Until the first consumer acknowledged them.
+  WorkQueue,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageType {
+  File,
+  Memory,
+}
+
+/// Which messages are discarded once a stream is full.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiscardPolicy {
+  /// The oldest messages make room for new ones.
+  Old,
+  /// New messages are rejected.
+  New,
+}
+
+/// Configuration of a stream. Negative limits mean unlimited.
+///
+/// ```no_run
+/// # use client::jetstream::StreamConfig;
+/// let mut nc = client::Client::new("nats://127.0.0.1:4222").unwrap();
+/// let config = StreamConfig {
+///   subjects: vec!["orders.>".to_owned()],
+///   ..StreamConfig::new("ORDERS")
+/// };
+/// nc.jetstream().create_stream(&config).unwrap();
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StreamConfig {
+  pub name: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub description: Option<String>,
+  pub subjects: Vec<String>,
+  pub retention: RetentionPolicy,
+  pub max_consumers: i64,
+  pub max_msgs: i64,
+  pub max_bytes: i64,
+  #[serde(with = "nanos")]
+  pub max_age: Duration,
+  pub max_msgs_per_subject: i64,
+  pub max_msg_size: i32,
+  pub storage: StorageType,
+  pub discard: DiscardPolicy,
+  pub num_replicas: usize,
+  /// Window within which messages with the same `Nats-Msg-Id` are
+  /// discarded as duplicates.
+  #[serde(with = "nanos")]
+  pub duplicate_window: Duration,
+  pub deny_delete: bool,
+  pub deny_purge: bool,
+  /// Allow the `Nats-Rollup` header to replace the messages of a subject or
+  /// of the stream.
+  pub allow_rollup_hdrs: bool,
+}
+
+impl Default for StreamConfig {
+  fn default() -> Self {
+    StreamConfig {
+      name: String::new(),
+      description: None,
+      subjects: Vec::new(),
+      retention: RetentionPolicy::Limits,
+      max_consumers: -1,
+      max_msgs: -1,
+      max_bytes: -1,
+      max_age: Duration::from_secs(0),
+      max_msgs_per_subject: -1,
+      max_msg_size: -1,
+      storage: StorageType::File,
+      discard: DiscardPolicy::Old,
+      num_replicas: 1,
+      duplicate_window: Duration::from_secs(2 * 60),
+      deny_delete: false,
+      deny_purge: false,
+      allow_rollup_hdrs: false,
+    }
+  }
+}
+
+impl StreamConfig {
+  /// Configuration of the stream `name` with the default limits. Without
+  /// subjects, the stream stores the messages published on its name.
+  pub fn new(name: &str) -> StreamConfig {
+    StreamConfig {
+      name: name.to_owned(),
+      ..StreamConfig::default()
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct StreamState {
+  pub messages: u64,
+  pub bytes: u64,
+  pub first_seq: u64,
+  pub last_seq: u64,
+  #[serde(default)]
+  pub consumer_count: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct StreamInfo {
+  pub config: StreamConfig,
+  pub state: StreamState,
+  /// Creation time, in RFC 3339 format.
+  pub created: String,
+}
+
+/// Where a consumer starts in its stream.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliverPolicy {
+  All,
+  Last,
+  New,
+  /// From `ConsumerConfig::opt_start_seq`.
+  ByStartSequence,
+  LastPerSubject,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AckPolicy {
+  /// Messages are acknowledged on delivery.
+  None,
+  /// Acknowledging a message acknowledges every message before it.
+  All,
+  /// Every message is acknowledged on its own.
+  Explicit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplayPolicy {
+  /// As fast as possible.
+  Instant,
+  /// At the pace they were published.
+  Original,
+}
+
+/// Configuration of a consumer, a push consumer when `deliver_subject` is
+/// set and a pull consumer otherwise. Consumers without a `durable_name` are
+/// ephemeral.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConsumerConfig {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub durable_name: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub description: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub deliver_subject: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub deliver_group: Option<String>,
+  pub deliver_policy: DeliverPolicy,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub opt_start_seq: Option<u64>,
+  pub ack_policy: AckPolicy,
+  #[serde(with = "nanos")]
+  pub ack_wait: Duration,
+  pub max_deliver: i64,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub filter_subject: Option<String>,
+  pub replay_policy: ReplayPolicy,
+  pub max_ack_pending: i64,
+  /// Interval of the heartbeats sent to an idle push consumer, zero for
+  /// none.
+  #[serde(with = "nanos", skip_serializing_if = "Duration::is_zero")]
+  pub idle_heartbeat: Duration,
+  pub flow_control: bool,
+}
+
+impl Default for ConsumerConfig {
+  fn default() -> Self {
+    ConsumerConfig {
+      durable_name: None,
+      description: None,
+      deliver_subject: None,
+      deliver_group: None,
+      deliver_policy: DeliverPolicy::All,
+      opt_start_seq: None,
+      ack_policy: AckPolicy::Explicit,
+      ack_wait: Duration::from_secs(30),
+      max_deliver: -1,
+      filter_subject: None,
+      replay_policy: ReplayPolicy::Instant,
+      max_ack_pending: -1,
+      idle_heartbeat: Duration::from_secs(0),
+      flow_control: false,
+    }
+  }
+}
+
+impl ConsumerConfig {
+  /// Configuration of the durable pull consumer `name`.
+  pub fn durable(name: &str) -> ConsumerConfig {
+    ConsumerConfig {
+      durable_name: Some(name.to_owned()),
+      ..ConsumerConfig::default()
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct SequenceInfo {
+  pub consumer_seq: u64,
+  pub stream_seq: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct ConsumerInfo {
+  pub stream_name: String,
+  pub name: String,
+  pub config: ConsumerConfig,
+  /// Creation time, in RFC 3339 format.
+  pub created: String,
+  /// Last message delivered.
+  pub delivered: SequenceInfo,
+  /// Last message acknowledged along with every message before it.
+  pub ack_floor: SequenceInfo,
+  pub num_ack_pending: u64,
+  pub num_redelivered: u64,
+  #[serde(default)]
+  pub num_waiting: u64,
+  pub num_pending: u64,
+}
+
+#[derive(Serialize)]
+struct CreateConsumerRequest<'a> {
+  stream_name: &'a str,
+  config: &'a ConsumerConfig,
+}
+
+/// Message read directly from a stream.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredMessage {
+  pub subject: String,
+  pub sequence: u64,
+  pub headers: Option<Headers>,
+  pub data: Bytes,
+  /// When the message was stored, in RFC 3339 format.
+  pub time: String,
+}
+
+#[derive(Serialize)]
+struct MsgGetRequest<'a> {
+  last_by_subj: &'a str,
+}
+
+#[derive(Deserialize)]
+struct MsgGetResponse {
+  message: RawMessage,
+}
+
+#[derive(Deserialize)]
+struct RawMessage {
+  subject: String,
+  seq: u64,
+  hdrs: Option<String>,
+  data: Option<String>,
+  time: String,
+}
+
+impl RawMessage {
+  fn decode(self) -> Result<StoredMessage, NatsClientError> {
+    let base64 = |data: Option<String>| {
+      base64::decode(data.unwrap_or_default())
+        .map_err(|e| NatsClientError::from((DecodeError, "Invalid stored message", e.to_string())))
+    };
+    let headers = match self.hdrs {
+      Some(hdrs) => Some(Headers::parse(&base64(Some(hdrs))?)?),
+      None => None,
+    };
+    Ok(StoredMessage {
+      subject: self.subject,
+      sequence: self.seq,
+      headers,
+      data: Bytes::from(base64(self.data)?),
+      time: self.time,
+    })
+  }
+}
+
+#[derive(Serialize)]
+struct ListRequest {
+  offset: usize,
+}
+
+#[derive(Deserialize)]
+struct StreamList {
+  total: usize,
+  streams: Option<Vec<StreamInfo>>,
+}
+
+#[derive(Deserialize)]
+struct ConsumerList {
+  total: usize,
+  consumers: Option<Vec<ConsumerInfo>>,
+}
+
+#[derive(Deserialize)]
+struct SuccessResponse {
+  success: bool,
+}
+
+#[derive(Serialize)]
+struct PurgeRequest<'a> {
+  filter: &'a str,
+}
+
+#[derive(Deserialize)]
+struct PurgeResponse {
+  success: bool,
+  purged: u64,
+}
+
+impl<'a> JetStream<'a> {
+  pub fn create_stream(&mut self, config: &StreamConfig) -> Result<StreamInfo, NatsClientError> {
+    check_name(&config.name)?;
+    self.api_request(&format!("STREAM.CREATE.{}", config.name), Some(config))
+  }
+
+  /// Change the configuration of an existing stream. Some settings, such as
+  /// the storage type, cannot be changed.
+  pub fn update_stream(&mut self, config: &StreamConfig) -> Result<StreamInfo, NatsClientError> {
+    check_name(&config.name)?;
+    self.api_request(&format!("STREAM.UPDATE.{}", config.name), Some(config))
+  }
+
+  pub fn stream_info(&mut self, stream: &str) -> Result<StreamInfo, NatsClientError> {
+    check_name(stream)?;
+    self.api_request(&format!("STREAM.INFO.{}", stream), None::<&()>)
+  }
+
+  /// Delete `stream` along with its messages and consumers.
+  pub fn delete_stream(&mut self, stream: &str) -> Result<(), NatsClientError> {
+    check_name(stream)?;
+    let response: SuccessResponse =
+      self.api_request(&format!("STREAM.DELETE.{}", stream), None::<&()>)?;
+    check_success(response.success)
+  }
+
+  /// Delete every message of `stream`, returning how many were deleted.
+  pub fn purge_stream(&mut self, stream: &str) -> Result<u64, NatsClientError> {
+    check_name(stream)?;
+    let response: PurgeResponse =
+      self.api_request(&format!("STREAM.PURGE.{}", stream), None::<&()>)?;
+    check_success(response.success)?;
+    Ok(response.purged)
+  }
+
+  /// Delete the messages of `stream` stored on the subjects matching
+  /// `filter`, returning how many were deleted.
+  pub fn purge_subject(&mut self, stream: &str, filter: &str) -> Result<u64, NatsClientError> {
+    check_name(stream)?;
+    check_subject(filter, true)?;
+    let request = PurgeRequest { filter };
+    let response: PurgeResponse =
+      self.api_request(&format!("STREAM.PURGE.{}", stream), Some(&request))?;
+    check_success(response.success)?;
+    Ok(response.purged)
+  }
+
+  /// Every stream of the account.
+  pub fn list_streams(&mut self) -> Result<Vec<StreamInfo>, NatsClientError> {
+    let mut streams = Vec::new();
+    loop {
+      let request = ListRequest {
+        offset: streams.len(),
+      };
+      let page: StreamList = self.api_request("STREAM.LIST", Some(&request))?;
+      let page_streams = page.streams.unwrap_or_default();
+      if page_streams.is_empty() {
+        return Ok(streams);
+      }
+      streams.extend(page_streams);
+      if streams.len() >= page.total {
+        return Ok(streams);
+      }
+    }
+  }
+
+  /// Create a consumer on `stream`, durable if `config` names it.
+  pub fn create_consumer(
+    &mut self,
+    stream: &str,
+    config: &ConsumerConfig,
+  ) -> Result<ConsumerInfo, NatsClientError> {
+    check_name(stream)?;
+    let endpoint = match config.durable_name {
+      Some(ref durable) => {
+        check_name(durable)?;
+        format!("CONSUMER.DURABLE.CREATE.{}.{}", stream, durable)
+      }
+      None => format!("CONSUMER.CREATE.{}", stream),
+    };
+    let request = CreateConsumerRequest {
+      stream_name: stream,
+      config,
+    };
+    self.api_request(&endpoint, Some(&request))
+  }
+
+  /// Change the configuration of a durable consumer. Only some settings,
+  /// such as its description or `max_deliver`, can be changed.
+  pub fn update_consumer(
+    &mut self,
+    stream: &str,
+    config: &ConsumerConfig,
+  ) -> Result<ConsumerInfo, NatsClientError> {
+    if config.durable_name.is_none() {
+      return Err(NatsClientError::from((
+        InvalidClientConfig,
+        "Only durable consumers can be updated",
+      )));
+    }
+    self.create_consumer(stream, config)
+  }
+
+  pub fn consumer_info(
+    &mut self,
+    stream: &str,
+    consumer: &str,
+  ) -> Result<ConsumerInfo, NatsClientError> {
+    check_name(stream)?;
+    check_name(consumer)?;
+    self.api_request(
+      &format!("CONSUMER.INFO.{}.{}", stream, consumer),
+      None::<&()>,
+    )
+  }
+
+  pub fn delete_consumer(&mut self, stream: &str, consumer: &str) -> Result<(), NatsClientError> {
+    check_name(stream)?;
+    check_name(consumer)?;
+    let response: SuccessResponse = self.api_request(
+      &format!("CONSUMER.DELETE.{}.{}", stream, consumer),
+      None::<&()>,
+    )?;
+    check_success(response.success)
+  }
+
+  /// Every consumer of `stream`.
+  pub fn list_consumers(&mut self, stream: &str) -> Result<Vec<ConsumerInfo>, NatsClientError> {
+    check_name(stream)?;
+    let endpoint = format!("CONSUMER.LIST.{}", stream);
+    let mut consumers = Vec::new();
+    loop {
+      let request = ListRequest {
+        offset: consumers.len(),
+      };
+      let page: ConsumerList = self.api_request(&endpoint, Some(&request))?;
+      let page_consumers = page.consumers.unwrap_or_default();
+      if page_consumers.is_empty() {
+        return Ok(consumers);
+      }
+      consumers.extend(page_consumers);
+      if consumers.len() >= page.total {
+        return Ok(consumers);
+      }
+    }
+  }
+
+  /// The last message stored on `subject` by `stream`, if any.
+  pub fn get_last_message(
+    &mut self,
+    stream: &str,
+    subject: &str,
+  ) -> Result<Option<StoredMessage>, NatsClientError> {
+    check_name(stream)?;
+    check_subject(subject, false)?;
+    let request = MsgGetRequest {
+      last_by_subj: subject,
+    };
+    let response = self.api_call(&format!("STREAM.MSG.GET.{}", stream), Some(&request))?;
+    if let Ok(ErrorResponse {
+      error: Some(ApiError {
+        code: NO_MESSAGES_STATUS,
+        ..
+      }),
+    }) = serde_json::from_slice(&response)
+    {
+      return Ok(None);
+    }
+    let response: MsgGetResponse = api_response(&response)?;
+    response.message.decode().map(Some)
+  }
+
+  /// Subscribe to an ephemeral push consumer of `stream` created for the
+  /// subscription, delivering the messages of `filter` without
+  /// acknowledgments. Also returns the consumer, whose `num_pending` counts
+  /// the messages to deliver.
+  pub(crate) fn ephemeral_subscribe(
+    &mut self,
+    stream: &str,
+    filter: &str,
+    deliver_policy: DeliverPolicy,
+  ) -> Result<(PushSubscription<'_>, ConsumerInfo), NatsClientError> {
+    // Subscribe first: the consumer starts delivering once created.
+    let inbox = self.client.new_inbox();
+    let subscription = self.client.subscribe(&inbox, None)?;
+    let config = ConsumerConfig {
+      deliver_subject: Some(inbox),
+      deliver_policy,
+      ack_policy: AckPolicy::None,
+      filter_subject: Some(filter.to_owned()),
+      idle_heartbeat: Duration::from_secs(5),
+      flow_control: true,
+      ..ConsumerConfig::default()
+    };
+    let info = self.create_consumer(stream, &config)?;
+    let mut subscription = PushSubscription {
+      client: &mut *self.client,
+      subscription,
+      idle_heartbeat: None,
+      last_activity: Instant::now(),
+    };
+    subscription.set_idle_heartbeat(config.idle_heartbeat);
+    Ok((subscription, info))
+  }
+
+  /// Send `request` to the `endpoint` of the API, e.g. `STREAM.INFO.ORDERS`,
+  /// and decode the response.
+  pub(crate) fn api_request<T: DeserializeOwned, R: Serialize>(
+    &mut self,
+    endpoint: &str,
+    request: Option<&R>,
+  ) -> Result<T, NatsClientError> {
+    let response = self.api_call(endpoint, request)?;
+    api_response(&response)
+  }
+
+  fn api_call<R: Serialize>(
+    &mut self,
+    endpoint: &str,
+    request: Option<&R>,
+  ) -> Result<Bytes, NatsClientError> {
+    let payload = match request {
+      Some(request) => serde_json::to_vec(request).map_err(|e| {
+        NatsClientError::from((
+          TypeError,
+          "Failed to encode JetStream request",
+          e.to_string(),
+        ))
+      })?,
+      None => Vec::new(),
+    };
+    let subject = format!("{}.{}", self.options.api_prefix, endpoint);
+    let reply = self
+      .client
+      .request_timeout(&subject, &payload, self.options.timeout)?;
+    Ok(reply.msg)
+  }
+}
+
+fn check_success(success: bool) -> Result<(), NatsClientError> {
+  if success {
+    Ok(())
+  } else {
+    Err(NatsClientError::from((
+      JetStreamError,
+      "JetStream request was not successful",
+    )))
+  }
+}
+
+/// Durations sent to the API as nanoseconds.
+mod nanos {
+  use serde::{Deserialize, Deserializer, Serializer};
+  use std::time::Duration;
+
+  pub(super) fn serialize<S: Serializer>(
+    duration: &Duration,
+    serializer: S,
+  ) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_nanos() as u64)
+  }
+
+  pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+  ) -> Result<Duration, D::Error> {
+    u64::deserialize(deserializer).map(Duration::from_nanos)
+  }
+}
+
+#[derive(Deserialize)]
+struct ApiError {
+  code: u16,
+  #[serde(default)]
+  err_code: u16,
+  description: String,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+  error: Option<ApiError>,
+}
+
+/// Decode a response of the JetStream API, which is either `T` or an error.
+fn api_response<T: DeserializeOwned>(msg: &[u8]) -> Result<T, NatsClientError> {
+  let decode_error = |e: serde_json::Error| {
+    NatsClientError::from((DecodeError, "Invalid JetStream response", e.to_string()))
+  };
+  let response: ErrorResponse = serde_json::from_slice(msg).map_err(decode_error)?;
+  if let Some(error) = response.error {
+    return Err(NatsClientError::from((
+      JetStreamError,
+      "JetStream request failed",
+      format!(
+        "{} (code {}, error code {})",
+        error.description, error.code, error.err_code
+      ),
+    )));
+  }
+  serde_json::from_slice(msg).map_err(decode_error)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::mock::{MockServer, ServerConn};
+  use std::sync::{Arc, Mutex};
+
+  /// Read a request published with headers, returning its reply subject and
+  /// its raw header block.
+  fn read_hpub(conn: &mut ServerConn) -> (String, String) {
+    let args = conn.expect("HPUB");
+    let payload = conn.read_payload(&args);
+    conn.ack();
+    let header_len: usize = args[args.len() - 2].parse().unwrap();
+    let headers = String::from_utf8(payload[..header_len].to_vec()).unwrap();
+    (args[1].clone(), headers)
+  }
+
+  #[test]
+  fn test_publish_retries_without_responders() {
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let seen = requests.clone();
+    let server = MockServer::new(move |mut conn| {
+      conn.handshake(r#"{"headers":true}"#);
+      let mux = conn.expect("SUB");
+      conn.ack();
+      let (reply, headers) = read_hpub(&mut conn);
+      seen.lock().unwrap().push(headers);
+      let status = "NATS/1.0 503\r\n\r\n";
+      conn.send(&format!(
+        "HMSG {} {} {} {}\r\n{}\r\n",
+        reply,
+        mux[1],
+        status.len(),
+        status.len(),
+        status
+      ));
+      let (reply, headers) = read_hpub(&mut conn);
+      seen.lock().unwrap().push(headers);
+      let ack = r#"{"stream":"ORDERS","seq":7}"#;
+      conn.send(&format!(
+        "MSG {} {} {}\r\n{}\r\n",
+        reply,
+        mux[1],
+        ack.len(),
+        ack
+      ));
+      while conn.read_line().is_some() {}
+    });
+    let mut nc = Client::new("nats://localhost").unwrap();
+    nc.mock_server = Some(server);
+    let options = JetStreamOptions::new().publish_retries(1, Duration::from_millis(1));
+    let ack = nc
+      .jetstream_with_options(options)
+      .publish_with_options(
+        "orders.new",
+        "42",
+        &PublishOptions::new().msg_id("order-42"),
+      )
+      .unwrap();
+    assert_eq!(ack.stream, "ORDERS");
+    assert_eq!(ack.sequence, 7);
+    assert!(!ack.duplicate);
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 2);
+    assert!(requests[1].contains("Nats-Msg-Id: order-42\r\n"));
+  }
+
+  #[test]
+  fn test_api_response() {
+    let ack: PubAck = api_response(br#"{"stream":"S","seq":3,"duplicate":true}"#).unwrap();
+    assert!(ack.duplicate);
+    let err = api_response::<PubAck>(
+      br#"{"error":{"code":400,"err_code":10060,"description":"expected stream does not match"}}"#,
+    )
+    .unwrap_err();
+    assert_eq!(err.kind(), JetStreamError);
+    assert!(err.to_string().contains("expected stream does not match"));
+  }
+
+  #[test]
+  fn test_message_info() {
+    let info = MessageInfo::parse("$JS.ACK.ORDERS.worker.2.10.3.1700000000000000000.5").unwrap();
+    assert_eq!(
+      info,
+      MessageInfo {
+        stream: "ORDERS".to_owned(),
+        consumer: "worker".to_owned(),
+        delivered: 2,
+        stream_sequence: 10,
+        consumer_sequence: 3,
+        timestamp: 1_700_000_000_000_000_000,
+        pending: 5,
+      }
+    );
+    let info =
+      MessageInfo::parse("$JS.ACK.hub.ACCHASH.ORDERS.worker.1.11.4.1700000000000000000.0.rand")
+        .unwrap();
+    assert_eq!(info.stream_sequence, 11);
+    assert!(MessageInfo::parse("_INBOX.abc").is_err());
+    assert!(MessageInfo::parse("$JS.ACK.ORDERS.worker.x.10.3.1.5").is_err());
+  }
+
+  #[test]
+  fn test_pull_fetch_and_ack() {
+    let acks = Arc::new(Mutex::new(Vec::new()));
+    let seen = acks.clone();
+    let server = MockServer::new(move |mut conn| {
+      conn.handshake(r#"{"headers":true}"#);
+      let inbox = conn.expect("SUB");
+      conn.ack();
+      let args = conn.expect("PUB");
+      assert_eq!(args[0], "$JS.API.CONSUMER.MSG.NEXT.ORDERS.worker");
+      assert_eq!(args[1], inbox[0]);
+      let request = conn.read_payload(&args);
+      assert_eq!(request, br#"{"batch":5,"expires":1000000000}"#.to_vec());
+      conn.ack();
+      for seq in 1..=2 {
+        conn.send(&format!(
+          "MSG orders.new {} $JS.ACK.ORDERS.worker.1.{}.{}.1700000000000000000.{} 1\r\n{}\r\n",
+          inbox[1],
+          seq,
+          seq,
+          2 - seq,
+          seq
+        ));
+      }
+      let status = "NATS/1.0 404 No Messages\r\n\r\n";
+      conn.send(&format!(
+        "HMSG {} {} {} {}\r\n{}\r\n",
+        inbox[0],
+        inbox[1],
+        status.len(),
+        status.len(),
+        status
+      ));
+      while let Some(line) = conn.read_line() {
+        if line.starts_with("PUB ") {
+          let args: Vec<String> = line.split_whitespace().skip(1).map(str::to_owned).collect();
+          let payload = conn.read_payload(&args);
+          conn.ack();
+          seen
+            .lock()
+            .unwrap()
+            .push((args[0].clone(), String::from_utf8(payload).unwrap()));
+        }
+      }
+    });
+    let mut nc = Client::new("nats://localhost").unwrap();
+    nc.mock_server = Some(server);
+    let mut sub = nc.jetstream().pull_subscribe("ORDERS", "worker").unwrap();
+    let messages = sub.fetch(5, Duration::from_secs(1)).unwrap();
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[1].msg, Bytes::from_static(b"2"));
+    assert_eq!(messages[1].info().unwrap().stream_sequence, 2);
+    sub.ack(&messages[0]).unwrap();
+    sub.nak(&messages[1]).unwrap();
+    drop(sub);
+    nc.flush().unwrap();
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while acks.lock().unwrap().len() < 2 && Instant::now() < deadline {
+      thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(
+      *acks.lock().unwrap(),
+      vec![
+        (
+          "$JS.ACK.ORDERS.worker.1.1.1.1700000000000000000.1".to_owned(),
+          "+ACK".to_owned()
+        ),
+        (
+          "$JS.ACK.ORDERS.worker.1.2.2.1700000000000000000.0".to_owned(),
+          "-NAK".to_owned()
+        ),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_push_flow_control() {
+    let server = MockServer::new(|mut conn| {
+      conn.handshake(r#"{"headers":true}"#);
+      let sub = conn.expect("SUB");
+      assert_eq!(sub[0], "deliver.orders");
+      conn.ack();
+      let status = "NATS/1.0 100 FlowControl Request\r\n\r\n";
+      conn.send(&format!(
+        "HMSG deliver.orders {} $JS.FC.ORDERS.abc.1 {} {}\r\n{}\r\n",
+        sub[1],
+        status.len(),
+        status.len(),
+        status
+      ));
+      let status =
+        "NATS/1.0 100 Idle Heartbeat\r\nNats-Consumer-Stalled: $JS.FC.ORDERS.abc.2\r\n\r\n";
+      conn.send(&format!(
+        "HMSG deliver.orders {} {} {}\r\n{}\r\n",
+        sub[1],
+        status.len(),
+        status.len(),
+        status
+      ));
+      for reply in &["$JS.FC.ORDERS.abc.1", "$JS.FC.ORDERS.abc.2"] {
+        let args = conn.expect("PUB");
+        assert_eq!(args, vec![reply.to_string(), "0".to_owned()]);
+        conn.read_payload(&args);
+        conn.ack();
+      }
+      conn.send(&format!(
+        "MSG orders.new {} $JS.ACK.ORDERS.abc.1.1.1.1700000000000000000.0 2\r\n42\r\n",
+        sub[1]
+      ));
+      while conn.read_line().is_some() {}
+    });
+    let mut nc = Client::new("nats://localhost").unwrap();
+    nc.mock_server = Some(server);
+    let mut sub = nc
+      .jetstream()
+      .push_subscribe("deliver.orders", None)
+      .unwrap();
+    let msg = sub.next_msg(Duration::from_secs(2)).unwrap().unwrap();
+    assert_eq!(msg.subject, "orders.new");
+    assert_eq!(msg.msg, Bytes::from_static(b"42"));
+  }
+
+  #[test]
+  fn test_push_missed_heartbeats() {
+    let server = MockServer::new(|mut conn| {
+      conn.handshake("{}");
+      conn.expect("SUB");
+      conn.ack();
+      while conn.read_line().is_some() {}
+    });
+    let mut nc = Client::new("nats://localhost").unwrap();
+    nc.mock_server = Some(server);
+    let mut sub = nc
+      .jetstream()
+      .push_subscribe("deliver.orders", None)
+      .unwrap();
+    assert!(sub.next_msg(Duration::from_millis(20)).unwrap().is_none());
+    sub.set_idle_heartbeat(Duration::from_millis(20));
+    let err = sub.next_msg(Duration::from_secs(2)).unwrap_err();
+    assert_eq!(err.kind(), Timeout);
+  }
+
+  #[test]
+  fn test_stream_config_serialization() {
+    let config = StreamConfig {
+      subjects: vec!["orders.>".to_owned()],
+      max_age: Duration::from_secs(60),
+      storage: StorageType::Memory,
+      retention: RetentionPolicy::WorkQueue,
+      ..StreamConfig::new("ORDERS")
+    };
+    let json: serde_json::Value = serde_json::to_value(&config).unwrap();
+    assert_eq!(json["max_age"], 60_000_000_000u64);
+    assert_eq!(json["storage"], "memory");
+    assert_eq!(json["retention"], "workqueue");
+    assert_eq!(json["max_msgs"], -1);
+    assert!(json.get("description").is_none());
+    // Settings unknown to the client are ignored, missing ones defaulted.
+    let parsed: StreamConfig =
+      serde_json::from_str(r#"{"name":"ORDERS","subjects":["orders.>"],"max_age":60000000000,"storage":"memory","retention":"workqueue","sealed":false}"#).unwrap();
+    assert_eq!(parsed, config);
+    let config = ConsumerConfig::durable("worker");
+    let json = serde_json::to_value(&config).unwrap();
+    assert_eq!(json["deliver_policy"], "all");
+    assert_eq!(json["ack_policy"], "explicit");
+    assert!(json.get("idle_heartbeat").is_none());
+  }
+
+  #[test]
+  fn test_list_streams_pages() {
+    let server = MockServer::new(|mut conn| {
+      conn.handshake("{}");
+      let mux = conn.expect("SUB");
+      conn.ack();
+      for offset in 0..2 {
+        let args = conn.expect("PUB");
+        assert_eq!(args[0], "$JS.API.STREAM.LIST");
+        let request = conn.read_payload(&args);
+        conn.ack();
+        assert_eq!(request, format!(r#"{{"offset":{}}}"#, offset).into_bytes());
+        let page = format!(
+          r#"{{"total":2,"offset":{},"limit":1,"streams":[{{"config":{{"name":"S{}"}},"state":{{"messages":0,"bytes":0,"first_seq":0,"last_seq":0}},"created":"2022-01-01T00:00:00Z"}}]}}"#,
+          offset, offset
+        );
+        conn.send(&format!(
+          "MSG {} {} {}\r\n{}\r\n",
+          args[1],
+          mux[1],
+          page.len(),
+          page
+        ));
+      }
+      let args = conn.expect("PUB");
+      assert_eq!(args[0], "$JS.API.STREAM.DELETE.S0");
+      conn.read_payload(&args);
+      conn.ack();
+      let error = r#"{"error":{"code":404,"err_code":10059,"description":"stream not found"}}"#;
+      conn.send(&format!(
+        "MSG {} {} {}\r\n{}\r\n",
+        args[1],
+        mux[1],
+        error.len(),
+        error
+      ));
+      while conn.read_line().is_some() {}
+    });
+    let mut nc = Client::new("nats://localhost").unwrap();
+    nc.mock_server = Some(server);
+    let mut js = nc.jetstream();
+    let streams = js.list_streams().unwrap();
+    let names: Vec<_> = streams.iter().map(|s| s.config.name.as_str()).collect();
+    assert_eq!(names, vec!["S0", "S1"]);
+    assert_eq!(streams[1].config.max_msgs, -1);
+    let err = js.delete_stream("S0").unwrap_err();
+    assert_eq!(err.kind(), JetStreamError);
+    assert!(js.delete_stream("a.b").is_err());
+  }
+}
@@ -0,0 +1,532 @@
+//! Key-value store built on JetStream.
+//!
+//! A bucket is the stream `KV_<bucket>`, and the value of a key is the last
+//! message on `$KV.<bucket>.<key>`. Deletions are messages with a
+//! `KV-Operation` header, so the history of a key is kept up to the limit of
+//! the bucket.
+//!
+//! ```no_run
+//! let mut nc = client::Client::new("nats://127.0.0.1:4222").unwrap();
+//! let mut kv = nc
+//!   .jetstream()
+//!   .create_bucket(&client::kv::Config::new("settings"))
+//!   .unwrap();
+//! kv.put("theme", "dark").unwrap();
+//! if let Some(entry) = kv.get("theme").unwrap() {
+//!   println!("{:?} at revision {}", entry.value, entry.revision);
+//! }
+//! ```
+
+use crate::client::check_subject;
+use crate::errors::{ErrorKind::*, *};
+use crate::headers::Headers;
+use crate::jetstream::{
+  DeliverPolicy, DiscardPolicy, JetStream, Message, PushSubscription, StorageType, StoredMessage,
+  StreamConfig,
+};
+use bytes::Bytes;
+use std::time::Duration;
+
+const OPERATION_HEADER: &str = "KV-Operation";
+const ROLLUP_HEADER: &str = "Nats-Rollup";
+const MAX_HISTORY: i64 = 64;
+
+/// Configuration of a bucket. Negative limits mean unlimited.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+  pub bucket: String,
+  pub description: Option<String>,
+  /// Number of values kept per key, at most 64.
+  pub history: i64,
+  /// How long values are kept, zero for ever.
+  pub max_age: Duration,
+  pub max_bytes: i64,
+  pub max_value_size: i32,
+  pub storage: StorageType,
+  pub num_replicas: usize,
+}
+
+impl Config {
+  pub fn new(bucket: &str) -> Config {
+    Config {
+      bucket: bucket.to_owned(),
+      description: None,
+      history: 1,
+      max_age: Duration::from_secs(0),
+      max_bytes: -1,
+      max_value_size: -1,
+      storage: StorageType::File,
+      num_replicas: 1,
+    }
+  }
+}
+
+/// Change recorded for a key.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operation {
+  Put,
+  Delete,
+  /// Deletion also removing the history of the key.
+  Purge,
+}
+
+/// Value of a key at a revision.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+  pub bucket: String,
+  pub key: String,
+  pub value: Bytes,
+  /// Sequence of the change in the bucket stream.
+  pub revision: u64,
+  pub operation: Operation,
+}
+
+/// Bucket of a key-value store, borrowing the client.
+#[derive(Debug)]
+pub struct Store<'a> {
+  js: JetStream<'a>,
+  bucket: String,
+  stream: String,
+  // `$KV.<bucket>.`
+  prefix: String,
+}
+
+impl<'a> JetStream<'a> {
+  /// Create the bucket described by `config`, or bind to it if it exists
+  /// with the same configuration.
+  pub fn create_bucket(mut self, config: &Config) -> Result<Store<'a>, NatsClientError> {
+    check_bucket(&config.bucket)?;
+    if config.history < 1 || config.history > MAX_HISTORY {
+      return Err(NatsClientError::from((
+        InvalidClientConfig,
+        "The history of a bucket must be between 1 and 64 values",
+      )));
+    }
+    let stream = StreamConfig {
+      description: config.description.clone(),
+      subjects: vec![format!("$KV.{}.>", config.bucket)],
+      max_msgs_per_subject: config.history,
+      max_age: config.max_age,
+      max_bytes: config.max_bytes,
+      max_msg_size: config.max_value_size,
+      storage: config.storage,
+      num_replicas: config.num_replicas,
+      discard: DiscardPolicy::New,
+      deny_delete: true,
+      allow_rollup_hdrs: true,
+      ..StreamConfig::new(&format!("KV_{}", config.bucket))
+    };
+    self.create_stream(&stream)?;
+    Ok(Store::new(self, &config.bucket))
+  }
+
+  /// Bind to the existing bucket `bucket`.
+  pub fn bucket(mut self, bucket: &str) -> Result<Store<'a>, NatsClientError> {
+    check_bucket(bucket)?;
+    self.stream_info(&format!("KV_{}", bucket))?;
+    Ok(Store::new(self, bucket))
+  }
+
+  /// Delete `bucket` along with its keys.
+  pub fn delete_bucket(&mut self, bucket: &str) -> Result<(), NatsClientError> {
+    check_bucket(bucket)?;
+    self.delete_stream(&format!("KV_{}", bucket))
+  }
+}
+
+impl<'a> Store<'a> {
+  fn new(js: JetStream<'a>, bucket: &str) -> Store<'a> {
+    Store {
+      js,
+      bucket: bucket.to_owned(),
+      stream: format!("KV_{}", bucket),
+      prefix: format!("$KV.{}.", bucket),
+    }
+  }
+
+  pub fn bucket(&self) -> &str {
+    &self.bucket
+  }
+
+  /// Current value of `key`, `None` if it was never set or was deleted.
+  pub fn get(&mut self, key: &str) -> Result<Option<Entry>, NatsClientError> {
+    check_key(key, false)?;
+    let subject = format!("{}{}", self.prefix, key);
+    let msg = match self.js.get_last_message(&self.stream, &subject)? {
+      Some(msg) => msg,
+      None => return Ok(None),
+    };
+    let entry = self.stored_entry(msg)?;
+    Ok(Some(entry).filter(|e| e.operation == Operation::Put))
+  }
+
+  /// Set the value of `key`, returning its revision.
+  pub fn put<V: AsRef<[u8]>>(&mut self, key: &str, value: V) -> Result<u64, NatsClientError> {
+    check_key(key, false)?;
+    let subject = format!("{}{}", self.prefix, key);
+    let ack = self
+      .js
+      .publish_with_headers(&subject, &Headers::new(), value.as_ref())?;
+    Ok(ack.sequence)
+  }
+
+  /// Delete `key`, keeping its history.
+  pub fn delete(&mut self, key: &str) -> Result<(), NatsClientError> {
+    let mut headers = Headers::new();
+    headers.insert(OPERATION_HEADER, "DEL");
+    self.mark(key, &headers)
+  }
+
+  /// Delete `key` along with its history.
+  pub fn purge(&mut self, key: &str) -> Result<(), NatsClientError> {
+    let mut headers = Headers::new();
+    headers.insert(OPERATION_HEADER, "PURGE");
+    headers.insert(ROLLUP_HEADER, "sub");
+    self.mark(key, &headers)
+  }
+
+  fn mark(&mut self, key: &str, headers: &Headers) -> Result<(), NatsClientError> {
+    check_key(key, false)?;
+    let subject = format!("{}{}", self.prefix, key);
+    self.js.publish_with_headers(&subject, headers, b"")?;
+    Ok(())
+  }
+
+  /// Every change of `key` still kept by the bucket, oldest first.
+  pub fn history(&mut self, key: &str) -> Result<Vec<Entry>, NatsClientError> {
+    check_key(key, false)?;
+    let subject = format!("{}{}", self.prefix, key);
+    self.entries(
+      &subject,
+      DeliverPolicy::All,
+      "History of the key not received in time",
+    )
+  }
+
+  /// Keys having a value, sorted.
+  pub fn keys(&mut self) -> Result<Vec<String>, NatsClientError> {
+    let subject = format!("{}>", self.prefix);
+    let entries = self.entries(
+      &subject,
+      DeliverPolicy::LastPerSubject,
+      "Keys of the bucket not received in time",
+    )?;
+    let mut keys: Vec<_> = entries
+      .into_iter()
+      .filter(|e| e.operation == Operation::Put)
+      .map(|e| e.key)
+      .collect();
+    keys.sort();
+    Ok(keys)
+  }
+
+  /// Entries of `subject` delivered by an ephemeral consumer, until none is
+  /// pending.
+  fn entries(
+    &mut self,
+    subject: &str,
+    policy: DeliverPolicy,
+    timeout_error: &'static str,
+  ) -> Result<Vec<Entry>, NatsClientError> {
+    let timeout = self.js.options.timeout;
+    let (bucket, prefix) = (self.bucket.clone(), self.prefix.clone());
+    let (mut sub, info) = self.js.ephemeral_subscribe(&self.stream, subject, policy)?;
+    let mut entries = Vec::new();
+    if info.num_pending == 0 {
+      return Ok(entries);
+    }
+    loop {
+      let msg = sub.next_msg(timeout)?.ok_or((Timeout, timeout_error))?;
+      let pending = msg.info()?.pending;
+      entries.push(delivered_entry(&bucket, &prefix, msg)?);
+      if pending == 0 {
+        return Ok(entries);
+      }
+    }
+  }
+
+  /// Watch the keys matching `keys`, which may contain wildcards, e.g.
+  /// `users.*` or `>` for every key. The watch starts with the current value
+  /// of every key, then yields their changes.
+  pub fn watch(&mut self, keys: &str) -> Result<Watch<'_>, NatsClientError> {
+    check_key(keys, true)?;
+    let subject = format!("{}{}", self.prefix, keys);
+    let (bucket, prefix) = (self.bucket.clone(), self.prefix.clone());
+    let (subscription, _) =
+      self
+        .js
+        .ephemeral_subscribe(&self.stream, &subject, DeliverPolicy::LastPerSubject)?;
+    Ok(Watch {
+      subscription,
+      bucket,
+      prefix,
+    })
+  }
+
+  fn stored_entry(&self, msg: StoredMessage) -> Result<Entry, NatsClientError> {
+    Ok(Entry {
+      bucket: self.bucket.clone(),
+      key: key_of(&self.prefix, &msg.subject)?,
+      operation: operation(msg.headers.as_ref()),
+      value: msg.data,
+      revision: msg.sequence,
+    })
+  }
+}
+
+/// Changes of the keys watched with `Store::watch()`.
+#[derive(Debug)]
+pub struct Watch<'a> {
+  subscription: PushSubscription<'a>,
+  bucket: String,
+  prefix: String,
+}
+
+impl<'a> Watch<'a> {
+  /// Wait at most `timeout` for the next change. Returns `Ok(None)` when no
+  /// key changed in time.
+  pub fn next(&mut self, timeout: Duration) -> Result<Option<Entry>, NatsClientError> {
+    match self.subscription.next_msg(timeout)? {
+      Some(msg) => delivered_entry(&self.bucket, &self.prefix, msg).map(Some),
+      None => Ok(None),
+    }
+  }
+}
+
+fn delivered_entry(bucket: &str, prefix: &str, msg: Message) -> Result<Entry, NatsClientError> {
+  Ok(Entry {
+    bucket: bucket.to_owned(),
+    key: key_of(prefix, &msg.subject)?,
+    revision: msg.info()?.stream_sequence,
+    operation: operation(msg.headers.as_ref()),
+    value: msg.msg,
+  })
+}
+
+fn key_of(prefix: &str, subject: &str) -> Result<String, NatsClientError> {
+  subject
+    .strip_prefix(prefix)
+    .map(str::to_owned)
+    .ok_or_else(|| {
+      NatsClientError::from((
+        ServerProtocolError,
+        "Message outside of the bucket",
+        subject.to_owned(),
+      ))
+    })
+}
+
+fn operation(headers: Option<&Headers>) -> Operation {
+  match headers.and_then(|h| h.get(OPERATION_HEADER)) {
+    Some("DEL") => Operation::Delete,
+    Some("PURGE") => Operation::Purge,
+    _ => Operation::Put,
+  }
+}
+
+fn check_bucket(bucket: &str) -> Result<(), NatsClientError> {
+  let valid = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+  if bucket.is_empty() || !bucket.chars().all(valid) {
+    return Err(NatsClientError::from((
+      InvalidClientConfig,
+      "Invalid bucket name",
+      bucket.to_owned(),
+    )));
+  }
+  Ok(())
+}
+
+/// Keys are subject tokens made of letters, digits and `-/_=`. Patterns may
+/// also contain wildcards.
+fn check_key(key: &str, allow_wildcards: bool) -> Result<(), NatsClientError> {
+  let valid = |c: char| {
+    c.is_ascii_alphanumeric() || "-/_=.".contains(c) || (allow_wildcards && "*>".contains(c))
+  };
+  if !key.chars().all(valid) {
+    return Err(NatsClientError::from((
+      ClientProtocolError,
+      "Invalid key",
+      key.to_owned(),
+    )));
+  }
+  check_subject(key, allow_wildcards)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::client::Client;
+  use crate::mock::MockServer;
+
+  fn stored(subject: &str, seq: u64, headers: Option<&str>, data: &str) -> String {
+    let hdrs = headers
+      .map(|h| format!(r#""hdrs":"{}","#, base64::encode(h)))
+      .unwrap_or_default();
+    format!(
+      r#"{{"message":{{"subject":"{}","seq":{},{}"data":"{}","time":"2022-01-01T00:00:00Z"}}}}"#,
+      subject,
+      seq,
+      hdrs,
+      base64::encode(data)
+    )
+  }
+
+  #[test]
+  fn test_put_get_delete() {
+    let server = MockServer::new(|mut conn| {
+      conn.handshake(r#"{"headers":true}"#);
+      let mut seq = 0;
+      conn.serve(|conn, msg| {
+        let response = match msg.subject.as_str() {
+          "$JS.API.STREAM.CREATE.KV_settings" => {
+            let config: serde_json::Value = serde_json::from_slice(&msg.payload).unwrap();
+            assert_eq!(config["subjects"][0], "$KV.settings.>");
+            assert_eq!(config["max_msgs_per_subject"], 5);
+            assert_eq!(config["discard"], "new");
+            assert_eq!(config["allow_rollup_hdrs"], true);
+            format!(
+              r#"{{"config":{},"state":{{"messages":0,"bytes":0,"first_seq":0,"last_seq":0}},"created":"2022-01-01T00:00:00Z"}}"#,
+              config
+            )
+          }
+          "$KV.settings.theme" => {
+            seq += 1;
+            let del = msg.headers.is_some_and(|h| h.contains("KV-Operation: DEL"));
+            assert_eq!(del, seq == 2);
+            format!(r#"{{"stream":"KV_settings","seq":{}}}"#, seq)
+          }
+          "$JS.API.STREAM.MSG.GET.KV_settings" => match seq {
+            0 => r#"{"error":{"code":404,"err_code":10037,"description":"no message found"}}"#
+              .to_owned(),
+            1 => stored("$KV.settings.theme", 1, None, "dark"),
+            _ => stored(
+              "$KV.settings.theme",
+              2,
+              Some("NATS/1.0\r\nKV-Operation: DEL\r\n\r\n"),
+              "",
+            ),
+          },
+          subject => panic!("unexpected {}", subject),
+        };
+        conn.deliver(
+          msg.reply.as_deref().unwrap(),
+          None,
+          None,
+          response.as_bytes(),
+        );
+      });
+    });
+    let mut nc = Client::new("nats://localhost").unwrap();
+    nc.mock_server = Some(server);
+    let config = Config {
+      history: 5,
+      ..Config::new("settings")
+    };
+    let mut kv = nc.jetstream().create_bucket(&config).unwrap();
+    assert_eq!(kv.get("theme").unwrap(), None);
+    assert_eq!(kv.put("theme", "dark").unwrap(), 1);
+    let entry = kv.get("theme").unwrap().unwrap();
+    assert_eq!(entry.value, Bytes::from_static(b"dark"));
+    assert_eq!(entry.revision, 1);
+    kv.delete("theme").unwrap();
+    assert_eq!(kv.get("theme").unwrap(), None);
+    assert!(kv.put("bad key", "x").is_err());
+  }
+
+  #[test]
+  fn test_history() {
+    let server = MockServer::new(|mut conn| {
+      conn.handshake(r#"{"headers":true}"#);
+      conn.serve(|conn, msg| {
+        if msg.subject.starts_with("$JS.API.STREAM.INFO.") {
+          let info = r#"{"config":{"name":"KV_settings"},"state":{"messages":2,"bytes":9,"first_seq":1,"last_seq":2},"created":"2022-01-01T00:00:00Z"}"#;
+          conn.deliver(msg.reply.as_deref().unwrap(), None, None, info.as_bytes());
+          return;
+        }
+        assert_eq!(msg.subject, "$JS.API.CONSUMER.CREATE.KV_settings");
+        let request: serde_json::Value = serde_json::from_slice(&msg.payload).unwrap();
+        let config = &request["config"];
+        assert_eq!(config["filter_subject"], "$KV.settings.theme");
+        assert_eq!(config["ack_policy"], "none");
+        let info = format!(
+          r#"{{"stream_name":"KV_settings","name":"eph","config":{},"created":"2022-01-01T00:00:00Z","delivered":{{"consumer_seq":0,"stream_seq":0}},"ack_floor":{{"consumer_seq":0,"stream_seq":0}},"num_ack_pending":0,"num_redelivered":0,"num_pending":2}}"#,
+          config
+        );
+        conn.deliver(msg.reply.as_deref().unwrap(), None, None, info.as_bytes());
+        let deliver = config["deliver_subject"].as_str().unwrap();
+        conn.deliver_as(
+          deliver,
+          "$KV.settings.theme",
+          Some("$JS.ACK.KV_settings.eph.1.1.1.1700000000000000000.1"),
+          None,
+          b"dark",
+        );
+        conn.deliver_as(
+          deliver,
+          "$KV.settings.theme",
+          Some("$JS.ACK.KV_settings.eph.1.2.2.1700000000000000000.0"),
+          Some("NATS/1.0\r\nKV-Operation: PURGE\r\n\r\n"),
+          b"",
+        );
+      });
+    });
+    let mut nc = Client::new("nats://localhost").unwrap();
+    nc.mock_server = Some(server);
+    let mut kv = nc.jetstream().bucket("settings").unwrap();
+    let history = kv.history("theme").unwrap();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].value, Bytes::from_static(b"dark"));
+    assert_eq!(history[0].operation, Operation::Put);
+    assert_eq!(history[1].revision, 2);
+    assert_eq!(history[1].operation, Operation::Purge);
+  }
+
+  #[test]
+  fn test_keys() {
+    let server = MockServer::new(|mut conn| {
+      conn.handshake(r#"{"headers":true}"#);
+      conn.serve(|conn, msg| {
+        if msg.subject.starts_with("$JS.API.STREAM.INFO.") {
+          let info = r#"{"config":{"name":"KV_settings"},"state":{"messages":3,"bytes":9,"first_seq":1,"last_seq":3},"created":"2022-01-01T00:00:00Z"}"#;
+          conn.deliver(msg.reply.as_deref().unwrap(), None, None, info.as_bytes());
+          return;
+        }
+        let request: serde_json::Value = serde_json::from_slice(&msg.payload).unwrap();
+        let config = &request["config"];
+        assert_eq!(config["filter_subject"], "$KV.settings.>");
+        assert_eq!(config["deliver_policy"], "last_per_subject");
+        let info = format!(
+          r#"{{"stream_name":"KV_settings","name":"eph","config":{},"created":"2022-01-01T00:00:00Z","delivered":{{"consumer_seq":0,"stream_seq":0}},"ack_floor":{{"consumer_seq":0,"stream_seq":0}},"num_ack_pending":0,"num_redelivered":0,"num_pending":3}}"#,
+          config
+        );
+        conn.deliver(msg.reply.as_deref().unwrap(), None, None, info.as_bytes());
+        let deliver = config["deliver_subject"].as_str().unwrap();
+        conn.deliver_as(
+          deliver,
+          "$KV.settings.theme",
+          Some("$JS.ACK.KV_settings.eph.1.1.1.1700000000000000000.2"),
+          None,
+          b"dark",
+        );
+        conn.deliver_as(
+          deliver,
+          "$KV.settings.font",
+          Some("$JS.ACK.KV_settings.eph.1.2.2.1700000000000000000.1"),
+          Some("NATS/1.0\r\nKV-Operation: DEL\r\n\r\n"),
+          b"",
+        );
+        conn.deliver_as(
+          deliver,
+          "$KV.settings.lang",
+          Some("$JS.ACK.KV_settings.eph.1.3.3.1700000000000000000.0"),
+          None,
+          b"en",
+        );
+      });
+    });
+    let mut nc = Client::new("nats://localhost").unwrap();
+    nc.mock_server = Some(server);
+    let mut kv = nc.jetstream().bucket("settings").unwrap();
+    assert_eq!(kv.keys().unwrap(), vec!["lang", "theme"]);
+  }
+}
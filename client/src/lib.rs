@@ -1,8 +1,36 @@
 pub use crate::client::*;
+pub use crate::dispatch::*;
 pub use crate::errors::*;
+pub use crate::headers::*;
+pub use crate::options::*;
+pub use crate::reconnect_policy::*;
 pub use crate::tls_config::*;
+pub use crate::typed::*;
+pub use bytes::Bytes;
+
+#[macro_use]
+mod macros;
+
+#[cfg(feature = "async")]
+pub mod asynk;
+pub mod jetstream;
+pub mod kv;
+pub mod object_store;
+pub mod service;
+pub mod subject;
 
 mod client;
+mod context;
+mod creds;
+mod dispatch;
 mod errors;
+mod headers;
+#[cfg(test)]
+mod mock;
+mod options;
+mod proxy;
+mod reconnect_policy;
 mod stream;
+mod telemetry;
 mod tls_config;
+mod typed;
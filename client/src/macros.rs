@@ -0,0 +1,45 @@
+//! Logging macros forwarding to the `log` crate when the `logging` feature is
+//! enabled. Otherwise they compile to nothing, only type-checking their
+//! arguments.
+
+#[cfg(feature = "logging")]
+macro_rules! debug {
+  ($($arg:tt)*) => { log::debug!($($arg)*) };
+}
+
+#[cfg(not(feature = "logging"))]
+macro_rules! debug {
+  ($($arg:tt)*) => {
+    if false {
+      let _ = format_args!($($arg)*);
+    }
+  };
+}
+
+#[cfg(feature = "logging")]
+macro_rules! info {
+  ($($arg:tt)*) => { log::info!($($arg)*) };
+}
+
+#[cfg(not(feature = "logging"))]
+macro_rules! info {
+  ($($arg:tt)*) => {
+    if false {
+      let _ = format_args!($($arg)*);
+    }
+  };
+}
+
+#[cfg(feature = "logging")]
+macro_rules! warn {
+  ($($arg:tt)*) => { log::warn!($($arg)*) };
+}
+
+#[cfg(not(feature = "logging"))]
+macro_rules! warn {
+  ($($arg:tt)*) => {
+    if false {
+      let _ = format_args!($($arg)*);
+    }
+  };
+}
@@ -0,0 +1,354 @@
+//! In-memory transport for unit tests. A `MockServer` runs a script on each
+//! connection opened by the client, so the handshake, reconnects and protocol
+//! parsing can be exercised without binding sockets.
+
+use std::{
+  collections::VecDeque,
+  fmt,
+  io::{self, BufRead, BufReader, Read, Write},
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Condvar, Mutex,
+  },
+  thread,
+  time::Duration,
+};
+
+/// Bytes in flight in one direction of a connection.
+#[derive(Debug, Default)]
+struct Buffer {
+  state: Mutex<BufferState>,
+  ready: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct BufferState {
+  data: VecDeque<u8>,
+  closed: bool,
+}
+
+impl Buffer {
+  fn close(&self) {
+    self.state.lock().unwrap().closed = true;
+    self.ready.notify_all();
+  }
+}
+
+/// One end of a connection, closed once every clone of it is dropped.
+#[derive(Debug)]
+struct End {
+  incoming: Arc<Buffer>,
+  outgoing: Arc<Buffer>,
+  read_timeout: Mutex<Option<Duration>>,
+}
+
+impl Drop for End {
+  fn drop(&mut self) {
+    self.incoming.close();
+    self.outgoing.close();
+  }
+}
+
+/// One end of an in-memory duplex connection. Like sockets, clones share
+/// the connection and its read timeout.
+#[derive(Clone, Debug)]
+pub(crate) struct MockStream {
+  end: Arc<End>,
+}
+
+impl MockStream {
+  /// Both ends of a new connection.
+  pub(crate) fn pair() -> (MockStream, MockStream) {
+    let a = Arc::new(Buffer::default());
+    let b = Arc::new(Buffer::default());
+    let end = |incoming, outgoing| MockStream {
+      end: Arc::new(End {
+        incoming,
+        outgoing,
+        read_timeout: Mutex::new(None),
+      }),
+    };
+    (end(a.clone(), b.clone()), end(b, a))
+  }
+
+  pub(crate) fn set_read_timeout(&self, timeout: Option<Duration>) {
+    *self.end.read_timeout.lock().unwrap() = timeout;
+  }
+}
+
+impl Read for MockStream {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let timeout = *self.end.read_timeout.lock().unwrap();
+    let incoming = &self.end.incoming;
+    let mut state = incoming.state.lock().unwrap();
+    while state.data.is_empty() && !state.closed {
+      state = match timeout {
+        None => incoming.ready.wait(state).unwrap(),
+        Some(timeout) => {
+          let (state, res) = incoming.ready.wait_timeout(state, timeout).unwrap();
+          if res.timed_out() && state.data.is_empty() {
+            return Err(io::ErrorKind::WouldBlock.into());
+          }
+          state
+        }
+      };
+    }
+    let len = buf.len().min(state.data.len());
+    for (dst, src) in buf.iter_mut().zip(state.data.drain(..len)) {
+      *dst = src;
+    }
+    Ok(len)
+  }
+}
+
+impl Write for MockStream {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let outgoing = &self.end.outgoing;
+    let mut state = outgoing.state.lock().unwrap();
+    if state.closed {
+      return Err(io::ErrorKind::BrokenPipe.into());
+    }
+    state.data.extend(buf);
+    outgoing.ready.notify_all();
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+type Script = dyn Fn(ServerConn) + Send + Sync;
+
+/// Fake server running `script` in a new thread for each connection.
+#[derive(Clone)]
+pub(crate) struct MockServer {
+  script: Arc<Script>,
+  connections: Arc<AtomicUsize>,
+}
+
+impl MockServer {
+  pub(crate) fn new<F>(script: F) -> MockServer
+  where
+    F: Fn(ServerConn) + Send + Sync + 'static,
+  {
+    MockServer {
+      script: Arc::new(script),
+      connections: Arc::new(AtomicUsize::new(0)),
+    }
+  }
+
+  /// Open a connection, returning the client end.
+  pub(crate) fn connect(&self) -> MockStream {
+    let (client, server) = MockStream::pair();
+    let index = self.connections.fetch_add(1, Ordering::SeqCst);
+    let script = self.script.clone();
+    thread::spawn(move || script(ServerConn::new(server, index)));
+    client
+  }
+
+  /// Number of connections opened so far.
+  pub(crate) fn connections(&self) -> usize {
+    self.connections.load(Ordering::SeqCst)
+  }
+}
+
+impl fmt::Debug for MockServer {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+    f.debug_struct("MockServer")
+      .field("connections", &self.connections())
+      .finish()
+  }
+}
+
+/// Server end of a connection, with helpers to script the exchange. Helpers
+/// panic when the client does not behave as expected.
+pub(crate) struct ServerConn {
+  reader: BufReader<MockStream>,
+  writer: MockStream,
+  verbose: bool,
+  // Subjects and sids of the subscriptions seen by `serve()`.
+  subs: Vec<(String, String)>,
+  /// Order of the connection, starting at 0.
+  pub index: usize,
+}
+
+impl ServerConn {
+  fn new(stream: MockStream, index: usize) -> ServerConn {
+    ServerConn {
+      reader: BufReader::new(stream.clone()),
+      writer: stream,
+      verbose: false,
+      subs: Vec::new(),
+      index,
+    }
+  }
+
+  pub(crate) fn send(&mut self, data: &str) {
+    self.writer.write_all(data.as_bytes()).unwrap();
+  }
+
+  /// Next line sent by the client, `None` once it closed the connection.
+  pub(crate) fn read_line(&mut self) -> Option<String> {
+    let mut line = String::new();
+    match self.reader.read_line(&mut line).unwrap() {
+      0 => None,
+      _ => Some(line),
+    }
+  }
+
+  /// Read a line starting with `op`, e.g. `SUB`, returning its arguments.
+  pub(crate) fn expect(&mut self, op: &str) -> Vec<String> {
+    let line = self.read_line().expect("connection closed by the client");
+    let mut args = line.split_whitespace().map(str::to_owned);
+    assert_eq!(args.next().as_deref(), Some(op), "unexpected {:?}", line);
+    args.collect()
+  }
+
+  /// Read the payload of a PUB, whose length is its last argument.
+  pub(crate) fn read_payload(&mut self, args: &[String]) -> Vec<u8> {
+    let len: usize = args.last().unwrap().parse().unwrap();
+    let mut payload = vec![0; len + 2];
+    self.reader.read_exact(&mut payload).unwrap();
+    payload.truncate(len);
+    payload
+  }
+
+  /// Acknowledge the last command if the client is verbose.
+  pub(crate) fn ack(&mut self) {
+    if self.verbose {
+      self.send("+OK\r\n");
+    }
+  }
+
+  /// Send `info`, then answer CONNECT and the PING following it.
+  pub(crate) fn handshake(&mut self, info: &str) {
+    self.send(&format!("INFO {}\r\n", info));
+    let args = self.expect("CONNECT");
+    self.verbose = args.join(" ").contains("\"verbose\":true");
+    self.ack();
+    self.expect("PING");
+    self.send("PONG\r\n");
+  }
+
+  /// Serve the client until it closes the connection: acknowledge its
+  /// commands, answer its PINGs, track its subscriptions, and pass the
+  /// messages it publishes to `handler`.
+  pub(crate) fn serve<F: FnMut(&mut ServerConn, Published)>(&mut self, mut handler: F) {
+    while let Some(line) = self.read_line() {
+      let mut args = line.split_whitespace().map(str::to_owned);
+      let op = args.next().unwrap_or_default();
+      let args: Vec<String> = args.collect();
+      match op.as_str() {
+        "SUB" => {
+          let sid = args.last().unwrap().clone();
+          self.subs.push((args[0].clone(), sid));
+          self.ack();
+        }
+        "UNSUB" => {
+          self.subs.retain(|(_, sid)| *sid != args[0]);
+          self.ack();
+        }
+        "PING" => self.send("PONG\r\n"),
+        "PUB" | "HPUB" => {
+          let mut payload = self.read_payload(&args);
+          self.ack();
+          let (reply, headers) = match (op.as_str(), args.len()) {
+            ("PUB", 3) | ("HPUB", 4) => (Some(args[1].clone()), args.len() == 4),
+            (op, _) => (None, op == "HPUB"),
+          };
+          let headers = if headers {
+            let len: usize = args[args.len() - 2].parse().unwrap();
+            let body = payload.split_off(len);
+            let headers = String::from_utf8(payload).unwrap();
+            payload = body;
+            Some(headers)
+          } else {
+            None
+          };
+          let published = Published {
+            subject: args[0].clone(),
+            reply,
+            headers,
+            payload,
+          };
+          handler(self, published);
+        }
+        _ => panic!("unexpected {:?}", line),
+      }
+    }
+  }
+
+  /// Send a message to every subscription seen by `serve()` that matches
+  /// `subject`.
+  pub(crate) fn deliver(
+    &mut self,
+    subject: &str,
+    reply: Option<&str>,
+    headers: Option<&str>,
+    payload: &[u8],
+  ) {
+    self.deliver_as(subject, subject, reply, headers, payload)
+  }
+
+  /// Like `deliver()`, sending the message to the subscriptions matching
+  /// `to` instead, as JetStream does for push consumers.
+  pub(crate) fn deliver_as(
+    &mut self,
+    to: &str,
+    subject: &str,
+    reply: Option<&str>,
+    headers: Option<&str>,
+    payload: &[u8],
+  ) {
+    let sids: Vec<String> = self
+      .subs
+      .iter()
+      .filter(|(pattern, _)| matches(pattern, to))
+      .map(|(_, sid)| sid.clone())
+      .collect();
+    let reply = reply.map(|r| format!("{} ", r)).unwrap_or_default();
+    for sid in sids {
+      let mut frame = match headers {
+        Some(headers) => format!(
+          "HMSG {} {} {}{} {}\r\n{}",
+          subject,
+          sid,
+          reply,
+          headers.len(),
+          headers.len() + payload.len(),
+          headers
+        ),
+        None => format!("MSG {} {} {}{}\r\n", subject, sid, reply, payload.len()),
+      }
+      .into_bytes();
+      frame.extend_from_slice(payload);
+      frame.extend_from_slice(b"\r\n");
+      self.writer.write_all(&frame).unwrap();
+    }
+  }
+}
+
+/// Message published by the client, see `ServerConn::serve()`.
+#[derive(Debug)]
+pub(crate) struct Published {
+  pub subject: String,
+  pub reply: Option<String>,
+  /// Raw header block of an HPUB.
+  pub headers: Option<String>,
+  pub payload: Vec<u8>,
+}
+
+/// Whether `subject` matches the subscription `pattern`.
+fn matches(pattern: &str, subject: &str) -> bool {
+  let mut tokens = subject.split('.');
+  for pattern in pattern.split('.') {
+    match (pattern, tokens.next()) {
+      (">", Some(_)) => return true,
+      ("*", Some(_)) => {}
+      (pattern, Some(token)) if pattern == token => {}
+      _ => return false,
+    }
+  }
+  tokens.next().is_none()
+}
@@ -0,0 +1,601 @@
+//! Object store built on JetStream, for values too large for a single
+//! message.
+//!
+//! A bucket is the stream `OBJ_<bucket>`. The content of an object is split
+//! in chunks stored on `$O.<bucket>.C.<nuid>`, and its metadata, including
+//! the SHA-256 digest of the content, is the last message on
+//! `$O.<bucket>.M.<encoded name>`. An object may also be a link to an object
+//! of another bucket.
+//!
+//! ```no_run
+//! # use std::fs::File;
+//! let mut nc = client::Client::new("nats://127.0.0.1:4222").unwrap();
+//! let mut store = nc
+//!   .jetstream()
+//!   .create_object_store(&client::object_store::Config::new("backups"))
+//!   .unwrap();
+//! store.put("db.dump", File::open("db.dump").unwrap()).unwrap();
+//! store.get("db.dump", File::create("restored.dump").unwrap()).unwrap();
+//! ```
+
+use crate::client::new_id;
+use crate::errors::{ErrorKind::*, *};
+use crate::headers::Headers;
+use crate::jetstream::{DeliverPolicy, DiscardPolicy, JetStream, StorageType, StreamConfig};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+  io::{self, Read, Write},
+  time::Duration,
+};
+
+const DEFAULT_CHUNK_SIZE: usize = 128 * 1024;
+const DIGEST_PREFIX: &str = "SHA-256=";
+const ROLLUP_HEADER: &str = "Nats-Rollup";
+
+/// Configuration of an object store bucket. Negative limits mean unlimited.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+  pub bucket: String,
+  pub description: Option<String>,
+  /// How long objects are kept, zero for ever.
+  pub max_age: Duration,
+  pub max_bytes: i64,
+  pub storage: StorageType,
+  pub num_replicas: usize,
+}
+
+impl Config {
+  pub fn new(bucket: &str) -> Config {
+    Config {
+      bucket: bucket.to_owned(),
+      description: None,
+      max_age: Duration::from_secs(0),
+      max_bytes: -1,
+      storage: StorageType::File,
+      num_replicas: 1,
+    }
+  }
+}
+
+/// Metadata of an object.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ObjectInfo {
+  pub name: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub description: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub options: Option<ObjectOptions>,
+  pub bucket: String,
+  /// Identifier of the chunks of the object.
+  pub nuid: String,
+  #[serde(default)]
+  pub size: u64,
+  #[serde(default)]
+  pub chunks: u64,
+  /// `SHA-256=` followed by the base64url digest of the content.
+  #[serde(default, skip_serializing_if = "String::is_empty")]
+  pub digest: String,
+  #[serde(default, skip_serializing_if = "is_false")]
+  pub deleted: bool,
+}
+
+impl ObjectInfo {
+  /// The object this object links to, if it is a link.
+  pub fn link(&self) -> Option<&ObjectLink> {
+    self.options.as_ref().and_then(|o| o.link.as_ref())
+  }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ObjectOptions {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub link: Option<ObjectLink>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub max_chunk_size: Option<usize>,
+}
+
+/// Link to the object `name` of `bucket`, or to the whole bucket without a
+/// name.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ObjectLink {
+  pub bucket: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub name: Option<String>,
+}
+
+fn is_false(value: &bool) -> bool {
+  !value
+}
+
+/// Bucket of an object store, borrowing the client.
+#[derive(Debug)]
+pub struct ObjectStore<'a> {
+  js: JetStream<'a>,
+  bucket: String,
+  stream: String,
+  chunk_size: usize,
+}
+
+impl<'a> JetStream<'a> {
+  /// Create the object store bucket described by `config`, or bind to it if
+  /// it exists with the same configuration.
+  pub fn create_object_store(
+    mut self,
+    config: &Config,
+  ) -> Result<ObjectStore<'a>, NatsClientError> {
+    check_bucket(&config.bucket)?;
+    let stream = StreamConfig {
+      description: config.description.clone(),
+      subjects: vec![
+        format!("$O.{}.C.>", config.bucket),
+        format!("$O.{}.M.>", config.bucket),
+      ],
+      max_age: config.max_age,
+      max_bytes: config.max_bytes,
+      storage: config.storage,
+      num_replicas: config.num_replicas,
+      discard: DiscardPolicy::New,
+      allow_rollup_hdrs: true,
+      ..StreamConfig::new(&format!("OBJ_{}", config.bucket))
+    };
+    self.create_stream(&stream)?;
+    Ok(ObjectStore::new(self, &config.bucket))
+  }
+
+  /// Bind to the existing object store bucket `bucket`.
+  pub fn object_store(mut self, bucket: &str) -> Result<ObjectStore<'a>, NatsClientError> {
+    check_bucket(bucket)?;
+    self.stream_info(&format!("OBJ_{}", bucket))?;
+    Ok(ObjectStore::new(self, bucket))
+  }
+
+  /// Delete the object store `bucket` along with its objects.
+  pub fn delete_object_store(&mut self, bucket: &str) -> Result<(), NatsClientError> {
+    check_bucket(bucket)?;
+    self.delete_stream(&format!("OBJ_{}", bucket))
+  }
+}
+
+impl<'a> ObjectStore<'a> {
+  fn new(js: JetStream<'a>, bucket: &str) -> ObjectStore<'a> {
+    ObjectStore {
+      js,
+      bucket: bucket.to_owned(),
+      stream: format!("OBJ_{}", bucket),
+      chunk_size: DEFAULT_CHUNK_SIZE,
+    }
+  }
+
+  pub fn bucket(&self) -> &str {
+    &self.bucket
+  }
+
+  /// Size of the chunks of the objects stored from now on, 128 KiB by
+  /// default.
+  pub fn set_chunk_size(&mut self, size: usize) {
+    self.chunk_size = size.max(1);
+  }
+
+  /// Store the content read from `reader` as the object `name`, replacing
+  /// the previous object of that name once it is completely stored.
+  pub fn put<R: Read>(&mut self, name: &str, mut reader: R) -> Result<ObjectInfo, NatsClientError> {
+    check_name(name)?;
+    let previous = self.info(name)?;
+    let mut info = ObjectInfo {
+      name: name.to_owned(),
+      description: None,
+      options: Some(ObjectOptions {
+        link: None,
+        max_chunk_size: Some(self.chunk_size),
+      }),
+      bucket: self.bucket.clone(),
+      nuid: new_id(),
+      size: 0,
+      chunks: 0,
+      digest: String::new(),
+      deleted: false,
+    };
+    let chunk_subject = self.chunk_subject(&info.nuid);
+    let mut hasher = Sha256::new();
+    let mut chunk = vec![0; self.chunk_size];
+    let res = loop {
+      let len = match read_chunk(&mut reader, &mut chunk) {
+        Ok(0) => break Ok(()),
+        Ok(len) => len,
+        Err(e) => break Err(NatsClientError::from(e)),
+      };
+      hasher.update(&chunk[..len]);
+      let res = self
+        .js
+        .publish_with_headers(&chunk_subject, &Headers::new(), &chunk[..len]);
+      if let Err(e) = res {
+        break Err(e);
+      }
+      info.size += len as u64;
+      info.chunks += 1;
+    };
+    info.digest = format!(
+      "{}{}",
+      DIGEST_PREFIX,
+      base64::encode_config(hasher.finalize(), base64::URL_SAFE)
+    );
+    if let Err(e) = res.and_then(|_| self.publish_info(&info)) {
+      // Do not leave the chunks of an incomplete object behind.
+      let _ = self.js.purge_subject(&self.stream, &chunk_subject);
+      return Err(e);
+    }
+    if let Some(previous) = previous {
+      self.purge_chunks(&previous)?;
+    }
+    Ok(info)
+  }
+
+  /// Write the content of the object `name` to `writer`, following links,
+  /// and check its digest.
+  pub fn get<W: Write>(
+    &mut self,
+    name: &str,
+    mut writer: W,
+  ) -> Result<ObjectInfo, NatsClientError> {
+    let info = self.info(name)?.ok_or_else(|| not_found(name))?;
+    if let Some(link) = info.link() {
+      let target = link.name.clone().ok_or((
+        InvalidClientConfig,
+        "The object is a link to a bucket, not to an object",
+      ))?;
+      if link.bucket == self.bucket {
+        return self.get(&target, writer);
+      }
+      let mut store = ObjectStore::new(self.js.reborrow(), &link.bucket);
+      return store.get(&target, writer);
+    }
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+    if info.chunks > 0 {
+      let timeout = self.js.options.timeout;
+      let chunk_subject = self.chunk_subject(&info.nuid);
+      let (mut sub, _) =
+        self
+          .js
+          .ephemeral_subscribe(&self.stream, &chunk_subject, DeliverPolicy::All)?;
+      loop {
+        let msg = sub
+          .next_msg(timeout)?
+          .ok_or((Timeout, "Object chunk not received in time"))?;
+        hasher.update(&msg.msg);
+        writer.write_all(&msg.msg)?;
+        size += msg.msg.len() as u64;
+        if msg.info()?.pending == 0 {
+          break;
+        }
+      }
+    }
+    writer.flush()?;
+    let digest = format!(
+      "{}{}",
+      DIGEST_PREFIX,
+      base64::encode_config(hasher.finalize(), base64::URL_SAFE)
+    );
+    if size != info.size || digest != info.digest {
+      return Err(NatsClientError::from((
+        DecodeError,
+        "Object content does not match its digest",
+        name.to_owned(),
+      )));
+    }
+    Ok(info)
+  }
+
+  /// Metadata of the object `name`, `None` if it does not exist.
+  pub fn info(&mut self, name: &str) -> Result<Option<ObjectInfo>, NatsClientError> {
+    check_name(name)?;
+    let subject = self.meta_subject(name);
+    let msg = match self.js.get_last_message(&self.stream, &subject)? {
+      Some(msg) => msg,
+      None => return Ok(None),
+    };
+    let info: ObjectInfo = serde_json::from_slice(&msg.data).map_err(|e| {
+      NatsClientError::from((DecodeError, "Invalid object metadata", e.to_string()))
+    })?;
+    Ok(Some(info).filter(|i| !i.deleted))
+  }
+
+  /// Delete the object `name` and its content.
+  pub fn delete(&mut self, name: &str) -> Result<(), NatsClientError> {
+    let mut info = self.info(name)?.ok_or_else(|| not_found(name))?;
+    let previous = info.clone();
+    info.deleted = true;
+    info.size = 0;
+    info.chunks = 0;
+    info.digest = String::new();
+    self.publish_info(&info)?;
+    self.purge_chunks(&previous)
+  }
+
+  /// Store the object `name` as a link to `target`, an object of this or
+  /// another bucket.
+  pub fn add_link(
+    &mut self,
+    name: &str,
+    target: &ObjectInfo,
+  ) -> Result<ObjectInfo, NatsClientError> {
+    check_name(name)?;
+    if target.deleted || target.link().is_some() {
+      return Err(NatsClientError::from((
+        InvalidClientConfig,
+        "Links must point to an existing object",
+      )));
+    }
+    if let Some(existing) = self.info(name)? {
+      if existing.link().is_none() {
+        return Err(NatsClientError::from((
+          InvalidClientConfig,
+          "An object with that name already exists",
+          name.to_owned(),
+        )));
+      }
+    }
+    let info = ObjectInfo {
+      name: name.to_owned(),
+      description: None,
+      options: Some(ObjectOptions {
+        link: Some(ObjectLink {
+          bucket: target.bucket.clone(),
+          name: Some(target.name.clone()),
+        }),
+        max_chunk_size: None,
+      }),
+      bucket: self.bucket.clone(),
+      nuid: new_id(),
+      size: 0,
+      chunks: 0,
+      digest: String::new(),
+      deleted: false,
+    };
+    self.publish_info(&info)?;
+    Ok(info)
+  }
+
+  /// Metadata of every object of the bucket.
+  pub fn list(&mut self) -> Result<Vec<ObjectInfo>, NatsClientError> {
+    let timeout = self.js.options.timeout;
+    let filter = format!("$O.{}.M.>", self.bucket);
+    let (mut sub, consumer) =
+      self
+        .js
+        .ephemeral_subscribe(&self.stream, &filter, DeliverPolicy::LastPerSubject)?;
+    let mut objects = Vec::new();
+    if consumer.num_pending == 0 {
+      return Ok(objects);
+    }
+    loop {
+      let msg = sub
+        .next_msg(timeout)?
+        .ok_or((Timeout, "Object list not received in time"))?;
+      let info: ObjectInfo = serde_json::from_slice(&msg.msg).map_err(|e| {
+        NatsClientError::from((DecodeError, "Invalid object metadata", e.to_string()))
+      })?;
+      if !info.deleted {
+        objects.push(info);
+      }
+      if msg.info()?.pending == 0 {
+        return Ok(objects);
+      }
+    }
+  }
+
+  /// Publish `info`, replacing the previous metadata of the object.
+  fn publish_info(&mut self, info: &ObjectInfo) -> Result<(), NatsClientError> {
+    let payload = serde_json::to_vec(info).map_err(|e| {
+      NatsClientError::from((TypeError, "Failed to encode object metadata", e.to_string()))
+    })?;
+    let mut headers = Headers::new();
+    headers.insert(ROLLUP_HEADER, "sub");
+    let subject = self.meta_subject(&info.name);
+    self.js.publish_with_headers(&subject, &headers, &payload)?;
+    Ok(())
+  }
+
+  fn purge_chunks(&mut self, info: &ObjectInfo) -> Result<(), NatsClientError> {
+    if info.chunks > 0 {
+      let subject = self.chunk_subject(&info.nuid);
+      self.js.purge_subject(&self.stream, &subject)?;
+    }
+    Ok(())
+  }
+
+  fn chunk_subject(&self, nuid: &str) -> String {
+    format!("$O.{}.C.{}", self.bucket, nuid)
+  }
+
+  /// Names may contain any character, so they are encoded in the subject.
+  fn meta_subject(&self, name: &str) -> String {
+    format!(
+      "$O.{}.M.{}",
+      self.bucket,
+      base64::encode_config(name, base64::URL_SAFE)
+    )
+  }
+}
+
+/// Fill `chunk` from `reader`, returning how many bytes were read, less than
+/// the size of `chunk` only at the end of the content.
+fn read_chunk<R: Read>(reader: &mut R, chunk: &mut [u8]) -> io::Result<usize> {
+  let mut len = 0;
+  while len < chunk.len() {
+    match reader.read(&mut chunk[len..]) {
+      Ok(0) => break,
+      Ok(n) => len += n,
+      Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+      Err(e) => return Err(e),
+    }
+  }
+  Ok(len)
+}
+
+fn not_found(name: &str) -> NatsClientError {
+  NatsClientError::from((JetStreamError, "Object not found", name.to_owned()))
+}
+
+fn check_bucket(bucket: &str) -> Result<(), NatsClientError> {
+  let valid = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+  if bucket.is_empty() || !bucket.chars().all(valid) {
+    return Err(NatsClientError::from((
+      InvalidClientConfig,
+      "Invalid bucket name",
+      bucket.to_owned(),
+    )));
+  }
+  Ok(())
+}
+
+fn check_name(name: &str) -> Result<(), NatsClientError> {
+  if name.is_empty() {
+    return Err(NatsClientError::from((
+      InvalidClientConfig,
+      "Object names cannot be empty",
+    )));
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::client::Client;
+  use crate::mock::{MockServer, ServerConn};
+  use std::sync::{Arc, Mutex};
+
+  /// Messages stored by the fake stream: subject, headers and payload.
+  type Stored = Arc<Mutex<Vec<(String, Option<String>, Vec<u8>)>>>;
+
+  /// Minimal JetStream storing every message published on `$O.`, serving
+  /// direct gets, purges and ephemeral consumers.
+  fn fake_jetstream(conn: &mut ServerConn, stored: &Stored) {
+    let stored = stored.clone();
+    conn.serve(move |conn, msg| {
+      let reply = msg.reply.clone().unwrap();
+      let mut messages = stored.lock().unwrap();
+      let response = if msg.subject.starts_with("$O.") {
+        messages.push((msg.subject.clone(), msg.headers.clone(), msg.payload.clone()));
+        format!(r#"{{"stream":"OBJ_files","seq":{}}}"#, messages.len())
+      } else if msg.subject == "$JS.API.STREAM.MSG.GET.OBJ_files" {
+        let request: serde_json::Value = serde_json::from_slice(&msg.payload).unwrap();
+        let subject = request["last_by_subj"].as_str().unwrap();
+        match messages.iter().rposition(|m| m.0 == subject) {
+          Some(pos) => format!(
+            r#"{{"message":{{"subject":"{}","seq":{},"data":"{}","time":"2022-01-01T00:00:00Z"}}}}"#,
+            subject,
+            pos + 1,
+            base64::encode(&messages[pos].2)
+          ),
+          None => r#"{"error":{"code":404,"err_code":10037,"description":"no message found"}}"#.to_owned(),
+        }
+      } else if msg.subject == "$JS.API.STREAM.PURGE.OBJ_files" {
+        let request: serde_json::Value = serde_json::from_slice(&msg.payload).unwrap();
+        let filter = request["filter"].as_str().unwrap().to_owned();
+        let before = messages.len();
+        messages.retain(|m| m.0 != filter);
+        format!(r#"{{"success":true,"purged":{}}}"#, before - messages.len())
+      } else if msg.subject == "$JS.API.CONSUMER.CREATE.OBJ_files" {
+        let request: serde_json::Value = serde_json::from_slice(&msg.payload).unwrap();
+        let config = &request["config"];
+        let filter = config["filter_subject"].as_str().unwrap();
+        let matching: Vec<_> = messages.iter().filter(|m| m.0 == filter).cloned().collect();
+        let info = format!(
+          r#"{{"stream_name":"OBJ_files","name":"eph","config":{},"created":"2022-01-01T00:00:00Z","delivered":{{"consumer_seq":0,"stream_seq":0}},"ack_floor":{{"consumer_seq":0,"stream_seq":0}},"num_ack_pending":0,"num_redelivered":0,"num_pending":{}}}"#,
+          config,
+          matching.len()
+        );
+        conn.deliver(&reply, None, None, info.as_bytes());
+        let deliver = config["deliver_subject"].as_str().unwrap();
+        for (i, (subject, _, payload)) in matching.iter().enumerate() {
+          let ack = format!(
+            "$JS.ACK.OBJ_files.eph.1.{}.{}.1700000000000000000.{}",
+            i + 1,
+            i + 1,
+            matching.len() - i - 1
+          );
+          conn.deliver_as(deliver, subject, Some(&ack), None, payload);
+        }
+        return;
+      } else {
+        panic!("unexpected {}", msg.subject);
+      };
+      conn.deliver(&reply, None, None, response.as_bytes());
+    });
+  }
+
+  fn store_server(stored: &Stored) -> MockServer {
+    let stored = stored.clone();
+    MockServer::new(move |mut conn| {
+      conn.handshake(r#"{"headers":true}"#);
+      fake_jetstream(&mut conn, &stored);
+    })
+  }
+
+  /// Bind to the bucket `files` without asking the server.
+  fn files(nc: &mut Client) -> ObjectStore<'_> {
+    let mut store = ObjectStore::new(nc.jetstream(), "files");
+    store.set_chunk_size(4);
+    store
+  }
+
+  #[test]
+  fn test_put_get_in_chunks() {
+    let stored = Stored::default();
+    let mut nc = Client::new("nats://localhost").unwrap();
+    nc.mock_server = Some(store_server(&stored));
+    let mut store = files(&mut nc);
+    let info = store.put("notes/a b.txt", &b"hello world"[..]).unwrap();
+    assert_eq!(info.size, 11);
+    assert_eq!(info.chunks, 3);
+    assert_eq!(
+      info.digest,
+      "SHA-256=uU0nuZNNPgilLlLX2n2r-sSE7-N6U4DukIj3rOLvzek="
+    );
+    let mut content = Vec::new();
+    let got = store.get("notes/a b.txt", &mut content).unwrap();
+    assert_eq!(content, b"hello world");
+    assert_eq!(got, info);
+
+    // Replacing the object removes the chunks of the previous one.
+    store.put("notes/a b.txt", &b"bye"[..]).unwrap();
+    let chunks = stored
+      .lock()
+      .unwrap()
+      .iter()
+      .filter(|m| m.0.starts_with("$O.files.C."))
+      .count();
+    assert_eq!(chunks, 1);
+
+    store.delete("notes/a b.txt").unwrap();
+    assert_eq!(store.info("notes/a b.txt").unwrap(), None);
+    assert!(store.get("notes/a b.txt", &mut content).is_err());
+  }
+
+  #[test]
+  fn test_link_and_digest_check() {
+    let stored = Stored::default();
+    let mut nc = Client::new("nats://localhost").unwrap();
+    nc.mock_server = Some(store_server(&stored));
+    let mut store = files(&mut nc);
+    let target = store.put("original", &b"data"[..]).unwrap();
+    let link = store.add_link("alias", &target).unwrap();
+    assert_eq!(link.link().unwrap().name.as_deref(), Some("original"));
+    let mut content = Vec::new();
+    store.get("alias", &mut content).unwrap();
+    assert_eq!(content, b"data");
+    assert!(store.add_link("original", &target).is_err());
+
+    // Corrupt the stored chunk.
+    for message in stored.lock().unwrap().iter_mut() {
+      if message.0.starts_with("$O.files.C.") {
+        message.2 = b"DATA".to_vec();
+      }
+    }
+    let err = store.get("original", &mut Vec::new()).unwrap_err();
+    assert_eq!(err.kind(), DecodeError);
+  }
+}
@@ -0,0 +1,137 @@
+use rand::{thread_rng, Rng};
+use std::time::Duration;
+
+const DEFAULT_INITIAL_DELAY_MS: u64 = 250;
+const DEFAULT_MAX_DELAY_MS: u64 = 4000;
+const DEFAULT_MULTIPLIER: f64 = 2.0;
+const DEFAULT_JITTER: f64 = 0.1;
+const DEFAULT_MAX_ATTEMPTS: u32 = 4;
+
+/// How the client waits between rounds of connection attempts when none of
+/// the servers of the cluster can be reached.
+///
+/// The delay starts at `initial_delay` and is multiplied by `multiplier`
+/// after every round, up to `max_delay`. Each delay is then randomly spread
+/// by up to `jitter` of its value so that clients do not reconnect in lock
+/// step.
+///
+/// ```
+/// use std::time::Duration;
+///
+/// let policy = client::ReconnectPolicy::new()
+///   .initial_delay(Duration::from_millis(100))
+///   .max_delay(Duration::from_secs(10))
+///   .max_attempts(None);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReconnectPolicy {
+  initial_delay: Duration,
+  max_delay: Duration,
+  multiplier: f64,
+  jitter: f64,
+  max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+  fn default() -> Self {
+    ReconnectPolicy {
+      initial_delay: Duration::from_millis(DEFAULT_INITIAL_DELAY_MS),
+      max_delay: Duration::from_millis(DEFAULT_MAX_DELAY_MS),
+      multiplier: DEFAULT_MULTIPLIER,
+      jitter: DEFAULT_JITTER,
+      max_attempts: Some(DEFAULT_MAX_ATTEMPTS),
+    }
+  }
+}
+
+impl ReconnectPolicy {
+  pub fn new() -> ReconnectPolicy {
+    ReconnectPolicy::default()
+  }
+
+  /// Delay after the first failed round.
+  pub fn initial_delay(mut self, delay: Duration) -> ReconnectPolicy {
+    self.initial_delay = delay;
+    self
+  }
+
+  /// Upper bound of the delay, before jitter is applied.
+  pub fn max_delay(mut self, delay: Duration) -> ReconnectPolicy {
+    self.max_delay = delay;
+    self
+  }
+
+  /// Factor applied to the delay after every round. `1.0` keeps it constant.
+  pub fn multiplier(mut self, multiplier: f64) -> ReconnectPolicy {
+    self.multiplier = multiplier.max(1.0);
+    self
+  }
+
+  /// Fraction of the delay, between `0.0` and `1.0`, by which it is randomly
+  /// shortened or lengthened.
+  pub fn jitter(mut self, jitter: f64) -> ReconnectPolicy {
+    self.jitter = jitter.clamp(0.0, 1.0);
+    self
+  }
+
+  /// Number of rounds over every server before giving up, `None` retrying
+  /// forever.
+  pub fn max_attempts(mut self, max_attempts: Option<u32>) -> ReconnectPolicy {
+    self.max_attempts = max_attempts;
+    self
+  }
+
+  /// Whether another round should follow the `attempts` rounds that failed.
+  pub(crate) fn should_retry(&self, attempts: u32) -> bool {
+    self.max_attempts.is_none_or(|max| attempts < max)
+  }
+
+  /// Delay to wait after the `attempts` rounds that failed, counting from 1.
+  pub(crate) fn delay(&self, attempts: u32) -> Duration {
+    let exp = attempts.saturating_sub(1).min(i32::MAX as u32) as i32;
+    let delay = self.initial_delay.as_secs_f64() * self.multiplier.powi(exp);
+    let delay = delay.min(self.max_delay.as_secs_f64());
+    let delay = if self.jitter > 0.0 {
+      delay * thread_rng().gen_range(1.0 - self.jitter, 1.0 + self.jitter)
+    } else {
+      delay
+    };
+    Duration::from_secs_f64(delay)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_exponential_delay() {
+    let policy = ReconnectPolicy::new()
+      .initial_delay(Duration::from_millis(100))
+      .max_delay(Duration::from_millis(500))
+      .jitter(0.0);
+    let delays: Vec<_> = (1..6).map(|n| policy.delay(n).as_millis()).collect();
+    assert_eq!(delays, vec![100, 200, 400, 500, 500]);
+  }
+
+  #[test]
+  fn test_jitter() {
+    let policy = ReconnectPolicy::new()
+      .initial_delay(Duration::from_millis(1000))
+      .jitter(0.5);
+    for _ in 0..100 {
+      let delay = policy.delay(1);
+      assert!(delay >= Duration::from_millis(500) && delay <= Duration::from_millis(1500));
+    }
+  }
+
+  #[test]
+  fn test_max_attempts() {
+    let policy = ReconnectPolicy::new().max_attempts(Some(2));
+    assert!(policy.should_retry(1));
+    assert!(!policy.should_retry(2));
+    assert!(ReconnectPolicy::new()
+      .max_attempts(None)
+      .should_retry(u32::MAX));
+  }
+}
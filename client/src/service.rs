@@ -0,0 +1,453 @@
+//! Services answering requests on named endpoints, discoverable through the
+//! `$SRV.PING`, `$SRV.INFO` and `$SRV.STATS` subjects like the services of
+//! the other NATS clients.
+//!
+//! Every endpoint is a queue subscription, so the requests are balanced
+//! between the instances of a service.
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! let mut nc = client::Client::new("nats://127.0.0.1:4222").unwrap();
+//! let mut service = nc
+//!   .add_service(client::service::Config::new("calc", "1.0.0"))
+//!   .unwrap();
+//! service
+//!   .add_endpoint("double", "calc.double", |req| {
+//!     let n: u64 = std::str::from_utf8(&req.msg)
+//!       .ok()
+//!       .and_then(|n| n.parse().ok())
+//!       .ok_or_else(|| client::service::ServiceError::new(400, "not a number"))?;
+//!     Ok((n * 2).to_string().into_bytes())
+//!   })
+//!   .unwrap();
+//! loop {
+//!   service.serve(Duration::from_secs(1)).unwrap();
+//! }
+//! ```
+
+use crate::client::{new_id, Client, Event, Subscription};
+use crate::errors::{ErrorKind::*, *};
+use crate::headers::Headers;
+use serde::Serialize;
+use std::{
+  collections::HashMap,
+  fmt,
+  time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const DEFAULT_QUEUE_GROUP: &str = "q";
+const ERROR_HEADER: &str = "Nats-Service-Error";
+const ERROR_CODE_HEADER: &str = "Nats-Service-Error-Code";
+
+/// Identity of a service.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+  pub name: String,
+  /// Semantic version, e.g. `1.0.0`.
+  pub version: String,
+  pub description: Option<String>,
+  /// Queue group of the endpoints, `q` by default.
+  pub queue_group: String,
+}
+
+impl Config {
+  pub fn new(name: &str, version: &str) -> Config {
+    Config {
+      name: name.to_owned(),
+      version: version.to_owned(),
+      description: None,
+      queue_group: DEFAULT_QUEUE_GROUP.to_owned(),
+    }
+  }
+}
+
+/// Failure returned by a handler, sent to the requester in the
+/// `Nats-Service-Error` and `Nats-Service-Error-Code` headers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServiceError {
+  pub code: u16,
+  pub description: String,
+}
+
+impl ServiceError {
+  pub fn new(code: u16, description: &str) -> ServiceError {
+    ServiceError {
+      code,
+      description: description.to_owned(),
+    }
+  }
+}
+
+impl fmt::Display for ServiceError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+    write!(f, "{} {}", self.code, self.description)
+  }
+}
+
+type Handler = Box<dyn FnMut(&Event) -> Result<Vec<u8>, ServiceError> + Send>;
+
+/// Counters of an endpoint, reported on `$SRV.STATS`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct EndpointStats {
+  pub name: String,
+  pub subject: String,
+  pub queue_group: String,
+  pub num_requests: u64,
+  pub num_errors: u64,
+  pub last_error: String,
+  /// Total time spent in the handler, in nanoseconds.
+  pub processing_time: u64,
+  pub average_processing_time: u64,
+}
+
+struct Endpoint {
+  stats: EndpointStats,
+  handler: Handler,
+  _subscription: Subscription,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Discovery {
+  Ping,
+  Info,
+  Stats,
+}
+
+/// Running service, borrowing the client. The endpoints and discovery
+/// subjects are unsubscribed when it is dropped.
+pub struct Service<'a> {
+  client: &'a mut Client,
+  config: Config,
+  id: String,
+  started: String,
+  // Indexed by sid.
+  endpoints: HashMap<u64, Endpoint>,
+  discovery: HashMap<u64, (Discovery, Subscription)>,
+}
+
+impl Client {
+  /// Start a service, subscribing to its discovery subjects.
+  pub fn add_service(&mut self, config: Config) -> Result<Service<'_>, NatsClientError> {
+    check_name(&config.name)?;
+    if config.version.is_empty() {
+      return Err(NatsClientError::from((
+        InvalidClientConfig,
+        "The version of a service cannot be empty",
+      )));
+    }
+    let id = new_id();
+    let mut discovery = HashMap::new();
+    for (kind, verb) in &[
+      (Discovery::Ping, "PING"),
+      (Discovery::Info, "INFO"),
+      (Discovery::Stats, "STATS"),
+    ] {
+      for subject in &[
+        format!("$SRV.{}", verb),
+        format!("$SRV.{}.{}", verb, config.name),
+        format!("$SRV.{}.{}.{}", verb, config.name, id),
+      ] {
+        let sub = self.subscribe(subject, None)?;
+        discovery.insert(sub.channel().sid, (*kind, sub));
+      }
+    }
+    debug!("Started service {} with id {}", config.name, id);
+    Ok(Service {
+      client: self,
+      config,
+      id,
+      started: rfc3339(SystemTime::now()),
+      endpoints: HashMap::new(),
+      discovery,
+    })
+  }
+}
+
+impl<'a> Service<'a> {
+  /// Unique identifier of this instance of the service.
+  pub fn id(&self) -> &str {
+    &self.id
+  }
+
+  /// Answer the requests received on `subject` with `handler`, whose result
+  /// is sent as the reply.
+  pub fn add_endpoint<F>(
+    &mut self,
+    name: &str,
+    subject: &str,
+    handler: F,
+  ) -> Result<(), NatsClientError>
+  where
+    F: FnMut(&Event) -> Result<Vec<u8>, ServiceError> + Send + 'static,
+  {
+    check_name(name)?;
+    let subscription = self
+      .client
+      .queue_subscribe(subject, &self.config.queue_group)?;
+    let stats = EndpointStats {
+      name: name.to_owned(),
+      subject: subject.to_owned(),
+      queue_group: self.config.queue_group.clone(),
+      ..EndpointStats::default()
+    };
+    self.endpoints.insert(
+      subscription.channel().sid,
+      Endpoint {
+        stats,
+        handler: Box::new(handler),
+        _subscription: subscription,
+      },
+    );
+    Ok(())
+  }
+
+  /// Counters of every endpoint.
+  pub fn stats(&self) -> Vec<EndpointStats> {
+    let mut stats: Vec<_> = self.endpoints.values().map(|e| e.stats.clone()).collect();
+    stats.sort_by(|a, b| a.name.cmp(&b.name));
+    stats
+  }
+
+  /// Reset the counters of every endpoint.
+  pub fn reset(&mut self) {
+    for endpoint in self.endpoints.values_mut() {
+      let stats = &mut endpoint.stats;
+      stats.num_requests = 0;
+      stats.num_errors = 0;
+      stats.last_error.clear();
+      stats.processing_time = 0;
+      stats.average_processing_time = 0;
+    }
+  }
+
+  /// Answer the requests received during `timeout`, returning how many were
+  /// answered. Messages of the other subscriptions of the client are kept
+  /// for them.
+  pub fn serve(&mut self, timeout: Duration) -> Result<usize, NatsClientError> {
+    let deadline = Instant::now() + timeout;
+    let mut served = 0;
+    loop {
+      let event = {
+        let (endpoints, discovery) = (&self.endpoints, &self.discovery);
+        let ours = |sid| endpoints.contains_key(&sid) || discovery.contains_key(&sid);
+        match self.client.dequeue_matching(ours) {
+          Some(event) => event,
+          None => match self.client.read_event(Some(deadline))? {
+            Some(event) if ours(event.channel.sid) => event,
+            Some(event) => {
+              self.client.queue_event(event);
+              continue;
+            }
+            None => return Ok(served),
+          },
+        }
+      };
+      self.handle(event)?;
+      served += 1;
+    }
+  }
+
+  fn handle(&mut self, event: Event) -> Result<(), NatsClientError> {
+    let reply = match event.inbox {
+      Some(ref reply) => reply.clone(),
+      // Nothing to answer to.
+      None => return Ok(()),
+    };
+    let sid = event.channel.sid;
+    if let Some((kind, _)) = self.discovery.get(&sid) {
+      let response = self.discovery_response(*kind)?;
+      return self.client.publish(&reply, &response, None);
+    }
+    let endpoint = match self.endpoints.get_mut(&sid) {
+      Some(endpoint) => endpoint,
+      None => return Ok(()),
+    };
+    let start = Instant::now();
+    let res = (endpoint.handler)(&event);
+    let stats = &mut endpoint.stats;
+    stats.num_requests += 1;
+    stats.processing_time += start.elapsed().as_nanos() as u64;
+    stats.average_processing_time = stats.processing_time / stats.num_requests;
+    match res {
+      Ok(response) => self.client.publish(&reply, &response, None),
+      Err(error) => {
+        stats.num_errors += 1;
+        stats.last_error = error.to_string();
+        let mut headers = Headers::new();
+        headers.insert(ERROR_HEADER, &error.description);
+        headers.insert(ERROR_CODE_HEADER, &error.code.to_string());
+        self
+          .client
+          .publish_with_headers(&reply, &headers, b"", None)
+      }
+    }
+  }
+
+  fn discovery_response(&self, kind: Discovery) -> Result<Vec<u8>, NatsClientError> {
+    let metadata = HashMap::<String, String>::new();
+    let mut response = serde_json::json!({
+      "name": self.config.name,
+      "id": self.id,
+      "version": self.config.version,
+      "metadata": metadata,
+    });
+    match kind {
+      Discovery::Ping => {
+        response["type"] = "io.nats.micro.v1.ping_response".into();
+      }
+      Discovery::Info => {
+        let endpoints: Vec<_> = self
+          .stats()
+          .into_iter()
+          .map(|e| {
+            serde_json::json!({
+              "name": e.name,
+              "subject": e.subject,
+              "queue_group": e.queue_group,
+            })
+          })
+          .collect();
+        response["type"] = "io.nats.micro.v1.info_response".into();
+        response["description"] = self.config.description.clone().unwrap_or_default().into();
+        response["endpoints"] = endpoints.into();
+      }
+      Discovery::Stats => {
+        response["type"] = "io.nats.micro.v1.stats_response".into();
+        response["started"] = self.started.clone().into();
+        response["endpoints"] = serde_json::to_value(self.stats()).unwrap_or_default();
+      }
+    }
+    serde_json::to_vec(&response).map_err(|e| {
+      NatsClientError::from((
+        TypeError,
+        "Failed to encode service response",
+        e.to_string(),
+      ))
+    })
+  }
+}
+
+impl<'a> fmt::Debug for Service<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+    f.debug_struct("Service")
+      .field("name", &self.config.name)
+      .field("id", &self.id)
+      .field("endpoints", &self.stats())
+      .finish()
+  }
+}
+
+fn check_name(name: &str) -> Result<(), NatsClientError> {
+  let valid = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+  if name.is_empty() || !name.chars().all(valid) {
+    return Err(NatsClientError::from((
+      InvalidClientConfig,
+      "Service and endpoint names can only contain letters, digits, `_` and `-`",
+      name.to_owned(),
+    )));
+  }
+  Ok(())
+}
+
+/// Format `time` as an RFC 3339 UTC timestamp, e.g. `2022-01-31T12:00:00Z`.
+fn rfc3339(time: SystemTime) -> String {
+  let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+  let (days, secs) = (secs / 86400, secs % 86400);
+  // Civil date from the days since the epoch, after Howard Hinnant.
+  let z = days as i64 + 719_468;
+  let era = z / 146_097;
+  let doe = z - era * 146_097;
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = doy - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+  format!(
+    "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+    year,
+    month,
+    day,
+    secs / 3600,
+    secs / 60 % 60,
+    secs % 60
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::mock::{MockServer, Published};
+  use std::sync::{Arc, Mutex};
+
+  #[test]
+  fn test_rfc3339() {
+    assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+    let time = UNIX_EPOCH + Duration::from_secs(951_827_696);
+    assert_eq!(rfc3339(time), "2000-02-29T12:34:56Z");
+  }
+
+  #[test]
+  fn test_endpoints_and_discovery() {
+    let replies = Arc::new(Mutex::new(Vec::<Published>::new()));
+    let seen = replies.clone();
+    let server = MockServer::new(move |mut conn| {
+      conn.handshake(r#"{"headers":true}"#);
+      let mut sids = HashMap::new();
+      for _ in 0..10 {
+        let args = conn.expect("SUB");
+        sids.insert(args[0].clone(), args.last().unwrap().clone());
+        conn.ack();
+      }
+      assert_eq!(sids.len(), 10);
+      let add = &sids["calc.double"];
+      conn.send(&format!("MSG calc.double {} reply.1 2\r\n21\r\n", add));
+      conn.send(&format!("MSG calc.double {} reply.2 1\r\nx\r\n", add));
+      conn.send(&format!(
+        "MSG $SRV.PING {} reply.3 0\r\n\r\n",
+        sids["$SRV.PING"]
+      ));
+      let stats = &sids["$SRV.STATS.calc"];
+      conn.send(&format!("MSG $SRV.STATS.calc {} reply.4 0\r\n\r\n", stats));
+      let seen = seen.clone();
+      conn.serve(move |_, msg| seen.lock().unwrap().push(msg));
+    });
+    let mut nc = Client::new("nats://localhost").unwrap();
+    nc.mock_server = Some(server);
+    let mut service = nc.add_service(Config::new("calc", "1.0.0")).unwrap();
+    service
+      .add_endpoint("double", "calc.double", |req| {
+        let n: u64 = std::str::from_utf8(&req.msg)
+          .ok()
+          .and_then(|n| n.parse().ok())
+          .ok_or_else(|| ServiceError::new(400, "not a number"))?;
+        Ok((n * 2).to_string().into_bytes())
+      })
+      .unwrap();
+    assert_eq!(service.serve(Duration::from_millis(200)).unwrap(), 4);
+    let id = service.id().to_owned();
+    let stats = service.stats();
+    assert_eq!(stats[0].num_requests, 2);
+    assert_eq!(stats[0].num_errors, 1);
+    assert_eq!(stats[0].last_error, "400 not a number");
+    drop(service);
+    nc.flush().unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while replies.lock().unwrap().len() < 4 && Instant::now() < deadline {
+      std::thread::sleep(Duration::from_millis(10));
+    }
+    let replies = replies.lock().unwrap();
+    assert_eq!(replies[0].subject, "reply.1");
+    assert_eq!(replies[0].payload, b"42");
+    let headers = replies[1].headers.as_deref().unwrap();
+    assert!(headers.contains("Nats-Service-Error: not a number\r\n"));
+    assert!(headers.contains("Nats-Service-Error-Code: 400\r\n"));
+    let ping: serde_json::Value = serde_json::from_slice(&replies[2].payload).unwrap();
+    assert_eq!(ping["type"], "io.nats.micro.v1.ping_response");
+    assert_eq!(ping["id"], id.as_str());
+    let stats: serde_json::Value = serde_json::from_slice(&replies[3].payload).unwrap();
+    assert_eq!(stats["endpoints"][0]["num_requests"], 2);
+    assert_eq!(stats["endpoints"][0]["queue_group"], "q");
+  }
+}
@@ -1,22 +1,66 @@
-use std::io::{Read, Result, Write};
+#[cfg(test)]
+use crate::mock::MockStream;
+use std::io::{IoSlice, Read, Result, Write};
 use std::net::TcpStream;
+#[cfg(feature = "tls")]
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[cfg(feature = "tls")]
+pub type TlsStream = rustls::StreamOwned<rustls::ClientConnection, TcpStream>;
 
 #[derive(Debug)]
 pub enum Stream {
   Tcp(TcpStream),
+  // The reader and the writer share the TLS session, which cannot be cloned.
+  #[cfg(feature = "tls")]
+  Tls(Arc<Mutex<TlsStream>>),
+  #[cfg(test)]
+  Mock(MockStream),
 }
 
 impl Stream {
   pub fn try_clone(&self) -> Result<Stream> {
     match *self {
       Stream::Tcp(ref s) => Ok(Stream::Tcp(s.try_clone()?)),
+      #[cfg(feature = "tls")]
+      Stream::Tls(ref s) => Ok(Stream::Tls(s.clone())),
+      #[cfg(test)]
+      Stream::Mock(ref s) => Ok(Stream::Mock(s.clone())),
     }
   }
 
   pub fn as_tcp(&self) -> Result<TcpStream> {
     match *self {
       Stream::Tcp(ref s) => s.try_clone(),
+      #[cfg(feature = "tls")]
+      Stream::Tls(ref s) => s.lock().unwrap().sock.try_clone(),
+      #[cfg(test)]
+      Stream::Mock(_) => Err(std::io::ErrorKind::Unsupported.into()),
+    }
+  }
+
+  pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+    match *self {
+      Stream::Tcp(ref s) => s.set_read_timeout(timeout),
+      #[cfg(feature = "tls")]
+      Stream::Tls(ref s) => s.lock().unwrap().sock.set_read_timeout(timeout),
+      #[cfg(test)]
+      Stream::Mock(ref s) => {
+        s.set_read_timeout(timeout);
+        Ok(())
+      }
+    }
+  }
+
+  /// Writes to a mock stream never block, its write timeout is ignored.
+  pub fn set_write_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+    match *self {
+      Stream::Tcp(ref s) => s.set_write_timeout(timeout),
+      #[cfg(feature = "tls")]
+      Stream::Tls(ref s) => s.lock().unwrap().sock.set_write_timeout(timeout),
+      #[cfg(test)]
+      Stream::Mock(_) => Ok(()),
     }
   }
 }
@@ -25,6 +69,10 @@ impl Read for Stream {
   fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
     match *self {
       Stream::Tcp(ref mut s) => s.read(buf),
+      #[cfg(feature = "tls")]
+      Stream::Tls(ref s) => s.lock().unwrap().read(buf),
+      #[cfg(test)]
+      Stream::Mock(ref mut s) => s.read(buf),
     }
   }
 }
@@ -33,12 +81,30 @@ impl Write for Stream {
   fn write(&mut self, buf: &[u8]) -> Result<usize> {
     match *self {
       Stream::Tcp(ref mut s) => s.write(buf),
+      #[cfg(feature = "tls")]
+      Stream::Tls(ref s) => s.lock().unwrap().write(buf),
+      #[cfg(test)]
+      Stream::Mock(ref mut s) => s.write(buf),
+    }
+  }
+
+  fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<usize> {
+    match *self {
+      Stream::Tcp(ref mut s) => s.write_vectored(bufs),
+      #[cfg(feature = "tls")]
+      Stream::Tls(ref s) => s.lock().unwrap().write_vectored(bufs),
+      #[cfg(test)]
+      Stream::Mock(ref mut s) => s.write_vectored(bufs),
     }
   }
 
   fn flush(&mut self) -> Result<()> {
     match *self {
       Stream::Tcp(ref mut s) => s.flush(),
+      #[cfg(feature = "tls")]
+      Stream::Tls(ref s) => s.lock().unwrap().flush(),
+      #[cfg(test)]
+      Stream::Mock(ref mut s) => s.flush(),
     }
   }
 }
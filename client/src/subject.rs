@@ -0,0 +1,81 @@
+//! Subject validation.
+//!
+//! Subjects are made of non-empty tokens separated by `.`. Subscriptions may
+//! use the wildcards `*`, matching a single token, and `>`, matching one or
+//! more trailing tokens. Wildcards must be whole tokens and `>` must come
+//! last. Published subjects and reply subjects cannot contain wildcards.
+
+/// Check `subject`, returning the reason why it is invalid.
+///
+/// ```
+/// use client::subject;
+///
+/// assert!(subject::validate("time.*.east", true).is_ok());
+/// assert!(subject::validate("time.*.east", false).is_err());
+/// assert!(subject::validate("time..east", true).is_err());
+/// ```
+pub fn validate(subject: &str, allow_wildcards: bool) -> Result<(), &'static str> {
+  if subject.is_empty() {
+    return Err("Subject can't be empty");
+  }
+  if subject.chars().any(|c| c.is_whitespace() || c.is_control()) {
+    return Err("Subject can't contain whitespace or control characters");
+  }
+  let mut tokens = subject.split('.').peekable();
+  while let Some(token) = tokens.next() {
+    match token {
+      "" => return Err("Subject can't contain empty tokens"),
+      "*" | ">" if !allow_wildcards => return Err("Subject can't contain wildcards"),
+      ">" if tokens.peek().is_some() => return Err("Wildcard `>` must be the last token"),
+      "*" | ">" => {}
+      _ if token.contains(['*', '>']) => return Err("Wildcards must be whole tokens"),
+      _ => {}
+    }
+  }
+  Ok(())
+}
+
+/// Whether `subject` contains wildcard tokens.
+pub fn has_wildcards(subject: &str) -> bool {
+  subject.split('.').any(|token| token == "*" || token == ">")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_validate() {
+    for subject in &["foo", "time.us.east", "_INBOX.abc.1", "foo-bar.baz_1"] {
+      assert!(validate(subject, false).is_ok(), "{}", subject);
+      assert!(validate(subject, true).is_ok(), "{}", subject);
+    }
+    for subject in &["*", ">", "foo.*", "foo.>", "foo.*.bar.>"] {
+      assert!(validate(subject, true).is_ok(), "{}", subject);
+      assert!(validate(subject, false).is_err(), "{}", subject);
+    }
+    for subject in &[
+      "",
+      ".",
+      "foo.",
+      ".foo",
+      "foo..bar",
+      "foo bar",
+      "foo\tbar",
+      "foo\r\n",
+      "foo.>.bar",
+      "foo*",
+      "foo.b>",
+      "\u{7f}",
+    ] {
+      assert!(validate(subject, true).is_err(), "{:?}", subject);
+    }
+  }
+
+  #[test]
+  fn test_has_wildcards() {
+    assert!(has_wildcards("foo.*"));
+    assert!(has_wildcards(">"));
+    assert!(!has_wildcards("foo.bar"));
+  }
+}
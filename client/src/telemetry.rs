@@ -0,0 +1,59 @@
+//! Client metrics, exported through the `metrics` facade when the `metrics`
+//! feature is enabled. Without it the functions do nothing.
+//!
+//! | Name                                      | Type      |
+//! |-------------------------------------------|-----------|
+//! | `nats_client_publish_duration_seconds`    | histogram |
+//! | `nats_client_request_duration_seconds`    | histogram |
+//! | `nats_client_messages_sent_total`         | counter   |
+//! | `nats_client_messages_received_total`     | counter   |
+//! | `nats_client_dropped_messages_total`      | counter   |
+//! | `nats_client_reconnects_total`            | counter   |
+
+pub(crate) use imp::*;
+
+#[cfg(feature = "metrics")]
+mod imp {
+  use metrics::{counter, histogram};
+  use std::time::Duration;
+
+  /// A message was handed to the connection after `elapsed`.
+  pub(crate) fn published(elapsed: Duration) {
+    counter!("nats_client_messages_sent_total").increment(1);
+    histogram!("nats_client_publish_duration_seconds").record(elapsed);
+  }
+
+  /// A reply was received `elapsed` after the request was sent.
+  pub(crate) fn replied(elapsed: Duration) {
+    histogram!("nats_client_request_duration_seconds").record(elapsed);
+  }
+
+  pub(crate) fn received() {
+    counter!("nats_client_messages_received_total").increment(1);
+  }
+
+  /// A message was dropped because its subscription exceeded its pending
+  /// limits.
+  pub(crate) fn dropped() {
+    counter!("nats_client_dropped_messages_total").increment(1);
+  }
+
+  pub(crate) fn reconnected() {
+    counter!("nats_client_reconnects_total").increment(1);
+  }
+}
+
+#[cfg(not(feature = "metrics"))]
+mod imp {
+  use std::time::Duration;
+
+  pub(crate) fn published(_elapsed: Duration) {}
+
+  pub(crate) fn replied(_elapsed: Duration) {}
+
+  pub(crate) fn received() {}
+
+  pub(crate) fn dropped() {}
+
+  pub(crate) fn reconnected() {}
+}
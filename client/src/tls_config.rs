@@ -0,0 +1,93 @@
+use std::path::PathBuf;
+
+/// TLS settings used when the connection to the server is secured.
+///
+/// The webpki root certificates are always trusted; additional root CAs and a
+/// client certificate for mutual TLS can be configured here.
+#[derive(Clone, Debug, Default)]
+pub struct TlsConfig {
+  root_certificates: Vec<PathBuf>,
+  client_certificate: Option<(PathBuf, PathBuf)>,
+}
+
+impl TlsConfig {
+  pub fn new() -> TlsConfig {
+    TlsConfig::default()
+  }
+
+  /// Trust the PEM encoded CA certificates found in `path`.
+  pub fn add_root_certificate<P: Into<PathBuf>>(mut self, path: P) -> TlsConfig {
+    self.root_certificates.push(path.into());
+    self
+  }
+
+  /// Present the PEM encoded certificate chain and private key to the server.
+  pub fn client_certificate<P: Into<PathBuf>>(mut self, cert: P, key: P) -> TlsConfig {
+    self.client_certificate = Some((cert.into(), key.into()));
+    self
+  }
+}
+
+#[cfg(feature = "tls")]
+mod rustls_config {
+  use super::TlsConfig;
+  use crate::errors::{ErrorKind::*, *};
+  use rustls::{Certificate, ClientConfig, OwnedTrustAnchor, PrivateKey, RootCertStore};
+  use rustls_pemfile::Item;
+  use std::{fs::File, io::BufReader, path::Path, sync::Arc};
+
+  impl TlsConfig {
+    pub(crate) fn client_config(&self) -> Result<Arc<ClientConfig>, NatsClientError> {
+      let mut root_store = RootCertStore::empty();
+      root_store.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(
+          ta.subject,
+          ta.spki,
+          ta.name_constraints,
+        )
+      }));
+      for path in &self.root_certificates {
+        for cert in load_certs(path)? {
+          root_store.add(&cert).map_err(tls_error)?;
+        }
+      }
+      let builder = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(root_store);
+      let config = match self.client_certificate {
+        Some((ref cert, ref key)) => builder
+          .with_client_auth_cert(load_certs(cert)?, load_key(key)?)
+          .map_err(tls_error)?,
+        None => builder.with_no_client_auth(),
+      };
+      Ok(Arc::new(config))
+    }
+  }
+
+  pub(crate) fn tls_error(e: rustls::Error) -> NatsClientError {
+    NatsClientError::from((TlsError, "TLS error", e.to_string()))
+  }
+
+  fn load_certs(path: &Path) -> Result<Vec<Certificate>, NatsClientError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certs = rustls_pemfile::certs(&mut reader)?;
+    Ok(certs.into_iter().map(Certificate).collect())
+  }
+
+  fn load_key(path: &Path) -> Result<PrivateKey, NatsClientError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    while let Some(item) = rustls_pemfile::read_one(&mut reader)? {
+      match item {
+        Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key) => return Ok(PrivateKey(key)),
+        _ => {}
+      }
+    }
+    Err(NatsClientError::from((
+      InvalidClientConfig,
+      "No private key found in file",
+    )))
+  }
+}
+
+#[cfg(feature = "tls")]
+pub(crate) use rustls_config::tls_error;
@@ -0,0 +1,136 @@
+use crate::client::{Channel, Client, Subscription};
+use crate::errors::{ErrorKind::*, *};
+use serde::{de::DeserializeOwned, Serialize};
+use std::marker::PhantomData;
+
+/// Subscription whose payloads are decoded from JSON into `T`.
+///
+/// As an iterator, it yields an error for each payload failing to decode.
+/// An error of the connection is yielded too, and ends the iteration.
+#[derive(Debug)]
+pub struct TypedSubscription<'a, T> {
+  client: &'a mut Client,
+  subscription: Subscription,
+  // Whether the connection failed, ending the iteration.
+  failed: bool,
+  _marker: PhantomData<T>,
+}
+
+impl<'a, T: DeserializeOwned> TypedSubscription<'a, T> {
+  pub fn channel(&self) -> Channel {
+    self.subscription.channel()
+  }
+
+  /// Wait for the next message and decode it. A payload that is not a valid
+  /// `T` yields an error of kind `DecodeError`.
+  pub fn next_msg(&mut self) -> Result<T, NatsClientError> {
+    let event = self.client.next_event(self.subscription.channel())?;
+    decode(&event.msg)
+  }
+}
+
+impl<'a, T: DeserializeOwned> Iterator for TypedSubscription<'a, T> {
+  type Item = Result<T, NatsClientError>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.failed {
+      return None;
+    }
+    match self.client.next_event(self.subscription.channel()) {
+      Ok(event) => Some(decode(&event.msg)),
+      Err(e) => {
+        self.failed = true;
+        Some(Err(e))
+      }
+    }
+  }
+}
+
+fn decode<T: DeserializeOwned>(msg: &[u8]) -> Result<T, NatsClientError> {
+  serde_json::from_slice(msg)
+    .map_err(|e| NatsClientError::from((DecodeError, "Failed to decode message", e.to_string())))
+}
+
+impl Client {
+  /// Publish `value` serialized as JSON.
+  pub fn publish_json<T: Serialize>(
+    &mut self,
+    subject: &str,
+    value: &T,
+    inbox: Option<&str>,
+  ) -> Result<(), NatsClientError> {
+    let msg = serde_json::to_vec(value)
+      .map_err(|e| NatsClientError::from((TypeError, "Failed to encode message", e.to_string())))?;
+    self.publish(subject, &msg, inbox)
+  }
+
+  /// Subscribe to `subject`, decoding every payload from JSON into `T`.
+  pub fn subscribe_json<T: DeserializeOwned>(
+    &mut self,
+    subject: &str,
+    queue: Option<&str>,
+  ) -> Result<TypedSubscription<'_, T>, NatsClientError> {
+    let subscription = self.subscribe(subject, queue)?;
+    Ok(TypedSubscription {
+      client: self,
+      subscription,
+      failed: false,
+      _marker: PhantomData,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::mock::MockServer;
+  use crate::options::ConnectOptions;
+  use serde::Deserialize;
+
+  #[derive(Debug, Deserialize, PartialEq)]
+  struct Order {
+    id: u32,
+    item: String,
+  }
+
+  #[test]
+  fn test_decode() {
+    let order: Order = decode(br#"{"id":1,"item":"book"}"#).unwrap();
+    assert_eq!(
+      order,
+      Order {
+        id: 1,
+        item: "book".to_owned()
+      }
+    );
+    assert!(decode::<Order>(b"not json").is_err());
+  }
+
+  #[test]
+  fn test_iterator_errors() {
+    // Connecting again fails once the first connection is closed.
+    let server = MockServer::new(|mut conn| {
+      if conn.index > 0 {
+        return;
+      }
+      conn.handshake("{}");
+      let sub = conn.expect("SUB");
+      conn.ack();
+      let orders = [r#"{"id":1,"item":"book"}"#, "not json"];
+      for order in &orders {
+        conn.send(&format!("MSG orders {} {}\r\n{}\r\n", sub[1], order.len(), order));
+      }
+    });
+    let options = ConnectOptions::new().max_reconnects(0);
+    let mut nc = Client::with_options("nats://localhost", options).unwrap();
+    nc.mock_server = Some(server);
+    let mut orders = nc.subscribe_json::<Order>("orders", None).unwrap();
+    assert_eq!(orders.next().unwrap().unwrap().id, 1);
+    let err = orders.next().unwrap().unwrap_err();
+    assert_eq!(err.kind(), DecodeError);
+    // The connection error is yielded rather than ending the iteration
+    // silently.
+    assert!(orders.next().unwrap().is_err());
+    assert!(orders.next().is_none());
+  }
+}
@@ -0,0 +1,52 @@
+//! Clients talking to the server of the workspace, started on an ephemeral
+//! port for each test.
+
+use client::ConnectOptions;
+use server::config::ServerConfig;
+use server::test_server::TestServer;
+use std::thread;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[test]
+fn test_pub_sub() {
+  let server = TestServer::with_config(ServerConfig {
+    stream_threshold: Some(64 * 1024),
+    ..ServerConfig::default()
+  })
+  .unwrap();
+  let mut nc = ConnectOptions::new().connect(server.url()).unwrap();
+  let sub = nc.subscribe("greetings.*", None).unwrap();
+  nc.publish("greetings.en", "hello", None).unwrap();
+  // Large enough for the server to read it in chunks.
+  let large = vec![b'x'; 100_000];
+  nc.publish("greetings.fr", &large, None).unwrap();
+  nc.flush().unwrap();
+
+  let event = nc.next_msg(sub.channel(), TIMEOUT).unwrap().unwrap();
+  assert_eq!(event.subject, "greetings.en");
+  assert_eq!(&event.msg[..], b"hello");
+  let event = nc.next_msg(sub.channel(), TIMEOUT).unwrap().unwrap();
+  assert_eq!(event.subject, "greetings.fr");
+  assert_eq!(event.msg, large);
+}
+
+#[test]
+fn test_request() {
+  let server = TestServer::start().unwrap();
+  let mut responder = ConnectOptions::new().connect(server.url()).unwrap();
+  let sub = responder.subscribe("echo", None).unwrap();
+  // The subscription is registered once the server answered the PING.
+  responder.rtt().unwrap();
+  let handle = thread::spawn(move || {
+    let event = responder.next_msg(sub.channel(), TIMEOUT).unwrap().unwrap();
+    responder.publish(event.inbox.as_deref().unwrap(), &event.msg, None).unwrap();
+    responder.flush().unwrap();
+  });
+
+  let mut nc = ConnectOptions::new().connect(server.url()).unwrap();
+  let reply = nc.request_timeout("echo", "ping", TIMEOUT).unwrap();
+  assert_eq!(&reply.msg[..], b"ping");
+  handle.join().unwrap();
+}
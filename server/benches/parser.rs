@@ -0,0 +1,107 @@
+//! Throughput of the parser on typical client streams, compared with a naive
+//! parser splitting the input in lines.
+
+use bytes::BytesMut;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use server::parser::{Parser, ParserOptions};
+
+/// Typical TCP segment size, for the fragmented reads.
+const READ_SIZE: usize = 1460;
+
+fn pubs(count: usize, size: usize) -> Vec<u8> {
+    let mut buf = Vec::new();
+    let payload = vec![b'x'; size];
+    for _ in 0..count {
+        buf.extend_from_slice(format!("PUB foo.bar _INBOX.1 {}\r\n", size).as_bytes());
+        buf.extend_from_slice(&payload);
+        buf.extend_from_slice(b"\r\n");
+    }
+    buf
+}
+
+fn subs(count: usize) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for sid in 0..count {
+        buf.extend_from_slice(format!("SUB foo.{}.* workers {}\r\n", sid, sid).as_bytes());
+    }
+    buf
+}
+
+fn options() -> ParserOptions {
+    ParserOptions {
+        max_payload: 2 * 1024 * 1024,
+        ..ParserOptions::default()
+    }
+}
+
+/// Parse `reads` with `Parser::parse_all()`, returning the number of
+/// operations.
+fn parse_all(reads: &[&[u8]]) -> usize {
+    let mut p = Parser::new(options());
+    let mut count = 0;
+    for read in reads {
+        count += p.parse_all(read, |res| drop(black_box(res))).unwrap();
+    }
+    count
+}
+
+/// Parse `reads` with `Parser::parse_bytes()`, returning the number of
+/// operations.
+fn parse_bytes(reads: &[&[u8]]) -> usize {
+    let mut p = Parser::new(options());
+    let mut input = BytesMut::new();
+    let mut count = 0;
+    for read in reads {
+        input.extend_from_slice(read);
+        while let Some(frame) = p.parse_bytes(&mut input).unwrap() {
+            black_box(frame);
+            count += 1;
+        }
+    }
+    count
+}
+
+/// Baseline splitting a complete input on CRLF, the payload of a PUB being
+/// the following line.
+fn parse_lines(buf: &[u8]) -> usize {
+    let s = std::str::from_utf8(buf).unwrap();
+    let mut lines = s.split("\r\n");
+    let mut count = 0;
+    while let Some(line) = lines.next() {
+        if line.is_empty() {
+            continue;
+        }
+        let args: Vec<&str> = line.split_whitespace().collect();
+        if args[0].eq_ignore_ascii_case("PUB") {
+            black_box(lines.next());
+        }
+        black_box(args);
+        count += 1;
+    }
+    count
+}
+
+fn bench_stream(c: &mut Criterion, name: &str, buf: &[u8], ops: usize) {
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Bytes(buf.len() as u64));
+    let fragmented: Vec<&[u8]> = buf.chunks(READ_SIZE).collect();
+    for (reads, input) in &[("whole", vec![buf]), ("fragmented", fragmented)] {
+        group.bench_with_input(BenchmarkId::new("parse_all", reads), input, |b, input| {
+            b.iter(|| assert_eq!(parse_all(input), ops))
+        });
+        group.bench_with_input(BenchmarkId::new("parse_bytes", reads), input, |b, input| {
+            b.iter(|| assert_eq!(parse_bytes(input), ops))
+        });
+    }
+    group.bench_function("lines", |b| b.iter(|| assert_eq!(parse_lines(buf), ops)));
+    group.finish();
+}
+
+fn bench_parser(c: &mut Criterion) {
+    bench_stream(c, "small_pubs", &pubs(1000, 16), 1000);
+    bench_stream(c, "large_pubs", &pubs(4, 1024 * 1024), 4);
+    bench_stream(c, "sub_storm", &subs(1000), 1000);
+}
+
+criterion_group!(benches, bench_parser);
+criterion_main!(benches);
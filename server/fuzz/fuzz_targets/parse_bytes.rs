@@ -0,0 +1,69 @@
+//! Arbitrary bytes, split in arbitrary reads, must be parsed or rejected
+//! without panicking nor looping, `parse()` and `parse_bytes()` finding the
+//! same operations.
+
+#![no_main]
+
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+use server::parser::{ConnectionKind, Parser, ParserOptions};
+
+fuzz_target!(|input: (bool, bool, Vec<u8>, Vec<u8>)| {
+    let (route, pedantic, splits, data) = input;
+    let opts = ParserOptions {
+        kind: if route {
+            ConnectionKind::Route
+        } else {
+            ConnectionKind::Client
+        },
+        pedantic,
+        max_control_line: 256,
+        max_payload: 4096,
+        stream_threshold: Some(64),
+    };
+
+    let mut p = Parser::new(opts.clone());
+    let mut parsed = Ok(0);
+    for chunk in chunks(&data, &splits) {
+        // The state of the parser is undefined after an error.
+        match p.parse_all(chunk, |_| {}) {
+            Ok(count) => parsed = parsed.map(|n| n + count),
+            Err(e) => {
+                parsed = Err(e.error_code);
+                break;
+            }
+        }
+    }
+
+    let mut p = Parser::new(opts);
+    let mut input = BytesMut::new();
+    let mut frames = Ok(0);
+    'reads: for chunk in chunks(&data, &splits) {
+        input.extend_from_slice(chunk);
+        loop {
+            match p.parse_bytes(&mut input) {
+                Ok(Some(_)) => frames = frames.map(|n| n + 1),
+                Ok(None) => break,
+                Err(e) => {
+                    frames = Err(e.error_code);
+                    break 'reads;
+                }
+            }
+        }
+    }
+    assert_eq!(parsed, frames);
+});
+
+/// Split `data` in reads of the sizes given by `splits`, the last one taking
+/// what is left.
+fn chunks<'a>(data: &'a [u8], splits: &[u8]) -> Vec<&'a [u8]> {
+    let mut chunks = Vec::new();
+    let mut rest = data;
+    for size in splits {
+        let (chunk, tail) = rest.split_at((*size as usize).min(rest.len()));
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks.push(rest);
+    chunks
+}
@@ -0,0 +1,108 @@
+//! A valid stream of operations must give the same results however it is
+//! split across reads.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use server::parser::{Parser, ParserOptions};
+
+#[derive(Debug, Arbitrary)]
+enum Op {
+    Pub {
+        subject: u8,
+        reply: Option<u8>,
+        payload: Vec<u8>,
+    },
+    HPub {
+        subject: u8,
+        value: u8,
+        payload: Vec<u8>,
+    },
+    Sub {
+        subject: u8,
+        sid: u8,
+    },
+    Unsub {
+        sid: u8,
+        max_msgs: Option<u8>,
+    },
+    Ping,
+    Pong,
+    Connect {
+        verbose: bool,
+    },
+}
+
+impl Op {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Op::Pub {
+                subject,
+                reply,
+                payload,
+            } => {
+                let reply = reply.map(|r| format!(" _INBOX.{}", r)).unwrap_or_default();
+                let line = format!("PUB foo.{}{} {}\r\n", subject, reply, payload.len());
+                out.extend_from_slice(line.as_bytes());
+                out.extend_from_slice(payload);
+                out.extend_from_slice(b"\r\n");
+            }
+            Op::HPub {
+                subject,
+                value,
+                payload,
+            } => {
+                let headers = format!("NATS/1.0\r\nKey: {}\r\n\r\n", value);
+                let total = headers.len() + payload.len();
+                let line = format!("HPUB foo.{} {} {}\r\n", subject, headers.len(), total);
+                out.extend_from_slice(line.as_bytes());
+                out.extend_from_slice(headers.as_bytes());
+                out.extend_from_slice(payload);
+                out.extend_from_slice(b"\r\n");
+            }
+            Op::Sub { subject, sid } => {
+                out.extend_from_slice(format!("SUB foo.{} {}\r\n", subject, sid).as_bytes())
+            }
+            Op::Unsub { sid, max_msgs } => {
+                let max = max_msgs.map(|m| format!(" {}", m)).unwrap_or_default();
+                out.extend_from_slice(format!("UNSUB {}{}\r\n", sid, max).as_bytes())
+            }
+            Op::Ping => out.extend_from_slice(b"PING\r\n"),
+            Op::Pong => out.extend_from_slice(b"PONG\r\n"),
+            Op::Connect { verbose } => {
+                out.extend_from_slice(format!("CONNECT {{\"verbose\":{}}}\r\n", verbose).as_bytes())
+            }
+        }
+    }
+}
+
+fn parse(reads: &[&[u8]]) -> Vec<String> {
+    let mut p = Parser::new(ParserOptions::default());
+    let mut ops = Vec::new();
+    for read in reads {
+        p.parse_all(read, |res| ops.push(format!("{:?}", res)))
+            .expect("valid stream rejected");
+    }
+    ops
+}
+
+fuzz_target!(|input: (Vec<Op>, Vec<u16>)| {
+    let (ops, splits) = input;
+    let mut stream = Vec::new();
+    for op in &ops {
+        op.encode(&mut stream);
+    }
+    let whole = parse(&[&stream]);
+    assert_eq!(whole.len(), ops.len());
+
+    let mut reads = Vec::new();
+    let mut rest = &stream[..];
+    for size in splits {
+        let (read, tail) = rest.split_at((size as usize).min(rest.len()));
+        reads.push(read);
+        rest = tail;
+    }
+    reads.push(rest);
+    assert_eq!(parse(&reads), whole);
+});
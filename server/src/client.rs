@@ -0,0 +1,244 @@
+//! State of a client connection, shared by the thread reading from it and the
+//! threads delivering messages to it.
+
+use crate::net::Outbound;
+use crate::parser::ConnectInfo;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Subscription of a connection, also indexed by the sublist of the server.
+pub struct Subscription {
+    pub client: Arc<Client>,
+    pub sid: String,
+    pub subject: String,
+    pub queue: Option<String>,
+    /// Number of messages after which the subscription ends, 0 for none.
+    max_msgs: AtomicUsize,
+    delivered: AtomicUsize,
+}
+
+impl Subscription {
+    pub fn new(client: Arc<Client>, sid: &str, subject: &str, queue: Option<&str>) -> Self {
+        Self {
+            client,
+            sid: sid.to_owned(),
+            subject: subject.to_owned(),
+            queue: queue.map(str::to_owned),
+            max_msgs: AtomicUsize::new(0),
+            delivered: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn max_msgs(&self) -> Option<usize> {
+        Some(self.max_msgs.load(Ordering::Relaxed)).filter(|&n| n > 0)
+    }
+
+    pub fn set_max_msgs(&self, max_msgs: usize) {
+        self.max_msgs.store(max_msgs, Ordering::Relaxed);
+    }
+
+    /// Number of messages delivered.
+    pub fn delivered(&self) -> usize {
+        self.delivered.load(Ordering::Relaxed)
+    }
+
+    /// Count a message delivered, returning whether it is the last one, or
+    /// `None` if `max_msgs` were delivered already.
+    pub fn record_delivery(&self) -> Option<bool> {
+        let max_msgs = self.max_msgs();
+        self.delivered
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| match max_msgs {
+                Some(max_msgs) if n >= max_msgs => None,
+                _ => Some(n + 1),
+            })
+            .ok()
+            .map(|n| max_msgs == Some(n + 1))
+    }
+}
+
+/// Subscriptions are identified by their connection and sid.
+impl PartialEq for Subscription {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.client, &other.client) && self.sid == other.sid
+    }
+}
+
+impl fmt::Debug for Subscription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscription")
+            .field("cid", &self.client.cid())
+            .field("sid", &self.sid)
+            .field("subject", &self.subject)
+            .field("queue", &self.queue)
+            .field("max_msgs", &self.max_msgs())
+            .finish()
+    }
+}
+
+/// Messages and bytes received from and sent to a connection.
+#[derive(Debug, Default)]
+pub struct Stats {
+    in_msgs: AtomicU64,
+    in_bytes: AtomicU64,
+    out_msgs: AtomicU64,
+    out_bytes: AtomicU64,
+}
+
+impl Stats {
+    /// Count a message of `bytes` published by the connection.
+    pub fn record_in(&self, bytes: usize) {
+        self.in_msgs.fetch_add(1, Ordering::Relaxed);
+        self.add_in_bytes(bytes);
+    }
+
+    /// Count `bytes` more of the message being published, when streamed.
+    pub fn add_in_bytes(&self, bytes: usize) {
+        self.in_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Count a message of `bytes` delivered to the connection.
+    pub fn record_out(&self, bytes: usize) {
+        self.out_msgs.fetch_add(1, Ordering::Relaxed);
+        self.out_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn in_msgs(&self) -> u64 {
+        self.in_msgs.load(Ordering::Relaxed)
+    }
+
+    pub fn in_bytes(&self) -> u64 {
+        self.in_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn out_msgs(&self) -> u64 {
+        self.out_msgs.load(Ordering::Relaxed)
+    }
+
+    pub fn out_bytes(&self) -> u64 {
+        self.out_bytes.load(Ordering::Relaxed)
+    }
+}
+
+/// A connection of a client.
+pub struct Client {
+    /// Identifier of the connection, unique to the server.
+    cid: u64,
+    addr: SocketAddr,
+    /// Options sent in CONNECT.
+    opts: Mutex<ConnectInfo>,
+    /// Subscriptions by sid.
+    subs: Mutex<HashMap<String, Arc<Subscription>>>,
+    stats: Stats,
+    outbound: Outbound,
+    /// Whether data was read from the connection since the last PING
+    /// check.
+    active: AtomicBool,
+    /// PINGs sent and not answered yet.
+    pings_out: AtomicUsize,
+}
+
+impl Client {
+    pub fn new(cid: u64, addr: SocketAddr) -> Self {
+        Self {
+            cid,
+            addr,
+            opts: Mutex::new(ConnectInfo::default()),
+            subs: Mutex::new(HashMap::new()),
+            stats: Stats::default(),
+            outbound: Outbound::default(),
+            active: AtomicBool::new(false),
+            pings_out: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn cid(&self) -> u64 {
+        self.cid
+    }
+
+    /// Address of the client.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn opts(&self) -> ConnectInfo {
+        self.opts.lock().unwrap().clone()
+    }
+
+    pub fn set_opts(&self, opts: ConnectInfo) {
+        *self.opts.lock().unwrap() = opts;
+    }
+
+    /// Subscriptions by sid.
+    pub fn subs(&self) -> MutexGuard<'_, HashMap<String, Arc<Subscription>>> {
+        self.subs.lock().unwrap()
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Buffer of the data sent to the client.
+    pub fn outbound(&self) -> &Outbound {
+        &self.outbound
+    }
+
+    /// Record that data was read from the connection.
+    pub fn record_activity(&self) {
+        self.active.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether data was read from the connection since the last call.
+    pub fn take_activity(&self) -> bool {
+        self.active.swap(false, Ordering::Relaxed)
+    }
+
+    pub fn pings_out(&self) -> usize {
+        self.pings_out.load(Ordering::Relaxed)
+    }
+
+    /// Count a PING sent to the client.
+    pub fn record_ping(&self) {
+        self.pings_out.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that the client answered the PINGs sent.
+    pub fn record_pong(&self) {
+        self.pings_out.store(0, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let client = Client::new(1, "127.0.0.1:4222".parse().unwrap());
+        client.stats().record_in(5);
+        client.stats().record_in(0);
+        client.stats().add_in_bytes(3);
+        client.stats().record_out(7);
+        let stats = client.stats();
+        assert_eq!((stats.in_msgs(), stats.in_bytes()), (2, 8));
+        assert_eq!((stats.out_msgs(), stats.out_bytes()), (1, 7));
+    }
+
+    #[test]
+    fn test_max_msgs() {
+        let client = Arc::new(Client::new(1, "127.0.0.1:4222".parse().unwrap()));
+        let sub = Subscription::new(client.clone(), "1", "foo", None);
+        assert_eq!(sub.record_delivery(), Some(false));
+        sub.set_max_msgs(3);
+        assert_eq!(sub.record_delivery(), Some(false));
+        assert_eq!(sub.record_delivery(), Some(true));
+        assert_eq!(sub.record_delivery(), None);
+        assert_eq!(sub.delivered(), 3);
+
+        assert_eq!(sub, Subscription::new(client, "1", "bar", None));
+        let other = Arc::new(Client::new(2, "127.0.0.1:4222".parse().unwrap()));
+        assert_ne!(sub, Subscription::new(other, "1", "foo", None));
+    }
+}
@@ -0,0 +1,64 @@
+use crate::parser::{ConnectionKind, ParserOptions};
+use std::time::Duration;
+
+/// Default address the server listens on.
+pub const DEFAULT_HOST: &str = "0.0.0.0";
+pub const DEFAULT_PORT: u16 = 4222;
+/// Default maximum length of the control line of an operation, in bytes.
+pub const DEFAULT_MAX_CONTROL_LINE: usize = 4096;
+/// Default maximum payload size, in bytes.
+pub const DEFAULT_MAX_PAYLOAD: usize = 1024 * 1024;
+/// Default interval between the PINGs sent to idle clients.
+pub const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(2 * 60);
+/// Default number of PINGs left unanswered before a client is disconnected.
+pub const DEFAULT_MAX_PINGS_OUT: usize = 2;
+
+/// Server configuration.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    /// Longest control line accepted from clients, subjects and reply
+    /// subjects included.
+    pub max_control_line: usize,
+    /// Largest message payload accepted from clients, advertised in INFO.
+    pub max_payload: usize,
+    /// Payloads larger than this are read in chunks as they arrive rather
+    /// than into a buffer of their size, and delivered once complete. Off by
+    /// default.
+    pub stream_threshold: Option<usize>,
+    /// Interval between the PINGs sent to the clients which sent nothing
+    /// since the last one.
+    pub ping_interval: Duration,
+    /// Number of PINGs left unanswered after which a client is considered
+    /// gone and disconnected.
+    pub max_pings_out: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: DEFAULT_HOST.to_owned(),
+            port: DEFAULT_PORT,
+            max_control_line: DEFAULT_MAX_CONTROL_LINE,
+            max_payload: DEFAULT_MAX_PAYLOAD,
+            stream_threshold: None,
+            ping_interval: DEFAULT_PING_INTERVAL,
+            max_pings_out: DEFAULT_MAX_PINGS_OUT,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Options of the parser reading the operations of each connection of
+    /// `kind`.
+    pub fn parser_options(&self, kind: ConnectionKind) -> ParserOptions {
+        ParserOptions {
+            kind,
+            pedantic: false,
+            max_control_line: self.max_control_line,
+            max_payload: self.max_payload,
+            stream_threshold: self.stream_threshold,
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use crate::proto::errors::ErrorResponse;
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 
@@ -6,21 +7,62 @@ pub const ERROR_MESSAGE_SIZE_TOO_LARGE: i32 = 2;
 pub const ERROR_INVALID_SUBJECT: i32 = 3;
 pub const ERROR_SUBSCRIBTION_NOT_FOUND: i32 = 4;
 pub const ERROR_CONNECTION_CLOSED: i32 = 5;
+pub const ERROR_MAX_CONTROL_LINE: i32 = 6;
 pub const ERROR_UNKOWN_ERROR: i32 = 1000;
 
+/// Bytes of input kept around the position of a parse error.
+const SNIPPET_LEN: usize = 32;
+
 #[derive(Debug)]
 pub struct NError {
     pub error_code: i32,
+    /// Where the input was rejected, for parse errors.
+    pub context: Option<ParseContext>,
+}
+
+/// Position of a parse error, for diagnostics.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseContext {
+    /// Operation being parsed, `None` when its name was not recognized.
+    pub op: Option<&'static str>,
+    /// Offset of the offending byte in the parsed buffer.
+    pub offset: usize,
+    /// Input around the offending byte.
+    pub snippet: String,
 }
 
 impl NError {
     pub fn new(error_code: i32) -> Self {
-        Self { error_code }
+        Self {
+            error_code,
+            context: None,
+        }
+    }
+
+    /// Record that the error occurred at `offset` in `buf`, while parsing
+    /// `op`.
+    pub fn at(mut self, op: Option<&'static str>, buf: &[u8], offset: usize) -> Self {
+        let start = offset.saturating_sub(SNIPPET_LEN / 2);
+        let end = (start + SNIPPET_LEN).min(buf.len());
+        self.context = Some(ParseContext {
+            op,
+            offset,
+            snippet: String::from_utf8_lossy(&buf[start..end]).into_owned(),
+        });
+        self
+    }
+
+    /// Error sent to the client in `-ERR`.
+    pub fn response(&self) -> ErrorResponse {
+        ErrorResponse::from(self)
     }
 
     pub fn description(&self) -> &'static str {
         match self.error_code {
             ERROR_PARSE => "parse error",
+            ERROR_MESSAGE_SIZE_TOO_LARGE => "maximum payload violation",
+            ERROR_INVALID_SUBJECT => "invalid subject",
+            ERROR_MAX_CONTROL_LINE => "maximum control line exceeded",
             _ => "unknown error",
         }
     }
@@ -29,7 +71,17 @@ impl NError {
 impl Error for NError {}
 impl Display for NError {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
-        write!(f, "NEror[{}, {}]", self.error_code, self.description())
+        write!(f, "NEror[{}, {}]", self.error_code, self.description())?;
+        if let Some(context) = &self.context {
+            write!(
+                f,
+                " in {} at byte {}: {:?}",
+                context.op.unwrap_or("unknown operation"),
+                context.offset,
+                context.snippet
+            )?;
+        }
+        Ok(())
     }
 }
 
@@ -41,4 +93,14 @@ mod tests {
         println!("{}", NError::new(ERROR_PARSE));
         // assert!(format!("{}", NError::new(ERROR_PARSE)) == "" );
     }
+
+    #[test]
+    fn test_context() {
+        let e = NError::new(ERROR_PARSE).at(Some("SUB"), b"SUB foo\r\n", 7);
+        assert_eq!(
+            e.to_string(),
+            "NEror[1, parse error] in SUB at byte 7: \"SUB foo\\r\\n\""
+        );
+        assert_eq!(e.response(), ErrorResponse::UnknownProtocolOperation);
+    }
 }
@@ -0,0 +1,89 @@
+//! INFO sent to clients when they connect.
+
+use crate::config::ServerConfig;
+use crate::proto::encode;
+use bytes::BufMut;
+use serde::Serialize;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Version of the client protocol supported, 1 for asynchronous INFO.
+const PROTO: i32 = 1;
+
+/// Description of the server, sent in INFO.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ServerInfo {
+    pub server_id: String,
+    pub version: String,
+    pub host: String,
+    pub port: u16,
+    /// Largest payload accepted, headers included.
+    pub max_payload: usize,
+    pub proto: i32,
+    /// Whether HPUB and HMSG are supported.
+    pub headers: bool,
+    pub auth_required: bool,
+    pub tls_required: bool,
+    /// Other servers of the cluster the clients may connect to.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub connect_urls: Vec<String>,
+    /// CID of the connection the INFO is sent to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<u64>,
+}
+
+impl ServerInfo {
+    pub fn new(config: &ServerConfig) -> Self {
+        Self {
+            server_id: server_id(),
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            host: config.host.clone(),
+            port: config.port,
+            max_payload: config.max_payload,
+            proto: PROTO,
+            headers: true,
+            auth_required: false,
+            tls_required: false,
+            connect_urls: Vec::new(),
+            client_id: None,
+        }
+    }
+
+    /// Append `INFO {...}` to `buf`.
+    pub fn encode<B: BufMut>(&self, buf: &mut B) {
+        // Serializing strings and numbers cannot fail.
+        let _ = encode::info(buf, self);
+    }
+}
+
+/// Identifier unique to this run of the server.
+fn server_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos());
+    format!("N{:X}{:X}", nanos, process::id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let info = ServerInfo::new(&ServerConfig::default());
+        let mut buf = Vec::new();
+        info.encode(&mut buf);
+        let line = String::from_utf8(buf).unwrap();
+        let json = line
+            .strip_prefix("INFO ")
+            .and_then(|l| l.strip_suffix("\r\n"))
+            .unwrap();
+        let value: serde_json::Value = serde_json::from_str(json).unwrap();
+        assert_eq!(value["server_id"], info.server_id.as_str());
+        assert_eq!(value["port"], 4222);
+        assert_eq!(value["max_payload"], 1024 * 1024);
+        assert_eq!(value["headers"], true);
+        assert!(value.get("connect_urls").is_none());
+        assert!(value.get("client_id").is_none());
+    }
+}
@@ -0,0 +1,14 @@
+#[macro_use]
+mod macros;
+
+pub mod client;
+pub mod config;
+pub mod error;
+pub mod info;
+pub mod net;
+pub mod parser;
+pub mod proto;
+pub mod subject;
+pub mod sublist;
+#[cfg(any(test, feature = "test-server"))]
+pub mod test_server;
@@ -0,0 +1,17 @@
+//! Logging macros forwarding to the `log` crate when the `logging` feature is
+//! enabled. Otherwise they compile to nothing, only type-checking their
+//! arguments.
+
+#[cfg(feature = "logging")]
+macro_rules! trace {
+    ($($arg:tt)*) => { log::trace!($($arg)*) };
+}
+
+#[cfg(not(feature = "logging"))]
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = format_args!($($arg)*);
+        }
+    };
+}
@@ -1,6 +1,35 @@
-mod error;
-mod parser;
+use server::config::ServerConfig;
+use server::net::Server;
+use std::process;
+
+const USAGE: &str = "Usage: server [-a <host>] [-p <port>]";
 
 fn main() {
-    println!("Hello, world!");
+    let mut config = ServerConfig::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("-a" | "--addr", Some(host)) => config.host = host,
+            ("-p" | "--port", Some(port)) => match port.parse() {
+                Ok(port) => config.port = port,
+                Err(_) => exit(&format!("Invalid port: {}", port)),
+            },
+            _ => exit(USAGE),
+        }
+    }
+    let server = match Server::bind(config) {
+        Ok(server) => server,
+        Err(e) => exit(&format!("Cannot listen: {}", e)),
+    };
+    if let Ok(addr) = server.local_addr() {
+        println!("Listening on {}", addr);
+    }
+    if let Err(e) = server.run() {
+        exit(&format!("Server failed: {}", e));
+    }
+}
+
+fn exit(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
 }
@@ -0,0 +1,929 @@
+//! Listener accepting client connections, each served by a reader thread
+//! parsing its operations and a writer thread flushing its outbound buffer.
+//!
+//! Payloads are not copied: the frames parsed share the memory they were
+//! read into with the outbound buffers of the recipients. The chunks of a
+//! streamed payload are kept until it is complete, so that a publisher
+//! stalling or leaving halfway never holds nor cuts the recipients short.
+
+use crate::client::{Client, Subscription};
+use crate::config::ServerConfig;
+use crate::info::ServerInfo;
+use crate::parser::{ConnectionKind, Frame, Parser};
+use crate::proto::encode;
+use crate::proto::errors::ErrorResponse;
+use crate::subject;
+use crate::sublist::Sublist;
+use bytes::{Buf, Bytes, BytesMut};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, IoSlice, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread;
+
+/// Size of the reads from a connection.
+const READ_SIZE: usize = 32 * 1024;
+/// Most segments of an outbound buffer written at once.
+const MAX_IOV: usize = 64;
+
+/// State shared by the connections of a server.
+#[derive(Default)]
+struct State {
+    /// Connections by CID.
+    clients: Mutex<HashMap<u64, Arc<Client>>>,
+    sublist: RwLock<Sublist<Arc<Subscription>>>,
+}
+
+impl State {
+    /// Send PING to the clients which sent nothing since the last call,
+    /// disconnecting those which left `max_pings_out` PINGs unanswered.
+    fn ping_clients(&self, max_pings_out: usize) {
+        let clients: Vec<_> = self.clients.lock().unwrap().values().cloned().collect();
+        for client in clients {
+            if client.take_activity() {
+                continue;
+            }
+            let outbound = client.outbound();
+            if client.pings_out() >= max_pings_out {
+                trace!("closing stale cid {}", client.cid());
+                outbound.write_with(|buf| ErrorResponse::StaleConnection.encode(buf));
+                // The writer shuts the socket down, ending the read loop.
+                outbound.close();
+            } else {
+                client.record_ping();
+                outbound.write_with(encode::ping);
+            }
+        }
+    }
+
+    /// Remove `sub` from the sublist and its connection.
+    fn unsubscribe(&self, sub: &Arc<Subscription>) {
+        self.sublist
+            .write()
+            .unwrap()
+            .remove(&sub.subject, sub.queue.as_deref(), sub);
+        let mut subs = sub.client.subs();
+        // The sid may have been reused since.
+        if subs.get(&sub.sid).is_some_and(|s| Arc::ptr_eq(s, sub)) {
+            subs.remove(&sub.sid);
+        }
+    }
+}
+
+pub struct Server {
+    config: ServerConfig,
+    listener: TcpListener,
+    state: Arc<State>,
+    /// CID of the last connection accepted.
+    last_cid: AtomicU64,
+    /// Whether `shutdown()` was called.
+    stopped: AtomicBool,
+}
+
+impl Server {
+    /// Listen on the address of `config`. Port 0 picks a free port, see
+    /// `local_addr()`.
+    pub fn bind(config: ServerConfig) -> io::Result<Self> {
+        if config.ping_interval.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "ping_interval must not be zero",
+            ));
+        }
+        let listener = TcpListener::bind((config.host.as_str(), config.port))?;
+        Ok(Self {
+            config,
+            listener,
+            state: Arc::default(),
+            last_cid: AtomicU64::new(0),
+            stopped: AtomicBool::new(false),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Connections currently open, in the order they were accepted.
+    pub fn clients(&self) -> Vec<Arc<Client>> {
+        let clients = self.state.clients.lock().unwrap();
+        let mut clients: Vec<_> = clients.values().cloned().collect();
+        clients.sort_by_key(|c| c.cid());
+        clients
+    }
+
+    /// Number of subscriptions of all the connections.
+    pub fn num_subscriptions(&self) -> usize {
+        self.state.sublist.read().unwrap().count()
+    }
+
+    /// Accept connections, serving each on its own threads, until
+    /// `shutdown()` is called.
+    pub fn run(&self) -> io::Result<()> {
+        let mut info = ServerInfo::new(&self.config);
+        info.port = self.local_addr()?.port();
+        let state = self.state.clone();
+        let (interval, max_pings_out) = (self.config.ping_interval, self.config.max_pings_out);
+        // The pinger stops once `stop` is dropped, when returning.
+        let (stop, stopped) = mpsc::channel::<()>();
+        let pinger = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                state.ping_clients(max_pings_out);
+            }
+        });
+        for stream in self.listener.incoming() {
+            if self.stopped.load(Ordering::SeqCst) {
+                break;
+            }
+            let conn = match stream.and_then(|stream| self.accept(stream)) {
+                Ok(conn) => conn,
+                Err(e) => {
+                    // The failure is specific to the connection.
+                    trace!("accept failed: {}", e);
+                    continue;
+                }
+            };
+            info.client_id = Some(conn.client.cid());
+            conn.client.outbound().write_with(|buf| info.encode(buf));
+            let parser = Parser::new(self.config.parser_options(ConnectionKind::Client));
+            thread::spawn(move || conn.run(parser));
+        }
+        drop(stop);
+        let _ = pinger.join();
+        Ok(())
+    }
+
+    /// Stop accepting connections and close those open, `run()` returning.
+    pub fn shutdown(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // Wake the accept loop up.
+        if let Ok(mut addr) = self.local_addr() {
+            if addr.ip().is_unspecified() {
+                addr.set_ip(match addr.ip() {
+                    IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                    IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+                });
+            }
+            let _ = TcpStream::connect(addr);
+        }
+        for client in self.clients() {
+            client.outbound().close();
+        }
+    }
+
+    fn accept(&self, stream: TcpStream) -> io::Result<ClientConn> {
+        stream.set_nodelay(true)?;
+        let cid = self.last_cid.fetch_add(1, Ordering::Relaxed) + 1;
+        let client = Arc::new(Client::new(cid, stream.peer_addr()?));
+        trace!("accepted cid {} from {}", cid, client.addr());
+        let mut clients = self.state.clients.lock().unwrap();
+        clients.insert(cid, client.clone());
+        Ok(ClientConn {
+            stream,
+            client,
+            state: self.state.clone(),
+            streamed: None,
+        })
+    }
+}
+
+/// Message published with a payload streamed in chunks.
+struct Streamed {
+    subject: Bytes,
+    reply: Option<Bytes>,
+    size: usize,
+    /// Chunks of the payload read so far, shared with the recipients.
+    chunks: Vec<Bytes>,
+}
+
+/// Connection of a client, read by the thread serving it.
+pub struct ClientConn {
+    stream: TcpStream,
+    client: Arc<Client>,
+    state: Arc<State>,
+    /// Message being streamed, delivered once complete.
+    streamed: Option<Streamed>,
+}
+
+impl ClientConn {
+    pub fn client(&self) -> &Arc<Client> {
+        &self.client
+    }
+
+    /// Serve the connection until the client disconnects or is disconnected,
+    /// reading its operations with `parser`.
+    pub fn run(mut self, parser: Parser) {
+        match self.stream.try_clone() {
+            Ok(stream) => {
+                let client = self.client.clone();
+                let writer = thread::spawn(move || client.outbound().flush_loop(stream));
+                if let Err(e) = self.read_loop(parser) {
+                    trace!("read failed: {}", e);
+                }
+                self.client.outbound().close();
+                let _ = writer.join();
+            }
+            Err(e) => trace!("cannot write to cid {}: {}", self.client.cid(), e),
+        }
+        self.close();
+    }
+
+    /// Remove the connection and its subscriptions from the server.
+    fn close(&self) {
+        let subs: Vec<_> = self.client.subs().drain().map(|(_, sub)| sub).collect();
+        let mut sublist = self.state.sublist.write().unwrap();
+        for sub in &subs {
+            sublist.remove(&sub.subject, sub.queue.as_deref(), sub);
+        }
+        drop(sublist);
+        let mut clients = self.state.clients.lock().unwrap();
+        clients.remove(&self.client.cid());
+    }
+
+    fn read_loop(&mut self, mut parser: Parser) -> io::Result<()> {
+        let mut input = BytesMut::new();
+        // Whether to acknowledge the operations, as set in CONNECT.
+        let mut verbose = false;
+        loop {
+            // Reuses the memory of the previous reads once the frames sharing
+            // it are dropped.
+            let len = input.len();
+            input.resize(len + READ_SIZE, 0);
+            let n = self.stream.read(&mut input[len..])?;
+            input.truncate(len + n);
+            if n == 0 {
+                return Ok(());
+            }
+            self.client.record_activity();
+            loop {
+                let frame = match parser.parse_bytes(&mut input) {
+                    Ok(Some(frame)) => frame,
+                    Ok(None) => break,
+                    Err(e) => {
+                        trace!("closing cid {}: {}", self.client.cid(), e);
+                        self.send_error(&e.response());
+                        return Ok(());
+                    }
+                };
+                // PING is answered with PONG, and a streamed PUB acknowledged
+                // once complete, unless it was rejected.
+                let ack = match frame {
+                    Frame::Connect(_)
+                    | Frame::Pub { .. }
+                    | Frame::HPub { .. }
+                    | Frame::Sub { .. }
+                    | Frame::Unsub { .. } => true,
+                    Frame::PayloadEnd => self.streamed.is_some(),
+                    _ => false,
+                };
+                let processed = match frame {
+                    Frame::Connect(info) => {
+                        parser.set_pedantic(info.pedantic);
+                        verbose = info.verbose;
+                        self.client.set_opts(info);
+                        Ok(())
+                    }
+                    frame => self.process(frame),
+                };
+                match processed {
+                    Ok(()) if ack && verbose => self.client.outbound().write_with(encode::ok),
+                    Ok(()) => {}
+                    Err(e) => {
+                        self.send_error(&e);
+                        if e.closes_connection() {
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Apply an operation other than CONNECT.
+    fn process(&mut self, frame: Frame) -> Result<(), ErrorResponse> {
+        if let Err(e) = subject::check(&frame) {
+            return Err(e.response());
+        }
+        let stats = self.client.stats();
+        match frame {
+            Frame::Ping => self.client.outbound().write_with(encode::pong),
+            Frame::Pong => self.client.record_pong(),
+            Frame::Pub {
+                subject,
+                reply,
+                payload,
+            } => {
+                stats.record_in(payload.len());
+                let (reply, size) = (reply.as_deref(), payload.len());
+                let payload = [payload];
+                self.deliver(&subject, size, |sub| {
+                    let sid = sub.sid.as_bytes();
+                    sub.client.outbound().write_msg(
+                        |buf| encode::msg_header(buf, &subject, sid, reply, size),
+                        &payload,
+                    )
+                });
+            }
+            Frame::HPub {
+                subject,
+                reply,
+                headers,
+                payload,
+            } => {
+                let (reply, header_size) = (reply.as_deref(), headers.len());
+                let size = header_size + payload.len();
+                stats.record_in(size);
+                // The header block is forwarded as sent.
+                let payload = [headers, payload];
+                self.deliver(&subject, size, |sub| {
+                    let sid = sub.sid.as_bytes();
+                    sub.client.outbound().write_msg(
+                        |buf| encode::hmsg_header(buf, &subject, sid, reply, header_size, size),
+                        &payload,
+                    )
+                });
+            }
+            Frame::PubStream {
+                subject,
+                reply,
+                size,
+            } => {
+                stats.record_in(0);
+                self.streamed = Some(Streamed {
+                    subject,
+                    reply,
+                    size,
+                    chunks: Vec::new(),
+                });
+            }
+            Frame::PayloadChunk(chunk) => {
+                stats.add_in_bytes(chunk.len());
+                if let Some(streamed) = &mut self.streamed {
+                    streamed.chunks.push(chunk);
+                }
+            }
+            Frame::PayloadEnd => {
+                // The recipients get the message at once, the chunks being
+                // interleaved with the messages of other publishers otherwise.
+                if let Some(m) = self.streamed.take() {
+                    let reply = m.reply.as_deref();
+                    self.deliver(&m.subject, m.size, |sub| {
+                        let sid = sub.sid.as_bytes();
+                        sub.client.outbound().write_msg(
+                            |buf| encode::msg_header(buf, &m.subject, sid, reply, m.size),
+                            &m.chunks,
+                        )
+                    });
+                }
+            }
+            Frame::Sub {
+                subject,
+                queue,
+                sid,
+            } => {
+                let (subject, queue, sid) =
+                    (text(&subject), queue.as_deref().map(text), text(&sid));
+                let sub = Arc::new(Subscription::new(self.client.clone(), sid, subject, queue));
+                self.state
+                    .sublist
+                    .write()
+                    .unwrap()
+                    .insert(subject, queue, sub.clone())
+                    .map_err(|e| e.response())?;
+                let replaced = self.client.subs().insert(sid.to_owned(), sub);
+                if let Some(replaced) = replaced {
+                    self.state.unsubscribe(&replaced);
+                }
+            }
+            Frame::Unsub { sid, max_msgs } => {
+                let sub = self.client.subs().get(text(&sid)).cloned();
+                if let Some(sub) = sub {
+                    match max_msgs {
+                        Some(max_msgs) if sub.delivered() < max_msgs => sub.set_max_msgs(max_msgs),
+                        _ => self.state.unsubscribe(&sub),
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Deliver a message of `size` bytes published to `subject` by the
+    /// connection, `write` appending it to the outbound buffer of each
+    /// recipient subscription.
+    fn deliver<F: FnMut(&Arc<Subscription>)>(&self, subject: &[u8], size: usize, mut write: F) {
+        let mut matches = self.state.sublist.read().unwrap().matches(text(subject));
+        if matches.is_empty() {
+            return;
+        }
+        if !self.client.opts().echo {
+            matches.retain(|sub| !Arc::ptr_eq(&sub.client, &self.client));
+        }
+        for sub in matches.recipients() {
+            let last = match sub.record_delivery() {
+                Some(last) => last,
+                // Unsubscribed by a concurrent delivery.
+                None => continue,
+            };
+            write(&sub);
+            sub.client.stats().record_out(size);
+            if last {
+                self.state.unsubscribe(&sub);
+            }
+        }
+    }
+
+    fn send_error(&self, e: &ErrorResponse) {
+        self.client.outbound().write_with(|buf| e.encode(buf));
+    }
+}
+
+/// Text of an argument, which the parser checked to be UTF-8.
+fn text(arg: &[u8]) -> &str {
+    std::str::from_utf8(arg).unwrap_or_default()
+}
+
+/// Data waiting to be sent on a connection. Any thread may append to it,
+/// the writer thread of the connection flushing it.
+#[derive(Default)]
+pub struct Outbound {
+    state: Mutex<OutboundState>,
+    ready: Condvar,
+}
+
+#[derive(Default)]
+struct OutboundState {
+    queue: Segments,
+    closed: bool,
+}
+
+impl OutboundState {
+    /// Where to append, unless the connection is closed.
+    fn writable(&mut self) -> Option<&mut Segments> {
+        if self.closed {
+            None
+        } else {
+            Some(&mut self.queue)
+        }
+    }
+}
+
+/// Data to send, including payloads shared with the other recipients of a
+/// message.
+#[derive(Default)]
+struct Segments {
+    frozen: VecDeque<Bytes>,
+    /// Data copied since the last shared payload, to send after `frozen`.
+    tail: BytesMut,
+}
+
+impl Segments {
+    fn is_empty(&self) -> bool {
+        self.frozen.is_empty() && self.tail.is_empty()
+    }
+
+    /// Move the data copied so far to the frozen segments.
+    fn seal(&mut self) {
+        if !self.tail.is_empty() {
+            let tail = self.tail.split().freeze();
+            self.frozen.push_back(tail);
+        }
+    }
+
+    /// Append `bytes` without copying them.
+    fn push(&mut self, bytes: &Bytes) {
+        if !bytes.is_empty() {
+            self.seal();
+            self.frozen.push_back(bytes.clone());
+        }
+    }
+}
+
+impl Outbound {
+    /// Append to the buffer with `f`, unless the connection is closed.
+    pub fn write_with<F: FnOnce(&mut BytesMut)>(&self, f: F) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(segments) = state.writable() {
+            f(&mut segments.tail);
+            self.ready.notify_one();
+        }
+    }
+
+    /// Append a message, unless the connection is closed: its control line
+    /// written by `header`, the parts of its payload, shared rather than
+    /// copied, and its end.
+    pub fn write_msg<F: FnOnce(&mut BytesMut)>(&self, header: F, payload: &[Bytes]) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(segments) = state.writable() {
+            header(&mut segments.tail);
+            for part in payload {
+                segments.push(part);
+            }
+            encode::msg_end(&mut segments.tail);
+            self.ready.notify_one();
+        }
+    }
+
+    /// Close the connection once the buffer is flushed.
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.ready.notify_one();
+    }
+
+    pub(crate) fn flush_loop(&self, mut stream: TcpStream) {
+        let mut pending = VecDeque::new();
+        loop {
+            let closed = {
+                let mut state = self.state.lock().unwrap();
+                while state.queue.is_empty() && !state.closed {
+                    state = self.ready.wait(state).unwrap();
+                }
+                // Written without holding the lock, for the other threads to
+                // go on appending.
+                state.queue.seal();
+                std::mem::swap(&mut state.queue.frozen, &mut pending);
+                state.closed
+            };
+            if write_segments(&mut stream, &mut pending).is_err() {
+                self.state.lock().unwrap().closed = true;
+                break;
+            }
+            if closed {
+                break;
+            }
+        }
+        let _ = stream.shutdown(Shutdown::Both);
+    }
+}
+
+/// Write the segments of `pending` in order, removing them once written.
+fn write_segments<W: Write>(writer: &mut W, pending: &mut VecDeque<Bytes>) -> io::Result<()> {
+    while !pending.is_empty() {
+        let mut n = {
+            let mut slices = [IoSlice::new(&[]); MAX_IOV];
+            let count = pending.len().min(MAX_IOV);
+            for (slice, segment) in slices.iter_mut().zip(pending.iter()) {
+                *slice = IoSlice::new(segment);
+            }
+            writer.write_vectored(&slices[..count])?
+        };
+        if n == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        while n > 0 {
+            let first = &mut pending[0];
+            if n < first.len() {
+                first.advance(n);
+                break;
+            }
+            n -= first.len();
+            pending.pop_front();
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::time::Duration;
+
+    fn start() -> (Arc<Server>, SocketAddr) {
+        start_with(ServerConfig::default())
+    }
+
+    fn start_with(config: ServerConfig) -> (Arc<Server>, SocketAddr) {
+        let config = ServerConfig {
+            host: "127.0.0.1".to_owned(),
+            port: 0,
+            ..config
+        };
+        let server = Arc::new(Server::bind(config).unwrap());
+        let addr = server.local_addr().unwrap();
+        let running = server.clone();
+        thread::spawn(move || running.run());
+        (server, addr)
+    }
+
+    fn connect(addr: SocketAddr) -> (TcpStream, BufReader<TcpStream>) {
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert!(line.starts_with("INFO {"), "{}", line);
+        assert!(line.contains(&format!("\"port\":{}", addr.port())));
+        (stream, reader)
+    }
+
+    fn read_line(reader: &mut BufReader<TcpStream>) -> String {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        line
+    }
+
+    /// Send `ops` followed by PING, returning the lines received before the
+    /// PONG.
+    fn sync(stream: &mut TcpStream, reader: &mut BufReader<TcpStream>, ops: &[u8]) -> Vec<String> {
+        stream.write_all(ops).unwrap();
+        stream.write_all(b"PING\r\n").unwrap();
+        let mut lines = Vec::new();
+        loop {
+            match read_line(reader).as_str() {
+                "PONG\r\n" => return lines,
+                "" => panic!("connection closed after {:?}", lines),
+                line => lines.push(line.trim_end().to_owned()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_ping() {
+        let (mut stream, mut reader) = connect(start().1);
+        stream
+            .write_all(b"CONNECT {\"verbose\":false}\r\nSUB foo 1\r\nPING\r\n")
+            .unwrap();
+        assert_eq!(read_line(&mut reader), "PONG\r\n");
+    }
+
+    #[test]
+    fn test_errors() {
+        let (mut stream, mut reader) = connect(start().1);
+        // An invalid subject does not close the connection.
+        stream.write_all(b"PUB foo.> 2\r\nhi\r\nPING\r\n").unwrap();
+        assert_eq!(read_line(&mut reader), "-ERR 'Invalid Subject'\r\n");
+        assert_eq!(read_line(&mut reader), "PONG\r\n");
+        stream.write_all(b"FOO\r\n").unwrap();
+        assert_eq!(
+            read_line(&mut reader),
+            "-ERR 'Unknown Protocol Operation'\r\n"
+        );
+        assert_eq!(read_line(&mut reader), "");
+    }
+
+    #[test]
+    fn test_fan_out() {
+        let (server, addr) = start();
+        let (mut a, mut a_reader) = connect(addr);
+        let (mut b, mut b_reader) = connect(addr);
+        let mut workers: Vec<_> = (0..2).map(|_| connect(addr)).collect();
+        let (mut p, mut p_reader) = connect(addr);
+        sync(
+            &mut a,
+            &mut a_reader,
+            b"SUB time.us.east 1\r\nSUB time.> 2\r\n",
+        );
+        sync(
+            &mut b,
+            &mut b_reader,
+            b"SUB time.*.east 1\r\nSUB time.us.west 2\r\n",
+        );
+        for (w, reader) in &mut workers {
+            sync(
+                w,
+                reader,
+                b"SUB time.us.* workers 1\r\nSUB time.us.* other 2\r\n",
+            );
+        }
+        assert_eq!(server.num_subscriptions(), 8);
+        let publish = b"PUB time.us.east inbox 5\r\nhello\r\n".repeat(10);
+        assert!(sync(&mut p, &mut p_reader, &publish).is_empty());
+
+        let lines = sync(&mut a, &mut a_reader, b"");
+        assert_eq!(lines.len(), 40);
+        for sid in &["1", "2"] {
+            let msg = format!("MSG time.us.east {} inbox 5", sid);
+            assert_eq!(lines.iter().filter(|l| **l == msg).count(), 10);
+        }
+        assert_eq!(lines.iter().filter(|l| *l == "hello").count(), 20);
+        let lines = sync(&mut b, &mut b_reader, b"");
+        assert_eq!(lines.len(), 20);
+        assert!(lines.contains(&"MSG time.us.east 1 inbox 5".to_owned()));
+
+        // Each group gets every message once, shared by its members.
+        let mut groups = [0, 0];
+        for (w, reader) in &mut workers {
+            for line in sync(w, reader, b"") {
+                if line.ends_with(" 1 inbox 5") {
+                    groups[0] += 1;
+                } else if line.ends_with(" 2 inbox 5") {
+                    groups[1] += 1;
+                }
+            }
+        }
+        assert_eq!(groups, [10, 10]);
+        let clients = server.clients();
+        let stats = clients[4].stats();
+        assert_eq!((stats.in_msgs(), stats.in_bytes()), (10, 50));
+        let stats = clients[0].stats();
+        assert_eq!((stats.out_msgs(), stats.out_bytes()), (20, 100));
+    }
+
+    #[test]
+    fn test_delivery() {
+        let config = ServerConfig {
+            stream_threshold: Some(16),
+            ..ServerConfig::default()
+        };
+        let (server, addr) = start_with(config);
+        let (mut s, mut reader) = connect(addr);
+        sync(
+            &mut s,
+            &mut reader,
+            b"SUB foo 1\r\nSUB foo 2\r\nUNSUB 2 2\r\n",
+        );
+        let payload = "x".repeat(40);
+        let ops = format!(
+            "PUB foo 2\r\nhi\r\nHPUB foo 18 20\r\nNATS/1.0\r\nA: b\r\n\r\nhi\r\nPUB foo {}\r\n{}\r\n",
+            payload.len(),
+            payload
+        );
+        let lines = sync(&mut s, &mut reader, ops.as_bytes());
+        let hmsg = ["NATS/1.0", "A: b", "", "hi"];
+        let mut expected = vec!["MSG foo 1 2", "hi", "MSG foo 2 2", "hi"];
+        expected.extend(&["HMSG foo 1 18 20"]);
+        expected.extend(&hmsg);
+        expected.extend(&["HMSG foo 2 18 20"]);
+        expected.extend(&hmsg);
+        // The streamed payload is forwarded to sid 1 only, sid 2 having ended.
+        expected.extend(&["MSG foo 1 40", &payload]);
+        assert_eq!(lines, expected);
+        assert_eq!(server.num_subscriptions(), 1);
+
+        // Without echo, the messages of a connection are not delivered to
+        // itself.
+        let ops = b"CONNECT {\"echo\":false}\r\nPUB foo 2\r\nhi\r\n";
+        assert!(sync(&mut s, &mut reader, ops).is_empty());
+        drop((s, reader));
+        while server.num_subscriptions() > 0 {
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn test_streamed_payload() {
+        let config = ServerConfig {
+            stream_threshold: Some(16),
+            ..ServerConfig::default()
+        };
+        let (server, addr) = start_with(config);
+        let (mut s, mut s_reader) = connect(addr);
+        sync(&mut s, &mut s_reader, b"SUB foo 1\r\n");
+        let (mut a, mut a_reader) = connect(addr);
+        let (mut b, mut b_reader) = connect(addr);
+
+        // A publisher stalling halfway through a payload does not hold the
+        // messages of the others.
+        a.write_all(b"PUB foo 40\r\n").unwrap();
+        a.write_all(&[b'a'; 20]).unwrap();
+        let ops = format!("PUB foo 20\r\n{}\r\nPUB foo 2\r\nhi\r\n", "b".repeat(20));
+        assert!(sync(&mut b, &mut b_reader, ops.as_bytes()).is_empty());
+        let lines = sync(&mut s, &mut s_reader, b"");
+        let b_payload = "b".repeat(20);
+        assert_eq!(lines, ["MSG foo 1 20", &b_payload, "MSG foo 1 2", "hi"]);
+        a.write_all(&[b'a'; 20]).unwrap();
+        a.write_all(b"\r\n").unwrap();
+        assert!(sync(&mut a, &mut a_reader, b"").is_empty());
+        let lines = sync(&mut s, &mut s_reader, b"");
+        let a_payload = "a".repeat(40);
+        assert_eq!(lines, ["MSG foo 1 40", &a_payload]);
+
+        // Nor does one leaving halfway cut the subscriber short.
+        a.write_all(b"PUB foo 40\r\n").unwrap();
+        a.write_all(&[b'a'; 20]).unwrap();
+        drop((a, a_reader));
+        while server.clients().len() > 2 {
+            thread::yield_now();
+        }
+        assert!(sync(&mut s, &mut s_reader, b"").is_empty());
+    }
+
+    #[test]
+    fn test_verbose() {
+        let config = ServerConfig {
+            stream_threshold: Some(4),
+            ..ServerConfig::default()
+        };
+        let (mut s, mut reader) = connect(start_with(config).1);
+        let ops = b"CONNECT {\"verbose\":true}\r\nSUB foo 1\r\nPUB foo 2\r\nhi\r\n";
+        let lines = sync(&mut s, &mut reader, ops);
+        assert_eq!(lines, ["+OK", "+OK", "MSG foo 1 2", "hi", "+OK"]);
+        // Failed operations are not acknowledged.
+        let ops = b"SUB foo. 2\r\nPUB foo 8\r\nstreamed\r\nUNSUB 1\r\n";
+        let lines = sync(&mut s, &mut reader, ops);
+        let expected = [
+            "-ERR 'Invalid Subject'",
+            "MSG foo 1 8",
+            "streamed",
+            "+OK",
+            "+OK",
+        ];
+        assert_eq!(lines, expected);
+        // Nor streamed PUBs to invalid subjects.
+        let ops = b"PUB foo.> 8\r\nstreamed\r\nPUB foo..bar 8\r\nstreamed\r\n";
+        let lines = sync(&mut s, &mut reader, ops);
+        assert_eq!(lines, ["-ERR 'Invalid Subject'", "-ERR 'Invalid Subject'"]);
+        let ops = b"CONNECT {\"verbose\":false}\r\nSUB foo 1\r\n";
+        assert!(sync(&mut s, &mut reader, ops).is_empty());
+    }
+
+    #[test]
+    fn test_stale_connection() {
+        let config = ServerConfig {
+            ping_interval: Duration::from_millis(20),
+            max_pings_out: 2,
+            ..ServerConfig::default()
+        };
+        let (server, addr) = start_with(config);
+        let (mut s, mut reader) = connect(addr);
+        // Answered, more than `max_pings_out` PINGs keep the connection open.
+        for _ in 0..4 {
+            assert_eq!(read_line(&mut reader), "PING\r\n");
+            s.write_all(b"PONG\r\n").unwrap();
+        }
+        assert_eq!(read_line(&mut reader), "PING\r\n");
+        assert_eq!(read_line(&mut reader), "PING\r\n");
+        assert_eq!(read_line(&mut reader), "-ERR 'Stale Connection'\r\n");
+        assert_eq!(read_line(&mut reader), "");
+        while !server.clients().is_empty() {
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let config = ServerConfig {
+            host: "127.0.0.1".to_owned(),
+            port: 0,
+            ..ServerConfig::default()
+        };
+        let server = Arc::new(Server::bind(config).unwrap());
+        let addr = server.local_addr().unwrap();
+        let running = server.clone();
+        let run = thread::spawn(move || running.run());
+        let (_s, mut reader) = connect(addr);
+        server.shutdown();
+        // The pinger is joined before returning.
+        run.join().unwrap().unwrap();
+        assert_eq!(read_line(&mut reader), "");
+        while !server.clients().is_empty() {
+            thread::yield_now();
+        }
+
+        let config = ServerConfig {
+            ping_interval: Duration::ZERO,
+            ..ServerConfig::default()
+        };
+        let err = Server::bind(config).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_outbound() {
+        let outbound = Outbound::default();
+        let payload = Bytes::from_static(b"hello");
+        outbound.write_with(encode::ping);
+        outbound.write_msg(
+            |buf| encode::msg_header(buf, b"foo", b"1", None, payload.len()),
+            std::slice::from_ref(&payload),
+        );
+        let mut state = outbound.state.lock().unwrap();
+        state.queue.seal();
+        // The payload is shared rather than copied.
+        let segments = &mut state.queue.frozen;
+        assert_eq!(segments.len(), 3);
+        assert_eq!(segments[1].as_ptr(), payload.as_ptr());
+        let mut out = Vec::new();
+        write_segments(&mut out, segments).unwrap();
+        assert_eq!(out, b"PING\r\nMSG foo 1 5\r\nhello\r\n");
+        assert!(segments.is_empty());
+    }
+
+    #[test]
+    fn test_clients() {
+        let (server, addr) = start();
+        let (mut first, mut first_reader) = connect(addr);
+        let (second, _) = connect(addr);
+        first
+            .write_all(b"CONNECT {\"name\":\"first\"}\r\nPUB foo 2\r\nhi\r\nSUB foo 1\r\nPING\r\n")
+            .unwrap();
+        assert_eq!(read_line(&mut first_reader), "PONG\r\n");
+        let clients = server.clients();
+        let cids: Vec<_> = clients.iter().map(|c| c.cid()).collect();
+        assert_eq!(cids, [1, 2]);
+        assert_eq!(clients[0].addr(), first.local_addr().unwrap());
+        assert_eq!(clients[0].opts().name.as_deref(), Some("first"));
+        assert_eq!(clients[0].subs()["1"].subject, "foo");
+        let stats = clients[0].stats();
+        assert_eq!((stats.in_msgs(), stats.in_bytes()), (1, 2));
+
+        drop(second);
+        while server.clients().len() > 1 {
+            thread::yield_now();
+        }
+        assert_eq!(server.clients()[0].cid(), 1);
+    }
+}
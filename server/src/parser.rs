@@ -21,21 +21,57 @@ time.eu.warsaw
 - Messages
     - NATS protocol operation names are case insensitive, thus `SUB foo 1\r\n` and `sub foo 1\r\n` are equivalent.
 
+## CONNECT
+```
+CONNECT {["option_name":option_value],...}\r
+```
 ## PUB
 ```
 PUB <subject> [reply-to] <#bytes>\r\n[payload]\r
 ```
+## HPUB
+```
+HPUB <subject> [reply-to] <#header bytes> <#total bytes>\r\n[headers]\r\n\r\n[payload]\r
+```
 ## SUB
 ```
 SUB <subject> [queue group] <sid>\r
 ```
+## UNSUB
+```
+UNSUB <sid> [max_msgs]\r
+```
+## PING/PONG
+```
+PING\r
+PONG\r
+```
 ## MSG
 ```
 MSG <subject> <sid> [reply-to] <#bytes>\r\n[payload]\r
+```
+
+Route connections, between servers, replace SUB, UNSUB and PUB with:
+## RS+
+```
+RS+ <account> <subject> [queue group] [weight]\r
+```
+## RS-
+```
+RS- <account> <subject> [queue group]\r
+```
+## RMSG
+```
+RMSG <account> <subject> [reply-to] <#bytes>\r\n[payload]\r
+RMSG <account> <subject> + <reply-to> <queue group>... <#bytes>\r\n[payload]\r
+RMSG <account> <subject> | <queue group>... <#bytes>\r\n[payload]\r
 ```
  */
 
+use crate::config::{DEFAULT_MAX_CONTROL_LINE, DEFAULT_MAX_PAYLOAD};
 use crate::error::*;
+use bytes::{Bytes, BytesMut};
+use serde::Deserialize;
 
 macro_rules! parse_error {
     () => {{
@@ -43,23 +79,6 @@ macro_rules! parse_error {
     }};
 }
 
-#[derive(Debug, Clone)]
-enum ParseState {
-    OpStart,
-    OpP,
-    OpPu,
-    OpPub,
-    OpPubSpace,
-    OpPubArg,
-    OpS,
-    OpSu,
-    OpSub,
-    OPSubSpace,
-    OpSubArg,
-    OpMsgPayload,
-    OpMsgEnd,
-}
-
 #[derive(Debug, PartialEq)]
 pub struct SubArg<'a> {
     pub subject: &'a str,
@@ -67,23 +86,151 @@ pub struct SubArg<'a> {
     pub queue: Option<&'a str>,
 }
 
+/// Options sent by a client in CONNECT.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ConnectInfo {
+    /// Acknowledge every operation with `+OK`.
+    pub verbose: bool,
+    pub pedantic: bool,
+    pub user: Option<String>,
+    pub pass: Option<String>,
+    pub auth_token: Option<String>,
+    pub name: Option<String>,
+    pub lang: String,
+    pub version: String,
+    pub protocol: i32,
+    /// Deliver the messages published by the connection to its own
+    /// subscriptions.
+    pub echo: bool,
+    pub headers: bool,
+}
+
+impl Default for ConnectInfo {
+    fn default() -> Self {
+        Self {
+            verbose: false,
+            pedantic: false,
+            user: None,
+            pass: None,
+            auth_token: None,
+            name: None,
+            lang: String::new(),
+            version: String::new(),
+            protocol: 0,
+            echo: true,
+            headers: false,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct UnsubArg<'a> {
+    pub sid: &'a str,
+    /// Number of messages after which the subscription ends.
+    pub max_msgs: Option<usize>,
+}
+
 #[derive(Debug, PartialEq)]
 pub struct PubArg<'a> {
     pub subject: &'a str,
+    pub reply: Option<&'a str>,
     pub size_buf: &'a str, // 1024 字符串形式,避免后续再次转换
     pub size: usize,       //1024 整数形式
     pub msg: &'a [u8],
 }
 
-const BUF_LEN: usize = 512;
+#[derive(Debug, PartialEq)]
+pub struct HPubArg<'a> {
+    pub subject: &'a str,
+    pub reply: Option<&'a str>,
+    pub header_size: usize,
+    /// Size of the headers and the payload.
+    pub total_size: usize,
+    /// Header names and values, in the order they were sent.
+    pub headers: Vec<(&'a str, &'a str)>,
+    pub msg: &'a [u8],
+}
+
+/// Interest of a remote server in a subject, RS+.
+#[derive(Debug, PartialEq)]
+pub struct RsSubArg<'a> {
+    pub account: &'a str,
+    pub subject: &'a str,
+    pub queue: Option<&'a str>,
+    /// Number of members of the queue group on the remote server.
+    pub weight: Option<u32>,
+}
+
+/// End of the interest of a remote server in a subject, RS-.
+#[derive(Debug, PartialEq)]
+pub struct RsUnsubArg<'a> {
+    pub account: &'a str,
+    pub subject: &'a str,
+    pub queue: Option<&'a str>,
+}
+
+/// Message forwarded by a remote server, RMSG.
+#[derive(Debug, PartialEq)]
+pub struct RMsgArg<'a> {
+    pub account: &'a str,
+    pub subject: &'a str,
+    pub reply: Option<&'a str>,
+    /// Queue groups of the receiving server to deliver the message to.
+    pub queues: Vec<&'a str>,
+    pub size: usize,
+    pub msg: &'a [u8],
+}
+
+/// Kind of connection read by a parser, setting which operations it accepts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConnectionKind {
+    Client,
+    /// Another server of the cluster.
+    Route,
+}
+
+/// Limits enforced by the parser, see `ServerConfig::parser_options()`.
+#[derive(Debug, Clone)]
+pub struct ParserOptions {
+    pub kind: ConnectionKind,
+    /// Require CR before LF and a single space or tab between arguments,
+    /// rather than tolerating what `nats-server` does.
+    pub pedantic: bool,
+    /// Longest control line accepted, the line of an operation preceding its
+    /// payload.
+    pub max_control_line: usize,
+    /// Largest payload accepted, headers included.
+    pub max_payload: usize,
+    /// Size above which the payload of a PUB is not buffered but returned in
+    /// chunks as it arrives, following a `PubStream` result or frame.
+    pub stream_threshold: Option<usize>,
+}
+
+impl Default for ParserOptions {
+    fn default() -> Self {
+        Self {
+            kind: ConnectionKind::Client,
+            pedantic: false,
+            max_control_line: DEFAULT_MAX_CONTROL_LINE,
+            max_payload: DEFAULT_MAX_PAYLOAD,
+            stream_threshold: None,
+        }
+    }
+}
+/// Version line starting every header block.
+const HEADER_VERSION: &str = "NATS/1.0";
 pub struct Parser {
-    state: ParseState,
-    buf: [u8; BUF_LEN],
-    arg_len: usize,
-    msg_buf: Option<Vec<u8>>,
-    msg_total_len: usize,
-    msg_len: usize,
-    debug: bool,
+    opts: ParserOptions,
+    /// Bytes of the streamed payload still to come, `Some(0)` once only its
+    /// terminator is.
+    streaming: Option<usize>,
+    /// Start of an operation split across calls to `parse()`, copied until
+    /// it is complete.
+    pending: Vec<u8>,
+    /// Whether `pending` holds the operation returned last, dropped by the
+    /// next call.
+    returned: bool,
 }
 
 #[derive(Debug, PartialEq)]
@@ -91,244 +238,847 @@ pub enum ParseResult<'a> {
     NoMsg,
     Sub(SubArg<'a>),
     Pub(PubArg<'a>),
+    HPub(HPubArg<'a>),
+    Unsub(UnsubArg<'a>),
+    Ping,
+    Pong,
+    Connect(ConnectInfo),
+    /// PUB whose payload, larger than `ParserOptions::stream_threshold`,
+    /// follows in `PayloadChunk` results. `msg` is empty.
+    PubStream(PubArg<'a>),
+    /// Part of the payload of the last `PubStream`, as read.
+    PayloadChunk(&'a [u8]),
+    /// The payload of the last `PubStream` is complete.
+    PayloadEnd,
+    RsSub(RsSubArg<'a>),
+    RsUnsub(RsUnsubArg<'a>),
+    RMsg(RMsgArg<'a>),
+}
+
+/// Operation parsed by `Parser::parse_bytes()`. Its fields are slices of the
+/// input sharing its memory, so a payload can be handed to any number of
+/// subscribers without being copied. Arguments of the control line are valid
+/// UTF-8.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    Connect(ConnectInfo),
+    Pub {
+        subject: Bytes,
+        reply: Option<Bytes>,
+        payload: Bytes,
+    },
+    HPub {
+        subject: Bytes,
+        reply: Option<Bytes>,
+        /// Raw header block, version line and trailing empty line included.
+        headers: Bytes,
+        payload: Bytes,
+    },
+    /// PUB whose payload of `size` bytes, larger than
+    /// `ParserOptions::stream_threshold`, follows in `PayloadChunk` frames.
+    PubStream {
+        subject: Bytes,
+        reply: Option<Bytes>,
+        size: usize,
+    },
+    /// Part of the payload of the last `PubStream`, as read.
+    PayloadChunk(Bytes),
+    /// The payload of the last `PubStream` is complete.
+    PayloadEnd,
+    Sub {
+        subject: Bytes,
+        queue: Option<Bytes>,
+        sid: Bytes,
+    },
+    Unsub {
+        sid: Bytes,
+        max_msgs: Option<usize>,
+    },
+    Ping,
+    Pong,
+    RsSub {
+        account: Bytes,
+        subject: Bytes,
+        queue: Option<Bytes>,
+        weight: Option<u32>,
+    },
+    RsUnsub {
+        account: Bytes,
+        subject: Bytes,
+        queue: Option<Bytes>,
+    },
+    RMsg {
+        account: Bytes,
+        subject: Bytes,
+        reply: Option<Bytes>,
+        queues: Vec<Bytes>,
+        payload: Bytes,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Connect,
+    Pub,
+    HPub,
+    Sub,
+    Unsub,
+    Ping,
+    Pong,
+    RsSub,
+    RsUnsub,
+    RMsg,
+}
+
+const CLIENT_OPS: [(&str, Op); 7] = [
+    ("CONNECT", Op::Connect),
+    ("PUB", Op::Pub),
+    ("HPUB", Op::HPub),
+    ("SUB", Op::Sub),
+    ("UNSUB", Op::Unsub),
+    ("PING", Op::Ping),
+    ("PONG", Op::Pong),
+];
+
+const ROUTE_OPS: [(&str, Op); 6] = [
+    ("CONNECT", Op::Connect),
+    ("RS+", Op::RsSub),
+    ("RS-", Op::RsUnsub),
+    ("RMSG", Op::RMsg),
+    ("PING", Op::Ping),
+    ("PONG", Op::Pong),
+];
+
+/// Most arguments of an operation, those of an HPUB with a reply subject,
+/// RMSG aside.
+const MAX_ARGS: usize = 4;
+
+/// Range of an argument or payload in the input.
+type Span = (usize, usize);
+
+/// Operation found by `Parser::scan()`, its arguments given by their range
+/// in the input.
+#[derive(Debug, PartialEq)]
+enum Scanned {
+    Connect(ConnectInfo),
+    Pub {
+        subject: Span,
+        reply: Option<Span>,
+        size: Span,
+        payload: Span,
+    },
+    HPub {
+        subject: Span,
+        reply: Option<Span>,
+        headers: Span,
+        payload: Span,
+    },
+    PubStream {
+        subject: Span,
+        reply: Option<Span>,
+        size: Span,
+        len: usize,
+    },
+    PayloadChunk(Span),
+    PayloadEnd,
+    Sub {
+        subject: Span,
+        queue: Option<Span>,
+        sid: Span,
+    },
+    Unsub {
+        sid: Span,
+        max_msgs: Option<usize>,
+    },
+    Ping,
+    Pong,
+    RsSub {
+        account: Span,
+        subject: Span,
+        queue: Option<Span>,
+        weight: Option<u32>,
+    },
+    RsUnsub {
+        account: Span,
+        subject: Span,
+        queue: Option<Span>,
+    },
+    RMsg {
+        account: Span,
+        subject: Span,
+        reply: Option<Span>,
+        queues: Vec<Span>,
+        payload: Span,
+    },
 }
 
 impl Parser {
-    pub fn new() -> Self {
+    pub fn new(opts: ParserOptions) -> Self {
         Self {
-            state: ParseState::OpStart,
-            buf: [0; BUF_LEN],
-            arg_len: 0,
-            msg_buf: None,
-            msg_total_len: 0,
-            msg_len: 0,
-            debug: true,
-        }
-    }
-    pub fn parse(&mut self, buf: &[u8]) -> Result<(ParseResult, usize), NError> {
-        let mut b;
-        let mut i = 0;
-
-        if self.debug {
-            println!(
-                "parse string: {}, state:{:?}",
-                unsafe { std::str::from_utf8_unchecked(buf) },
-                self.state
-            );
+            opts,
+            streaming: None,
+            pending: Vec::new(),
+            returned: false,
         }
+    }
 
-        while i < buf.len() {
-            b = buf[i] as char;
-            use ParseState::*;
-            match self.state {
-                OpStart => match b {
-                    'P' | 'p' => self.state = OpP,
-                    'S' | 's' => self.state = OpS,
-                    _ => parse_error!(),
-                },
-                OpP => match b {
-                    'U' | 'u' => self.state = OpPu,
-                    _ => parse_error!(),
-                },
-                OpPu => match b {
-                    'B' | 'b' => self.state = OpPub,
-                    _ => parse_error!(),
-                },
-                OpPub => match b {
-                    ' ' | '\t' => self.state = OpPubSpace,
-                    _ => parse_error!(),
-                },
-                OpPubSpace => match b {
-                    ' ' | '\t' => {}
-                    _ => {
-                        self.state = OpPubArg;
-                        self.arg_len = 0;
-                        continue;
-                    }
-                },
-                OpPubArg => match b {
-                    '\r' => {}
-                    '\n' => {
-                        self.state = OpMsgPayload;
-                        let size = self.process_payload_size()?;
-                        if size == 0 || size > 1 * 1024 * 1024 {
-                            return Err(NError::new(ERROR_MESSAGE_SIZE_TOO_LARGE));
-                        }
-                        if size + self.arg_len > BUF_LEN && self.msg_buf.is_none() {
-                            self.msg_buf = Some(Vec::with_capacity(size));
-                        }
-                        self.msg_total_len = size;
-                    }
-                    _ => self.add_arg(b as u8)?,
-                },
-                OpMsgPayload => {
-                    if self.msg_len < self.msg_total_len {
-                        self.add_msg(b as u8);
-                    } else {
-                        self.state = OpMsgEnd;
-                    }
+    /// Switch to pedantic parsing, as requested by a client in CONNECT.
+    pub fn set_pedantic(&mut self, pedantic: bool) {
+        self.opts.pedantic = pedantic;
+    }
+
+    /// Parse the operation at the start of `buf`, returning it with the
+    /// number of bytes of `buf` it took. An incomplete operation is kept for
+    /// the next call, `NoMsg` being returned.
+    pub fn parse<'a>(&'a mut self, buf: &'a [u8]) -> Result<(ParseResult<'a>, usize), NError> {
+        trace!(
+            "parse string: {}, pending: {}",
+            String::from_utf8_lossy(buf),
+            self.pending.len()
+        );
+        if std::mem::take(&mut self.returned) {
+            self.pending.clear();
+        }
+        if self.pending.is_empty() {
+            return match self.scan(buf)? {
+                Some((scanned, len)) => Ok((result(buf, scanned)?, len)),
+                None => {
+                    self.pending.extend_from_slice(buf);
+                    Ok((ParseResult::NoMsg, buf.len()))
                 }
-                OpMsgEnd => match b {
-                    ' ' | '\t' => {}
-                    '\n' => {
-                        self.state = OpStart;
-                        let res = self.process_payload()?;
-                        return Ok((res, i + 1));
-                    }
-                    _ => parse_error!(),
-                },
-                OpS => match b {
-                    'U' => self.state = OpSu,
-                    _ => parse_error!(),
-                },
-                OpSu => match b {
-                    'B' => self.state = OpSub,
-                    _ => parse_error!(),
-                },
-                OpSub => match b {
-                    ' ' | '\t' => self.state = OPSubSpace,
-                    _ => parse_error!(),
-                },
-                OPSubSpace => match b {
-                    ' ' | '\t' => {}
-                    _ => {
-                        self.state = OpSubArg;
-                        self.arg_len = 0;
-                        continue;
-                    }
-                },
-                OpSubArg => match b {
-                    '\r' => {}
-                    '\n' => {
-                        self.state = OpStart;
-                        let res = self.process_sub()?;
-                        return Ok((res, i + 1));
-                    }
-                    _ => self.add_arg(b as u8)?,
-                },
+            };
+        }
+        let before = self.pending.len();
+        self.pending.extend_from_slice(buf);
+        let pending = std::mem::take(&mut self.pending);
+        let scanned = self.scan(&pending);
+        self.pending = pending;
+        match scanned {
+            Ok(Some((scanned, len))) => {
+                self.returned = true;
+                // The operation was incomplete without `buf`, so ends in it.
+                Ok((result(&self.pending, scanned)?, len - before))
+            }
+            Ok(None) => Ok((ParseResult::NoMsg, buf.len())),
+            Err(mut e) => {
+                if let Some(context) = e.context.as_mut() {
+                    context.offset = context.offset.saturating_sub(before);
+                }
+                Err(e)
             }
-            i += 1;
         }
-        Ok((ParseResult::NoMsg, buf.len()))
     }
 
-    fn add_arg(&mut self, b: u8) -> Result<(), NError> {
-        if self.arg_len >= self.buf.len() {
-            parse_error!();
+    /// Parse every complete operation of `buf`, passing them to `f` in order,
+    /// and keep the trailing partial operation for the next call. Returns the
+    /// number of operations parsed.
+    pub fn parse_all<F>(&mut self, buf: &[u8], mut f: F) -> Result<usize, NError>
+    where
+        F: FnMut(ParseResult),
+    {
+        let mut count = 0;
+        let mut offset = 0;
+        while offset < buf.len() {
+            let (res, len) = self.parse(&buf[offset..]).map_err(|mut e| {
+                if let Some(context) = e.context.as_mut() {
+                    context.offset += offset;
+                }
+                e
+            })?;
+            offset += len;
+            if res != ParseResult::NoMsg {
+                count += 1;
+                f(res);
+            }
         }
-        self.buf[self.arg_len] = b;
-        self.arg_len += 1;
-        Ok(())
+        Ok(count)
     }
 
-    fn add_msg(&mut self, b: u8) {
-        if let Some(buf) = self.msg_buf.as_mut() {
-            buf.push(b);
-        } else {
-            if self.arg_len + self.msg_total_len > BUF_LEN {
-                panic!("message is large, should allocate space");
-            }
-            self.buf[self.arg_len + self.msg_len] = b;
-        }
-        self.msg_len += 1;
+    /// Parse the operation at the start of `input` and split it off. Returns
+    /// `None` while the operation is incomplete, leaving it in `input` for
+    /// the next read to be appended.
+    pub fn parse_bytes(&mut self, input: &mut BytesMut) -> Result<Option<Frame>, NError> {
+        let found = self.scan(input)?;
+        Ok(found.map(|(scanned, len)| frame(&input.split_to(len).freeze(), scanned)))
     }
 
-    fn process_sub(&self) -> Result<ParseResult, NError> {
-        let buf = &self.buf[0..self.arg_len];
-        let s = std::str::from_utf8(buf).unwrap();
-        let mut arg_buf = [""; 3];
-        let mut arg_len = 0;
-        for e in s.split(' ') {
-            if e.len() == 0 {
-                continue;
+    /// Find the operation starting `input`, returning it with its length, or
+    /// `None` while it is incomplete. The state of the parser only changes
+    /// once an operation is found.
+    fn scan(&mut self, input: &[u8]) -> Result<Option<(Scanned, usize)>, NError> {
+        if let Some(left) = self.streaming {
+            return self.scan_stream(input, left);
+        }
+        let max = self.opts.max_control_line;
+        let line_len = match input[..input.len().min(max)]
+            .iter()
+            .position(|b| *b == b'\n')
+        {
+            Some(pos) => pos + 1,
+            None if input.len() < max => return Ok(None),
+            None => {
+                let name = find_op(&input[..max], self.opts.kind).map(|(name, _, _)| name);
+                return Err(NError::new(ERROR_MAX_CONTROL_LINE).at(name, input, max));
             }
-            if arg_len >= 3 {
-                parse_error!()
+        };
+        let line = &input[..line_len];
+        let (name, op, name_len) = match find_op(line, self.opts.kind) {
+            Some(found) => found,
+            None => return Err(NError::new(ERROR_PARSE).at(None, input, 0)),
+        };
+        // Errors in the arguments are reported at the end of the line.
+        let end = line_len - if line.ends_with(b"\r\n") { 2 } else { 1 };
+        self.scan_op(input, line_len, op, name_len)
+            .map_err(|e| match e.context {
+                Some(_) => e,
+                None => e.at(Some(name), input, end),
+            })
+    }
+
+    /// Scan the operation `op`, whose control line takes the first `line_len`
+    /// bytes of `input`, and its name the first `name_len`.
+    fn scan_op(
+        &mut self,
+        input: &[u8],
+        line_len: usize,
+        op: Op,
+        name_len: usize,
+    ) -> Result<Option<(Scanned, usize)>, NError> {
+        let line = &input[..line_len];
+        if self.opts.pedantic && !is_pedantic_line(line, op) {
+            parse_error!();
+        }
+        if op == Op::Connect {
+            let info =
+                serde_json::from_slice(&line[name_len..]).map_err(|_| NError::new(ERROR_PARSE))?;
+            return Ok(Some((Scanned::Connect(info), line_len)));
+        }
+        if std::str::from_utf8(line).is_err() {
+            parse_error!();
+        }
+        if op == Op::RMsg {
+            let args: Vec<Span> = split_args(line, name_len).collect();
+            return self.scan_rmsg(input, line_len, &args);
+        }
+        let mut args = [(0, 0); MAX_ARGS];
+        let mut argc = 0;
+        for arg in split_args(line, name_len) {
+            if argc == MAX_ARGS {
+                parse_error!();
             }
-            arg_buf[arg_len] = e;
-            arg_len += 1;
+            args[argc] = arg;
+            argc += 1;
         }
-        let mut sub_arg = SubArg {
-            subject: arg_buf[0],
-            sid: "",
-            queue: None,
-        };
-        match arg_len {
-            2 => {
-                sub_arg.sid = arg_buf[1];
+        let scanned = match (op, &args[..argc]) {
+            (Op::Ping, []) => Scanned::Ping,
+            (Op::Pong, []) => Scanned::Pong,
+            (Op::Sub, &[subject, sid]) => Scanned::Sub {
+                subject,
+                queue: None,
+                sid,
+            },
+            (Op::Sub, &[subject, queue, sid]) => Scanned::Sub {
+                subject,
+                queue: Some(queue),
+                sid,
+            },
+            (Op::Unsub, &[sid]) => Scanned::Unsub {
+                sid,
+                max_msgs: None,
+            },
+            (Op::Unsub, &[sid, max_msgs]) => Scanned::Unsub {
+                sid,
+                max_msgs: Some(parse_number(line, max_msgs)?),
+            },
+            (Op::RsSub, &[account, subject, ref rest @ ..]) if rest.len() <= 2 => Scanned::RsSub {
+                account,
+                subject,
+                queue: rest.first().copied(),
+                weight: match rest.get(1) {
+                    Some(weight) => Some(parse_number(line, *weight)?),
+                    None => None,
+                },
+            },
+            (Op::RsUnsub, &[account, subject, ref rest @ ..]) if rest.len() <= 1 => {
+                Scanned::RsUnsub {
+                    account,
+                    subject,
+                    queue: rest.first().copied(),
+                }
+            }
+            (Op::Pub, &[subject, size]) => {
+                return self.scan_pub(input, line_len, subject, None, size)
             }
-            3 => {
-                sub_arg.sid = arg_buf[1];
-                sub_arg.queue = Some(arg_buf[2]);
+            (Op::Pub, &[subject, reply, size]) => {
+                return self.scan_pub(input, line_len, subject, Some(reply), size)
+            }
+            (Op::HPub, &[subject, header, total]) => {
+                return self.scan_hpub(input, line_len, subject, None, [header, total])
+            }
+            (Op::HPub, &[subject, reply, header, total]) => {
+                return self.scan_hpub(input, line_len, subject, Some(reply), [header, total])
             }
             _ => parse_error!(),
+        };
+        Ok(Some((scanned, line_len)))
+    }
+
+    /// Scan a PUB, whose payload is streamed when larger than the threshold.
+    fn scan_pub(
+        &mut self,
+        input: &[u8],
+        line_len: usize,
+        subject: Span,
+        reply: Option<Span>,
+        size: Span,
+    ) -> Result<Option<(Scanned, usize)>, NError> {
+        let len = self.payload_size(input, size)?;
+        if self.opts.stream_threshold.is_some_and(|t| len > t) {
+            self.streaming = Some(len);
+            let scanned = Scanned::PubStream {
+                subject,
+                reply,
+                size,
+                len,
+            };
+            return Ok(Some((scanned, line_len)));
         }
-        Ok(ParseResult::Sub(sub_arg))
+        let payload = (line_len, line_len + len);
+        Ok(self.scan_end(input, payload.1, "PUB")?.map(|frame_len| {
+            let scanned = Scanned::Pub {
+                subject,
+                reply,
+                size,
+                payload,
+            };
+            (scanned, frame_len)
+        }))
     }
 
-    fn process_payload(&self) -> Result<ParseResult, NError> {
-        let msg = if let Some(buf) = &self.msg_buf {
-            buf.as_slice()
-        } else {
-            &self.buf[self.arg_len..self.arg_len + self.msg_total_len]
+    /// Scan an HPUB, given the ranges of its header and total sizes.
+    fn scan_hpub(
+        &self,
+        input: &[u8],
+        line_len: usize,
+        subject: Span,
+        reply: Option<Span>,
+        [header, total]: [Span; 2],
+    ) -> Result<Option<(Scanned, usize)>, NError> {
+        let total: usize = self.payload_size(input, total)?;
+        let header: usize = parse_number(input, header)?;
+        if header < HEADER_VERSION.len() + 4 || header > total {
+            parse_error!();
+        }
+        let headers = (line_len, line_len + header);
+        let payload = (headers.1, line_len + total);
+        let frame_len = match self.scan_end(input, payload.1, "HPUB")? {
+            Some(frame_len) => frame_len,
+            None => return Ok(None),
         };
+        parse_headers(&input[headers.0..headers.1])
+            .map_err(|e| e.at(Some("HPUB"), input, line_len))?;
+        let scanned = Scanned::HPub {
+            subject,
+            reply,
+            headers,
+            payload,
+        };
+        Ok(Some((scanned, frame_len)))
+    }
 
-        let s = unsafe { std::str::from_utf8_unchecked(&self.buf[0..self.arg_len]) };
-        let mut arg_buf = [""; 2];
-        let mut arg_len = 0;
-        for e in s.split(' ') {
-            if e.len() == 0 {
-                continue;
+    /// Scan an RMSG, whose arguments `args` may list queue groups.
+    fn scan_rmsg(
+        &self,
+        input: &[u8],
+        line_len: usize,
+        args: &[Span],
+    ) -> Result<Option<(Scanned, usize)>, NError> {
+        let (account, subject, middle, size) = match args {
+            [account, subject, middle @ .., size] => (*account, *subject, middle, *size),
+            _ => parse_error!(),
+        };
+        let is = |(start, end): Span, arg: &[u8]| &input[start..end] == arg;
+        // Between the subject and the size.
+        let (reply, queues) = match middle {
+            [] => (None, &[][..]),
+            [reply] if !is(*reply, b"+") && !is(*reply, b"|") => (Some(*reply), &[][..]),
+            [plus, reply, queues @ ..] if is(*plus, b"+") && !queues.is_empty() => {
+                (Some(*reply), queues)
             }
-            arg_buf[arg_len] = e;
-            arg_len += 1;
-        }
-        let pub_arg = PubArg {
-            subject: arg_buf[0],
-            size_buf: arg_buf[1],
-            size: self.msg_total_len,
-            msg,
+            [bar, queues @ ..] if is(*bar, b"|") && !queues.is_empty() => (None, queues),
+            _ => parse_error!(),
         };
+        let len = self.payload_size(input, size)?;
+        let payload = (line_len, line_len + len);
+        Ok(self.scan_end(input, payload.1, "RMSG")?.map(|frame_len| {
+            let scanned = Scanned::RMsg {
+                account,
+                subject,
+                reply,
+                queues: queues.to_vec(),
+                payload,
+            };
+            (scanned, frame_len)
+        }))
+    }
 
-        Ok(ParseResult::Pub(pub_arg))
+    /// Scan the next part of a streamed payload, `left` bytes of which are
+    /// still to come.
+    fn scan_stream(
+        &mut self,
+        input: &[u8],
+        left: usize,
+    ) -> Result<Option<(Scanned, usize)>, NError> {
+        if left == 0 {
+            let frame_len = self.scan_end(input, 0, "PUB")?;
+            if frame_len.is_some() {
+                self.streaming = None;
+            }
+            return Ok(frame_len.map(|frame_len| (Scanned::PayloadEnd, frame_len)));
+        }
+        if input.is_empty() {
+            return Ok(None);
+        }
+        let len = left.min(input.len());
+        self.streaming = Some(left - len);
+        Ok(Some((Scanned::PayloadChunk((0, len)), len)))
     }
 
-    fn process_payload_size(&self) -> Result<usize, NError> {
-        let buf = &self.buf[0..self.arg_len];
-        let pos = buf
-            .iter()
-            .rev()
-            .position(|b| *b == ' ' as u8 || *b == '\t' as u8);
-        if pos.is_none() {
-            parse_error!();
+    /// Size of a payload given by the argument `size`, within the limit.
+    fn payload_size(&self, input: &[u8], size: Span) -> Result<usize, NError> {
+        let size = parse_number(input, size)?;
+        if size > self.opts.max_payload {
+            return Err(NError::new(ERROR_MESSAGE_SIZE_TOO_LARGE));
         }
-        let pos = pos.unwrap();
-        let size_buf = &buf[(self.arg_len - pos)..];
-        let s = unsafe { std::str::from_utf8_unchecked(size_buf) };
-        s.parse().map_err(|_| NError::new(ERROR_PARSE))
+        Ok(size)
+    }
+
+    /// Find the end of a payload of `op` ending at `at` in `input`: CRLF, or
+    /// outside of pedantic mode LF after any spaces, tabs or CRs. Returns the
+    /// length of the operation, `None` while its end is incomplete.
+    fn scan_end(&self, input: &[u8], at: usize, op: &'static str) -> Result<Option<usize>, NError> {
+        let fail = |offset| Err(NError::new(ERROR_PARSE).at(Some(op), input, offset));
+        if self.opts.pedantic {
+            return match input.get(at..).unwrap_or_default() {
+                [] | [b'\r'] => Ok(None),
+                [b'\r', b'\n', ..] => Ok(Some(at + 2)),
+                [b'\r', ..] => fail(at + 1),
+                _ => fail(at),
+            };
+        }
+        for (i, b) in input.iter().enumerate().skip(at) {
+            match b {
+                b'\n' => return Ok(Some(i + 1)),
+                // Bounded like a control line.
+                b' ' | b'\t' | b'\r' if i - at < self.opts.max_control_line => {}
+                _ => return fail(i),
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Result of `Parser::parse()` for the operation `scanned` of `input`.
+fn result(input: &[u8], scanned: Scanned) -> Result<ParseResult<'_>, NError> {
+    // `scan()` checked the control line to be UTF-8.
+    let arg = move |(start, end): Span| {
+        std::str::from_utf8(&input[start..end]).map_err(|_| NError::new(ERROR_PARSE))
+    };
+    let bytes = move |(start, end): Span| &input[start..end];
+    Ok(match scanned {
+        Scanned::Connect(info) => ParseResult::Connect(info),
+        Scanned::Pub {
+            subject,
+            reply,
+            size,
+            payload,
+        } => ParseResult::Pub(PubArg {
+            subject: arg(subject)?,
+            reply: reply.map(arg).transpose()?,
+            size_buf: arg(size)?,
+            size: payload.1 - payload.0,
+            msg: bytes(payload),
+        }),
+        Scanned::HPub {
+            subject,
+            reply,
+            headers,
+            payload,
+        } => ParseResult::HPub(HPubArg {
+            subject: arg(subject)?,
+            reply: reply.map(arg).transpose()?,
+            header_size: headers.1 - headers.0,
+            total_size: payload.1 - headers.0,
+            headers: parse_headers(bytes(headers))?,
+            msg: bytes(payload),
+        }),
+        Scanned::PubStream {
+            subject,
+            reply,
+            size,
+            len,
+        } => ParseResult::PubStream(PubArg {
+            subject: arg(subject)?,
+            reply: reply.map(arg).transpose()?,
+            size_buf: arg(size)?,
+            size: len,
+            msg: &[],
+        }),
+        Scanned::PayloadChunk(chunk) => ParseResult::PayloadChunk(bytes(chunk)),
+        Scanned::PayloadEnd => ParseResult::PayloadEnd,
+        Scanned::Sub {
+            subject,
+            queue,
+            sid,
+        } => ParseResult::Sub(SubArg {
+            subject: arg(subject)?,
+            sid: arg(sid)?,
+            queue: queue.map(arg).transpose()?,
+        }),
+        Scanned::Unsub { sid, max_msgs } => ParseResult::Unsub(UnsubArg {
+            sid: arg(sid)?,
+            max_msgs,
+        }),
+        Scanned::Ping => ParseResult::Ping,
+        Scanned::Pong => ParseResult::Pong,
+        Scanned::RsSub {
+            account,
+            subject,
+            queue,
+            weight,
+        } => ParseResult::RsSub(RsSubArg {
+            account: arg(account)?,
+            subject: arg(subject)?,
+            queue: queue.map(arg).transpose()?,
+            weight,
+        }),
+        Scanned::RsUnsub {
+            account,
+            subject,
+            queue,
+        } => ParseResult::RsUnsub(RsUnsubArg {
+            account: arg(account)?,
+            subject: arg(subject)?,
+            queue: queue.map(arg).transpose()?,
+        }),
+        Scanned::RMsg {
+            account,
+            subject,
+            reply,
+            queues,
+            payload,
+        } => ParseResult::RMsg(RMsgArg {
+            account: arg(account)?,
+            subject: arg(subject)?,
+            reply: reply.map(arg).transpose()?,
+            queues: queues.into_iter().map(arg).collect::<Result<_, _>>()?,
+            size: payload.1 - payload.0,
+            msg: bytes(payload),
+        }),
+    })
+}
+
+/// Frame of the operation `scanned`, the whole of `input`.
+fn frame(input: &Bytes, scanned: Scanned) -> Frame {
+    let arg = |(start, end): Span| input.slice(start..end);
+    match scanned {
+        Scanned::Connect(info) => Frame::Connect(info),
+        Scanned::Pub {
+            subject,
+            reply,
+            payload,
+            ..
+        } => Frame::Pub {
+            subject: arg(subject),
+            reply: reply.map(arg),
+            payload: arg(payload),
+        },
+        Scanned::HPub {
+            subject,
+            reply,
+            headers,
+            payload,
+        } => Frame::HPub {
+            subject: arg(subject),
+            reply: reply.map(arg),
+            headers: arg(headers),
+            payload: arg(payload),
+        },
+        Scanned::PubStream {
+            subject,
+            reply,
+            len,
+            ..
+        } => Frame::PubStream {
+            subject: arg(subject),
+            reply: reply.map(arg),
+            size: len,
+        },
+        Scanned::PayloadChunk(chunk) => Frame::PayloadChunk(arg(chunk)),
+        Scanned::PayloadEnd => Frame::PayloadEnd,
+        Scanned::Sub {
+            subject,
+            queue,
+            sid,
+        } => Frame::Sub {
+            subject: arg(subject),
+            queue: queue.map(arg),
+            sid: arg(sid),
+        },
+        Scanned::Unsub { sid, max_msgs } => Frame::Unsub {
+            sid: arg(sid),
+            max_msgs,
+        },
+        Scanned::Ping => Frame::Ping,
+        Scanned::Pong => Frame::Pong,
+        Scanned::RsSub {
+            account,
+            subject,
+            queue,
+            weight,
+        } => Frame::RsSub {
+            account: arg(account),
+            subject: arg(subject),
+            queue: queue.map(arg),
+            weight,
+        },
+        Scanned::RsUnsub {
+            account,
+            subject,
+            queue,
+        } => Frame::RsUnsub {
+            account: arg(account),
+            subject: arg(subject),
+            queue: queue.map(arg),
+        },
+        Scanned::RMsg {
+            account,
+            subject,
+            reply,
+            queues,
+            payload,
+        } => Frame::RMsg {
+            account: arg(account),
+            subject: arg(subject),
+            reply: reply.map(arg),
+            queues: queues.into_iter().map(arg).collect(),
+            payload: arg(payload),
+        },
     }
 }
 
+/// Parse a header block: the version line, `Name: Value` lines, and an empty
+/// line.
+fn parse_headers(block: &[u8]) -> Result<Vec<(&str, &str)>, NError> {
+    let s = std::str::from_utf8(block).map_err(|_| NError::new(ERROR_PARSE))?;
+    let s = match s.strip_suffix("\r\n\r\n") {
+        Some(s) => s,
+        None => parse_error!(),
+    };
+    let mut lines = s.split("\r\n");
+    if !lines.next().unwrap_or_default().starts_with(HEADER_VERSION) {
+        parse_error!();
+    }
+    let mut headers = Vec::new();
+    for line in lines {
+        match line.split_once(':') {
+            Some((name, value)) if !name.trim().is_empty() => {
+                headers.push((name.trim(), value.trim()))
+            }
+            _ => parse_error!(),
+        }
+    }
+    Ok(headers)
+}
+
+/// Operation accepted from a connection of `kind` whose name starts `input`,
+/// with the length of the name.
+fn find_op(input: &[u8], kind: ConnectionKind) -> Option<(&'static str, Op, usize)> {
+    let len = input
+        .iter()
+        .position(|b| is_space(*b))
+        .unwrap_or(input.len());
+    let ops: &[(&str, Op)] = match kind {
+        ConnectionKind::Client => &CLIENT_OPS,
+        ConnectionKind::Route => &ROUTE_OPS,
+    };
+    ops.iter()
+        .find(|(name, _)| name.as_bytes().eq_ignore_ascii_case(&input[..len]))
+        .map(|(name, op)| (*name, *op, len))
+}
+
+/// Whether a control line of `op` ends with CRLF and has single spaces or
+/// tabs between its operation and arguments, CONNECT being exempt of the
+/// latter.
+fn is_pedantic_line(line: &[u8], op: Op) -> bool {
+    let line = match line.strip_suffix(b"\r\n") {
+        Some(line) => line,
+        None => return false,
+    };
+    if line.contains(&b'\r') {
+        return false;
+    }
+    if op == Op::Connect {
+        return true;
+    }
+    let blank = |b: &u8| *b == b' ' || *b == b'\t';
+    !(line.last().is_some_and(blank) || line.windows(2).any(|w| blank(&w[0]) && blank(&w[1])))
+}
+
+fn is_space(b: u8) -> bool {
+    matches!(b, b' ' | b'\t' | b'\r' | b'\n')
+}
+
+/// Ranges of the arguments of a control line following the operation name.
+fn split_args(line: &[u8], from: usize) -> impl Iterator<Item = Span> + '_ {
+    let mut start = None;
+    // The line ends with LF, closing the last argument.
+    line.iter()
+        .enumerate()
+        .skip(from)
+        .filter_map(move |(i, b)| match (is_space(*b), start) {
+            (true, Some(s)) => {
+                start = None;
+                Some((s, i))
+            }
+            (false, None) => {
+                start = Some(i);
+                None
+            }
+            _ => None,
+        })
+}
+
+fn parse_number<T: std::str::FromStr>(buf: &[u8], (start, end): Span) -> Result<T, NError> {
+    std::str::from_utf8(&buf[start..end])
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| NError::new(ERROR_PARSE))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::proto::errors::ErrorResponse;
     #[test]
-    fn test_process_sub() {
-        let mut p = Parser::new();
-        let buf = "subject 5".as_bytes();
-        p.buf[0..buf.len()].copy_from_slice(buf);
-        p.arg_len = buf.len();
-        let r = p.process_sub();
-        assert!(r.is_ok());
-        if let ParseResult::Sub(sub) = r.unwrap() {
-            assert_eq!(sub.subject, "subject");
-            assert_eq!(sub.sid, "5");
-            assert!(sub.queue.is_none());
-        }
+    fn test_scan_sub() {
+        let mut p = Parser::new(ParserOptions::default());
+        let r = p.scan(b"SUB subject 5\r\n");
+        assert_eq!(
+            r.unwrap(),
+            Some((
+                Scanned::Sub {
+                    subject: (4, 11),
+                    queue: None,
+                    sid: (12, 13),
+                },
+                15
+            ))
+        );
     }
     #[test]
     fn test_sub() {
-        let mut p = Parser::new();
+        let mut p = Parser::new(ParserOptions::default());
         let buf = "SUB subject 1\r\n".as_bytes();
         let r = p.parse(buf);
         assert!(r.is_ok());
@@ -340,56 +1090,59 @@ mod tests {
             assert_eq!(sub.sid, "1");
             assert_eq!(sub.queue, None);
         } else {
-            assert!(false, "unkown error");
+            panic!("unkown error");
         }
+    }
 
-        // let buf = "SUB subject queue 1\r\n".as_bytes();
-        // let r = p.parse(buf);
-        // println!("r={:?}", r);
-        // assert!(r.is_ok());
-        // let r = r.unwrap();
-        // assert_eq!(r.1, buf.len());
-        // if let ParseResult::Sub(sub) = r.0 {
-        //     assert_eq!(sub.subject, "subject");
-        //     assert_eq!(sub.sid, "1");
-        //     assert_eq!(sub.queue, Some("queue"));
-        // } else {
-        //     assert!(false, "unkown error");
-        // }
+    #[test]
+    fn test_sub_queue() {
+        let mut p = Parser::new(ParserOptions::default());
+        for buf in &["SUB subject queue 1\r\n", "SUB subject\tqueue  1\r\n"] {
+            let (r, len) = p.parse(buf.as_bytes()).unwrap();
+            assert_eq!(len, buf.len());
+            if let ParseResult::Sub(sub) = r {
+                assert_eq!(sub.subject, "subject");
+                assert_eq!(sub.sid, "1");
+                assert_eq!(sub.queue, Some("queue"));
+            } else {
+                panic!("unkown error");
+            }
+        }
     }
 
     #[test]
-    fn test_process_payload_size() {
-        let mut p = Parser::new();
+    fn test_parse_number() {
         let buf = "FOO 11".as_bytes();
-        p.buf[0..buf.len()].copy_from_slice(buf);
-        p.arg_len = buf.len();
-        let r = p.process_payload_size();
+        let r = parse_number::<usize>(buf, (4, 6));
         assert!(r.is_ok());
-        let size = r.unwrap();
-        assert_eq!(size, 11);
+        assert_eq!(r.unwrap(), 11);
+        assert!(parse_number::<usize>(buf, (0, 3)).is_err());
     }
 
     #[test]
-    fn test_process_payload() {
-        let mut p = Parser::new();
-        let buf = "FOO 11Hello NATS!".as_bytes();
-        p.buf[0..buf.len()].copy_from_slice(buf);
-        p.arg_len = "FOO 11".as_bytes().len();
-        p.msg_total_len = 11;
-        let r = p.process_payload();
-        assert!(r.is_ok());
-        if let ParseResult::Pub(pub_arg) = r.unwrap() {
-            assert_eq!(pub_arg.subject, "FOO");
-            assert_eq!(pub_arg.size_buf, "11");
-            assert_eq!(pub_arg.size, 11);
-            assert_eq!(pub_arg.msg, "Hello NATS!".as_bytes());
-        }
+    fn test_scan_payload() {
+        let mut p = Parser::new(ParserOptions::default());
+        let buf = "PUB FOO 11\r\nHello NATS!\r\n".as_bytes();
+        // Incomplete until the end of the payload is read.
+        assert_eq!(p.scan(&buf[..buf.len() - 1]).unwrap(), None);
+        let r = p.scan(buf).unwrap();
+        assert_eq!(
+            r,
+            Some((
+                Scanned::Pub {
+                    subject: (4, 7),
+                    reply: None,
+                    size: (8, 10),
+                    payload: (12, 23),
+                },
+                buf.len()
+            ))
+        );
     }
 
     #[test]
     fn test_pub() {
-        let mut p = Parser::new();
+        let mut p = Parser::new(ParserOptions::default());
         let buf = "PUB FOO 11\r\nHello NATS!\r\n".as_bytes();
         let r = p.parse(buf);
         assert!(r.is_ok());
@@ -401,7 +1154,565 @@ mod tests {
             assert_eq!(pub_arg.size, 11);
             // assert_eq!(pub_arg.msg, "Hello NATS!".as_bytes());
         } else {
-            assert!(false, "unkown error")
+            panic!("unkown error")
+        }
+    }
+
+    #[test]
+    fn test_unsub() {
+        let mut p = Parser::new(ParserOptions::default());
+        let buf = "UNSUB 1\r\n".as_bytes();
+        let r = p.parse(buf).unwrap();
+        assert_eq!(r.1, buf.len());
+        assert_eq!(
+            r.0,
+            ParseResult::Unsub(UnsubArg {
+                sid: "1",
+                max_msgs: None
+            })
+        );
+
+        let buf = "unsub 2  5\r\n".as_bytes();
+        let r = p.parse(buf).unwrap();
+        assert_eq!(r.1, buf.len());
+        assert_eq!(
+            r.0,
+            ParseResult::Unsub(UnsubArg {
+                sid: "2",
+                max_msgs: Some(5)
+            })
+        );
+    }
+
+    #[test]
+    fn test_unsub_invalid() {
+        for buf in &["UNSUB\r\n", "UNSUB 1 x\r\n", "UNSUB 1 2 3\r\n"] {
+            let mut p = Parser::new(ParserOptions::default());
+            assert!(p.parse(buf.as_bytes()).is_err(), "{:?}", buf);
+        }
+    }
+
+    #[test]
+    fn test_ping_pong() {
+        let mut p = Parser::new(ParserOptions::default());
+        let buf = "PING\r\nPONG\r\n".as_bytes();
+        let r = p.parse(buf).unwrap();
+        assert_eq!(r, (ParseResult::Ping, 6));
+        let r = p.parse(&buf[6..]).unwrap();
+        assert_eq!(r, (ParseResult::Pong, 6));
+        assert_eq!(p.parse(b"ping\r\n").unwrap().0, ParseResult::Ping);
+        assert!(Parser::new(ParserOptions::default())
+            .parse(b"PINGX\r\n")
+            .is_err());
+    }
+
+    #[test]
+    fn test_connect() {
+        let mut p = Parser::new(ParserOptions::default());
+        let buf = r#"CONNECT {"verbose":true,"pedantic":false,"user":"derek","pass":"s3cr3t","name":"worker","lang":"rust","protocol":1,"echo":false}"#;
+        let buf = format!("{}\r\n", buf);
+        let r = p.parse(buf.as_bytes()).unwrap();
+        assert_eq!(r.1, buf.len());
+        if let ParseResult::Connect(info) = r.0 {
+            assert!(info.verbose);
+            assert_eq!(info.user.as_deref(), Some("derek"));
+            assert_eq!(info.pass.as_deref(), Some("s3cr3t"));
+            assert_eq!(info.name.as_deref(), Some("worker"));
+            assert_eq!(info.lang, "rust");
+            assert_eq!(info.protocol, 1);
+            assert!(!info.echo);
+            assert_eq!(info.auth_token, None);
+        } else {
+            panic!("unkown error");
+        }
+
+        // Omitted options take their defaults.
+        let r = p.parse(b"connect {}\r\n").unwrap();
+        assert_eq!(r.0, ParseResult::Connect(ConnectInfo::default()));
+        assert!(Parser::new(ParserOptions::default())
+            .parse(b"CONNECT {\r\n")
+            .is_err());
+    }
+
+    #[test]
+    fn test_pub_reply() {
+        let mut p = Parser::new(ParserOptions::default());
+        let buf = "PUB FOO _INBOX.1 11\r\nHello NATS!\r\n".as_bytes();
+        let r = p.parse(buf).unwrap();
+        assert_eq!(r.1, buf.len());
+        if let ParseResult::Pub(pub_arg) = r.0 {
+            assert_eq!(pub_arg.subject, "FOO");
+            assert_eq!(pub_arg.reply, Some("_INBOX.1"));
+            assert_eq!(pub_arg.size_buf, "11");
+            assert_eq!(pub_arg.size, 11);
+            assert_eq!(pub_arg.msg, "Hello NATS!".as_bytes());
+        } else {
+            panic!("unkown error")
+        }
+
+        let buf = "PUB FOO _INBOX.1 extra 2\r\nhi\r\n".as_bytes();
+        assert!(Parser::new(ParserOptions::default()).parse(buf).is_err());
+    }
+
+    #[test]
+    fn test_hpub() {
+        let mut p = Parser::new(ParserOptions::default());
+        let headers = "NATS/1.0\r\nBar: Baz\r\nBar: Qux\r\n\r\n";
+        let buf = format!(
+            "HPUB FOO INBOX.1 {} {}\r\n{}Hello NATS!\r\n",
+            headers.len(),
+            headers.len() + 11,
+            headers
+        );
+        let r = p.parse(buf.as_bytes()).unwrap();
+        assert_eq!(r.1, buf.len());
+        if let ParseResult::HPub(hpub) = r.0 {
+            assert_eq!(hpub.subject, "FOO");
+            assert_eq!(hpub.reply, Some("INBOX.1"));
+            assert_eq!(hpub.header_size, headers.len());
+            assert_eq!(hpub.total_size, headers.len() + 11);
+            assert_eq!(hpub.headers, vec![("Bar", "Baz"), ("Bar", "Qux")]);
+            assert_eq!(hpub.msg, "Hello NATS!".as_bytes());
+        } else {
+            panic!("unkown error")
+        }
+
+        // Headers without payload.
+        let buf = "HPUB FOO 12 12\r\nNATS/1.0\r\n\r\n\r\n";
+        let r = p.parse(buf.as_bytes()).unwrap();
+        if let ParseResult::HPub(hpub) = r.0 {
+            assert!(hpub.headers.is_empty());
+            assert!(hpub.msg.is_empty());
+        } else {
+            panic!("unkown error")
+        }
+    }
+
+    #[test]
+    fn test_hpub_invalid() {
+        for buf in &[
+            "HPUB FOO 22 11\r\nNATS/1.0\r\nA: B\r\n\r\n\r\n",
+            "HPUB FOO 12 14\r\nHTTP/1.1\r\n\r\nhi\r\n",
+            "HPUB FOO 16 16\r\nNATS/1.0\r\nAB\r\n\r\n\r\n",
+            "HPUB FOO 12\r\nNATS/1.0\r\n\r\n\r\n",
+        ] {
+            let mut p = Parser::new(ParserOptions::default());
+            assert!(p.parse(buf.as_bytes()).is_err(), "{:?}", buf);
+        }
+    }
+
+    #[test]
+    fn test_max_payload() {
+        let opts = ParserOptions {
+            max_payload: 4,
+            ..ParserOptions::default()
+        };
+        let mut p = Parser::new(opts.clone());
+        let r = p.parse(b"PUB FOO 5\r\nhello\r\n");
+        assert_eq!(r.unwrap_err().error_code, ERROR_MESSAGE_SIZE_TOO_LARGE);
+        let buf = "HPUB FOO 12 12\r\nNATS/1.0\r\n\r\n\r\n";
+        let mut p = Parser::new(opts.clone());
+        let r = p.parse(buf.as_bytes());
+        assert_eq!(r.unwrap_err().error_code, ERROR_MESSAGE_SIZE_TOO_LARGE);
+
+        let mut p = Parser::new(opts);
+        let r = p.parse(b"PUB FOO 4\r\nhey!\r\n").unwrap();
+        assert!(matches!(r.0, ParseResult::Pub(_)));
+        // Empty payloads are valid.
+        let r = p.parse(b"PUB FOO 0\r\n\r\n").unwrap();
+        if let ParseResult::Pub(pub_arg) = r.0 {
+            assert!(pub_arg.msg.is_empty());
+        } else {
+            panic!("unkown error")
+        }
+    }
+
+    #[test]
+    fn test_parse_all() {
+        let mut p = Parser::new(ParserOptions::default());
+        let mut ops = Vec::new();
+        let buf = b"PING\r\nSUB foo 1\r\nPUB foo 2\r\nhi\r\nPO";
+        let r = p.parse_all(buf, |res| ops.push(format!("{:?}", res)));
+        assert_eq!(r.unwrap(), 3);
+        assert_eq!(ops.len(), 3);
+        assert!(ops[1].starts_with("Sub("));
+        assert!(ops[2].starts_with("Pub("));
+        let r = p.parse_all(b"NG\r\n", |res| assert_eq!(res, ParseResult::Pong));
+        assert_eq!(r.unwrap(), 1);
+        assert!(p.parse_all(b"XXX\r\n", |_| {}).is_err());
+    }
+
+    #[test]
+    fn test_error_context() {
+        let context = |buf: &[u8]| {
+            let mut p = Parser::new(ParserOptions::default());
+            p.parse_all(buf, |_| {}).unwrap_err().context.unwrap()
+        };
+        let c = context(b"XXX\r\n");
+        assert_eq!((c.op, c.offset), (None, 0));
+        let c = context(b"PUB foo 2\r\nhiX\r\n");
+        assert_eq!((c.op, c.offset), (Some("PUB"), 13));
+        assert_eq!(c.snippet, "PUB foo 2\r\nhiX\r\n");
+        let c = context(b"PING\r\nSUB\r\n");
+        assert_eq!((c.op, c.offset), (Some("SUB"), 9));
+
+        let mut p = Parser::new(ParserOptions::default());
+        let e = p.parse_bytes(&mut BytesMut::from(&b"sub foo\r\n"[..]));
+        assert_eq!(e.unwrap_err().context.unwrap().op, Some("SUB"));
+    }
+
+    #[test]
+    fn test_stream_payload() {
+        let mut p = Parser::new(ParserOptions {
+            stream_threshold: Some(4),
+            ..ParserOptions::default()
+        });
+        let buf = b"PUB foo 3\r\nabc\r\nPUB bar 10\r\n0123456789\r\nPING\r\n";
+        let mut payload = Vec::new();
+        let mut ops = Vec::new();
+        for read in buf.chunks(7) {
+            p.parse_all(read, |res| match res {
+                ParseResult::PayloadChunk(chunk) => {
+                    // The streamed payload is never buffered.
+                    assert!(read.as_ptr_range().contains(&chunk.as_ptr()));
+                    payload.extend_from_slice(chunk)
+                }
+                ParseResult::PubStream(arg) => {
+                    assert_eq!((arg.subject, arg.size, arg.msg), ("bar", 10, &b""[..]));
+                    ops.push("PubStream".to_owned())
+                }
+                res => ops.push(format!("{:?}", res)),
+            })
+            .unwrap();
+        }
+        assert_eq!(payload, b"0123456789");
+        assert_eq!(ops.len(), 4);
+        assert!(ops[0].starts_with("Pub("));
+        assert_eq!(&ops[1..], ["PubStream", "PayloadEnd", "Ping"]);
+
+        // Nor copied when it follows the control line in the same read.
+        let mut buf = b"PUB foo 8192\r\n".to_vec();
+        buf.resize(buf.len() + 8192, b'x');
+        let (res, len) = p.parse(&buf).unwrap();
+        assert!(matches!(res, ParseResult::PubStream(_)));
+        assert!(p.pending.is_empty());
+        let (res, _) = p.parse(&buf[len..]).unwrap();
+        assert_eq!(res, ParseResult::PayloadChunk(&buf[len..]));
+        assert!(p.pending.is_empty());
+    }
+
+    #[test]
+    fn test_route_ops() {
+        let mut p = Parser::new(ParserOptions {
+            kind: ConnectionKind::Route,
+            ..ParserOptions::default()
+        });
+        let mut ops = Vec::new();
+        let buf = b"RS+ $G foo\r\nRS+ $G bar workers 3\r\nRS- $G bar workers\r\n\
+            RMSG $G foo 2\r\nhi\r\nRMSG $G foo + _INBOX.1 q1 q2 2\r\nhi\r\n\
+            RMSG $G foo | q1 0\r\n\r\nPING\r\n";
+        p.parse_all(buf, |res| ops.push(format!("{:?}", res)))
+            .unwrap();
+        assert_eq!(ops.len(), 7);
+        let mut p = Parser::new(ParserOptions {
+            kind: ConnectionKind::Route,
+            ..ParserOptions::default()
+        });
+        let (res, _) = p.parse(b"RS+ $G bar workers 3\r\n").unwrap();
+        assert_eq!(
+            res,
+            ParseResult::RsSub(RsSubArg {
+                account: "$G",
+                subject: "bar",
+                queue: Some("workers"),
+                weight: Some(3),
+            })
+        );
+        let (res, _) = p
+            .parse(b"RMSG $G foo + _INBOX.1 q1 q2 2\r\nhi\r\n")
+            .unwrap();
+        assert_eq!(
+            res,
+            ParseResult::RMsg(RMsgArg {
+                account: "$G",
+                subject: "foo",
+                reply: Some("_INBOX.1"),
+                queues: vec!["q1", "q2"],
+                size: 2,
+                msg: b"hi",
+            })
+        );
+        // Client operations are not accepted from routes, and conversely.
+        assert!(p.parse(b"PUB foo 2\r\n").is_err());
+        let mut p = Parser::new(ParserOptions::default());
+        assert!(p.parse(b"RS+ $G foo\r\n").is_err());
+        let mut p = Parser::new(ParserOptions {
+            kind: ConnectionKind::Route,
+            ..ParserOptions::default()
+        });
+        assert!(p.parse(b"RMSG $G foo + 2\r\nhi\r\n").is_err());
+
+        let mut p = Parser::new(ParserOptions {
+            kind: ConnectionKind::Route,
+            ..ParserOptions::default()
+        });
+        let mut input = BytesMut::from(&b"RMSG $G foo | q1 q2 2\r\nhi\r\n"[..]);
+        assert_eq!(
+            p.parse_bytes(&mut input).unwrap(),
+            Some(Frame::RMsg {
+                account: Bytes::from("$G"),
+                subject: Bytes::from("foo"),
+                reply: None,
+                queues: vec![Bytes::from("q1"), Bytes::from("q2")],
+                payload: Bytes::from("hi"),
+            })
+        );
+    }
+
+    #[test]
+    fn test_max_control_line() {
+        let opts = ParserOptions {
+            max_control_line: 16,
+            ..ParserOptions::default()
+        };
+        let mut p = Parser::new(opts.clone());
+        assert!(p.parse(b"SUB foo.bar 1\r\n").is_ok());
+        let r = p.parse(b"SUB foo.bar.baz.qux 1\r\n");
+        let e = r.unwrap_err();
+        assert_eq!(e.error_code, ERROR_MAX_CONTROL_LINE);
+        assert_eq!(e.response(), ErrorResponse::MaximumControlLineExceeded);
+        // Larger than the default of older versions.
+        let subject = "x".repeat(1000);
+        let mut p = Parser::new(ParserOptions::default());
+        let buf = format!("PUB {} _INBOX.{} 2\r\nhi\r\n", subject, subject);
+        assert!(matches!(
+            p.parse(buf.as_bytes()),
+            Ok((ParseResult::Pub(_), _))
+        ));
+    }
+
+    #[test]
+    fn test_pedantic() {
+        let valid: &[&[u8]] = &[
+            b"SUB foo 1\r\n",
+            b"PUB foo 2\r\nhi\r\n",
+            b"PING\r\n",
+            b"CONNECT {\"verbose\": false}\r\n",
+        ];
+        let invalid: &[&[u8]] = &[
+            b"SUB foo 1\n",
+            b"SUB  foo 1\r\n",
+            b"SUB foo  1\r\n",
+            b"SUB foo 1 \r\n",
+            b"SUB foo\r 1\r\n",
+            b"PUB foo 2\r\nhi\n",
+            b"PUB foo 2\r\nhi \r\n",
+            b"PING \r\n",
+        ];
+        let opts = ParserOptions {
+            pedantic: true,
+            ..ParserOptions::default()
+        };
+        for buf in valid {
+            let mut p = Parser::new(opts.clone());
+            assert_eq!(p.parse_all(buf, |_| {}).ok(), Some(1), "{:?}", buf);
+            let r = p.parse_bytes(&mut BytesMut::from(*buf));
+            assert!(matches!(r, Ok(Some(_))), "{:?}", buf);
+        }
+        for buf in invalid {
+            let mut p = Parser::new(ParserOptions::default());
+            assert_eq!(p.parse_all(buf, |_| {}).ok(), Some(1), "{:?}", buf);
+            p.set_pedantic(true);
+            assert!(p.parse_all(buf, |_| {}).is_err(), "{:?}", buf);
+            assert!(
+                p.parse_bytes(&mut BytesMut::from(*buf)).is_err(),
+                "{:?}",
+                buf
+            );
+        }
+    }
+
+    /// Parse `buf` split in two reads at every possible position, checking
+    /// the payload of the resulting message.
+    fn check_split_reads(buf: &[u8], payload: &[u8]) {
+        for at in 0..buf.len() {
+            let mut p = Parser::new(ParserOptions::default());
+            let (res, len) = p.parse(&buf[..at]).unwrap();
+            assert_eq!(res, ParseResult::NoMsg, "split at {}", at);
+            assert_eq!(len, at);
+            let (res, len) = p.parse(&buf[at..]).unwrap();
+            assert_eq!(len, buf.len() - at, "split at {}", at);
+            let msg = match res {
+                ParseResult::Pub(pub_arg) => pub_arg.msg,
+                ParseResult::HPub(hpub) => hpub.msg,
+                res => panic!("split at {}: {:?}", at, res),
+            };
+            assert_eq!(msg, payload, "split at {}", at);
+        }
+    }
+
+    #[test]
+    fn test_split_reads() {
+        check_split_reads(b"PUB FOO 11\r\nHello NATS!\r\n", b"Hello NATS!");
+        check_split_reads(b"HPUB FOO 18 20\r\nNATS/1.0\r\nA: B\r\n\r\nhi\r\n", b"hi");
+        // Larger than the argument buffer.
+        let payload = vec![b'x'; 2000];
+        let mut buf = b"PUB FOO 2000\r\n".to_vec();
+        buf.extend_from_slice(&payload);
+        buf.extend_from_slice(b"\r\n");
+        check_split_reads(&buf, &payload);
+    }
+
+    #[test]
+    fn test_byte_by_byte_reads() {
+        let mut p = Parser::new(ParserOptions::default());
+        let buf = b"PUB FOO 5\r\nhello\r\nPUB BAR 2\r\nhi\r\n";
+        let mut msgs = Vec::new();
+        for b in buf.iter() {
+            if let (ParseResult::Pub(pub_arg), _) = p.parse(&[*b]).unwrap() {
+                msgs.push((pub_arg.subject.to_owned(), pub_arg.msg.to_vec()));
+            }
+        }
+        assert_eq!(
+            msgs,
+            vec![
+                ("FOO".to_owned(), b"hello".to_vec()),
+                ("BAR".to_owned(), b"hi".to_vec())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_bytes() {
+        let mut p = Parser::new(ParserOptions::default());
+        let mut input = BytesMut::from(&b"PUB foo bar 5\r\nhello\r\nsub foo q 1\r\nPI"[..]);
+        let start = input.as_ptr() as usize;
+        match p.parse_bytes(&mut input).unwrap() {
+            Some(Frame::Pub {
+                subject,
+                reply,
+                payload,
+            }) => {
+                assert_eq!(subject, "foo");
+                assert_eq!(reply.unwrap(), "bar");
+                assert_eq!(payload, "hello");
+                // The payload was not copied.
+                assert_eq!(payload.as_ptr() as usize, start + 15);
+            }
+            r => panic!("{:?}", r),
+        }
+        let sub = p.parse_bytes(&mut input).unwrap().unwrap();
+        assert_eq!(
+            sub,
+            Frame::Sub {
+                subject: Bytes::from("foo"),
+                queue: Some(Bytes::from("q")),
+                sid: Bytes::from("1"),
+            }
+        );
+        assert_eq!(p.parse_bytes(&mut input).unwrap(), None);
+        assert_eq!(&input[..], b"PI");
+        input.extend_from_slice(b"NG\r\nUNSUB 1 5\r\n");
+        assert_eq!(p.parse_bytes(&mut input).unwrap(), Some(Frame::Ping));
+        assert_eq!(
+            p.parse_bytes(&mut input).unwrap(),
+            Some(Frame::Unsub {
+                sid: Bytes::from("1"),
+                max_msgs: Some(5),
+            })
+        );
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn test_parse_bytes_split_reads() {
+        let mut p = Parser::new(ParserOptions::default());
+        let buf = b"HPUB FOO 18 20\r\nNATS/1.0\r\nA: B\r\n\r\nhi\r\nPONG\r\n";
+        for at in 0..buf.len() {
+            let mut input = BytesMut::from(&buf[..at]);
+            let mut frames = Vec::new();
+            while let Some(frame) = p.parse_bytes(&mut input).unwrap() {
+                frames.push(frame);
+            }
+            input.extend_from_slice(&buf[at..]);
+            while let Some(frame) = p.parse_bytes(&mut input).unwrap() {
+                frames.push(frame);
+            }
+            assert!(input.is_empty(), "split at {}", at);
+            assert_eq!(frames.len(), 2, "split at {}", at);
+            match &frames[0] {
+                Frame::HPub {
+                    headers, payload, ..
+                } => {
+                    assert_eq!(headers, "NATS/1.0\r\nA: B\r\n\r\n");
+                    assert_eq!(payload, "hi");
+                }
+                frame => panic!("split at {}: {:?}", at, frame),
+            }
+            assert_eq!(frames[1], Frame::Pong);
+        }
+    }
+
+    #[test]
+    fn test_parse_bytes_stream() {
+        let mut p = Parser::new(ParserOptions {
+            stream_threshold: Some(4),
+            ..ParserOptions::default()
+        });
+        let mut input = BytesMut::from(&b"PUB foo 10\r\n0123"[..]);
+        assert_eq!(
+            p.parse_bytes(&mut input).unwrap(),
+            Some(Frame::PubStream {
+                subject: Bytes::from("foo"),
+                reply: None,
+                size: 10,
+            })
+        );
+        let start = input.as_ptr();
+        match p.parse_bytes(&mut input).unwrap() {
+            Some(Frame::PayloadChunk(chunk)) => {
+                assert_eq!(chunk, "0123");
+                assert_eq!(chunk.as_ptr(), start);
+            }
+            r => panic!("{:?}", r),
+        }
+        assert_eq!(p.parse_bytes(&mut input).unwrap(), None);
+        input.extend_from_slice(b"456789\r");
+        assert_eq!(
+            p.parse_bytes(&mut input).unwrap(),
+            Some(Frame::PayloadChunk(Bytes::from("456789")))
+        );
+        assert_eq!(p.parse_bytes(&mut input).unwrap(), None);
+        input.extend_from_slice(b"\nPING\r\n");
+        assert_eq!(p.parse_bytes(&mut input).unwrap(), Some(Frame::PayloadEnd));
+        assert_eq!(p.parse_bytes(&mut input).unwrap(), Some(Frame::Ping));
+    }
+
+    #[test]
+    fn test_parse_bytes_invalid() {
+        let mut p = Parser::new(ParserOptions {
+            max_payload: 4,
+            ..ParserOptions::default()
+        });
+        for buf in [
+            &b"XXX\r\n"[..],
+            b"SUB foo\r\n",
+            b"PING 1\r\n",
+            b"PUB foo x\r\n",
+            b"PUB foo 2\r\nhiX\r\n",
+            b"HPUB foo 2 2\r\n",
+        ] {
+            assert!(
+                p.parse_bytes(&mut BytesMut::from(buf)).is_err(),
+                "{:?}",
+                buf
+            );
         }
+        let r = p.parse_bytes(&mut BytesMut::from(&b"PUB foo 5\r\n"[..]));
+        assert_eq!(r.unwrap_err().error_code, ERROR_MESSAGE_SIZE_TOO_LARGE);
+        let mut long = BytesMut::from(&b"SUB "[..]);
+        long.resize(DEFAULT_MAX_CONTROL_LINE, b'x');
+        let r = p.parse_bytes(&mut long);
+        assert_eq!(r.unwrap_err().error_code, ERROR_MAX_CONTROL_LINE);
     }
 }
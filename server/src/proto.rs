@@ -0,0 +1,4 @@
+//! Protocol operations sent by the server.
+
+pub mod encode;
+pub mod errors;
@@ -0,0 +1,251 @@
+//! Writers appending server operations to an outbound buffer, such as a
+//! `Vec<u8>` or a `BytesMut`, and client operations for tests.
+//!
+//! Sizes are formatted without allocating, so that the control line of a
+//! message costs no more than copying its subject.
+
+use bytes::BufMut;
+use serde::Serialize;
+
+const CRLF: &[u8] = b"\r\n";
+pub const OK: &[u8] = b"+OK\r\n";
+pub const PING: &[u8] = b"PING\r\n";
+pub const PONG: &[u8] = b"PONG\r\n";
+/// Version line starting every header block.
+pub const HEADER_VERSION: &[u8] = b"NATS/1.0\r\n";
+
+fn put_usize<B: BufMut>(buf: &mut B, n: usize) {
+    buf.put_slice(itoa::Buffer::new().format(n).as_bytes());
+}
+
+/// Append the control line of a MSG whose payload has `size` bytes, which
+/// must follow with a CRLF, see `msg_end()`.
+pub fn msg_header<B: BufMut>(
+    buf: &mut B,
+    subject: &[u8],
+    sid: &[u8],
+    reply: Option<&[u8]>,
+    size: usize,
+) {
+    buf.put_slice(b"MSG ");
+    buf.put_slice(subject);
+    buf.put_u8(b' ');
+    buf.put_slice(sid);
+    buf.put_u8(b' ');
+    if let Some(reply) = reply {
+        buf.put_slice(reply);
+        buf.put_u8(b' ');
+    }
+    put_usize(buf, size);
+    buf.put_slice(CRLF);
+}
+
+/// Append the end of a message, following its payload.
+pub fn msg_end<B: BufMut>(buf: &mut B) {
+    buf.put_slice(CRLF);
+}
+
+/// Append `MSG <subject> <sid> [reply] <size>` and its payload.
+pub fn msg<B: BufMut>(
+    buf: &mut B,
+    subject: &[u8],
+    sid: &[u8],
+    reply: Option<&[u8]>,
+    payload: &[u8],
+) {
+    msg_header(buf, subject, sid, reply, payload.len());
+    buf.put_slice(payload);
+    msg_end(buf);
+}
+
+/// Append the control line of an HMSG whose header block has `header_size`
+/// bytes, and its payload `total_size` with the headers. They must follow,
+/// with a CRLF, see `msg_end()`.
+pub fn hmsg_header<B: BufMut>(
+    buf: &mut B,
+    subject: &[u8],
+    sid: &[u8],
+    reply: Option<&[u8]>,
+    header_size: usize,
+    total_size: usize,
+) {
+    buf.put_slice(b"HMSG ");
+    buf.put_slice(subject);
+    buf.put_u8(b' ');
+    buf.put_slice(sid);
+    buf.put_u8(b' ');
+    if let Some(reply) = reply {
+        buf.put_slice(reply);
+        buf.put_u8(b' ');
+    }
+    put_usize(buf, header_size);
+    buf.put_u8(b' ');
+    put_usize(buf, total_size);
+    buf.put_slice(CRLF);
+}
+
+/// Append `HMSG <subject> <sid> [reply] <header size> <total size>`, the
+/// header block `headers`, as returned by `header_block()`, and the payload.
+pub fn hmsg<B: BufMut>(
+    buf: &mut B,
+    subject: &[u8],
+    sid: &[u8],
+    reply: Option<&[u8]>,
+    headers: &[u8],
+    payload: &[u8],
+) {
+    let total_size = headers.len() + payload.len();
+    hmsg_header(buf, subject, sid, reply, headers.len(), total_size);
+    buf.put_slice(headers);
+    buf.put_slice(payload);
+    msg_end(buf);
+}
+
+/// Append a header block: the version line, a `Name: Value` line per
+/// header, and an empty line.
+pub fn header_block<B: BufMut>(buf: &mut B, headers: &[(&str, &str)]) {
+    buf.put_slice(HEADER_VERSION);
+    for (name, value) in headers {
+        buf.put_slice(name.as_bytes());
+        buf.put_slice(b": ");
+        buf.put_slice(value.as_bytes());
+        buf.put_slice(CRLF);
+    }
+    buf.put_slice(CRLF);
+}
+
+/// Append `INFO` followed by `info` in JSON.
+pub fn info<B: BufMut, T: Serialize>(buf: &mut B, info: &T) -> serde_json::Result<()> {
+    buf.put_slice(b"INFO ");
+    serde_json::to_writer(buf.writer(), info)?;
+    buf.put_slice(CRLF);
+    Ok(())
+}
+
+/// Append `-ERR '<message>'`.
+pub fn err<B: BufMut>(buf: &mut B, message: &str) {
+    buf.put_slice(b"-ERR '");
+    buf.put_slice(message.as_bytes());
+    buf.put_slice(b"'\r\n");
+}
+
+pub fn ok<B: BufMut>(buf: &mut B) {
+    buf.put_slice(OK);
+}
+
+pub fn ping<B: BufMut>(buf: &mut B) {
+    buf.put_slice(PING);
+}
+
+pub fn pong<B: BufMut>(buf: &mut B) {
+    buf.put_slice(PONG);
+}
+
+/// Append `PUB <subject> [reply] <size>` and its payload.
+pub fn publish<B: BufMut>(buf: &mut B, subject: &[u8], reply: Option<&[u8]>, payload: &[u8]) {
+    buf.put_slice(b"PUB ");
+    buf.put_slice(subject);
+    buf.put_u8(b' ');
+    if let Some(reply) = reply {
+        buf.put_slice(reply);
+        buf.put_u8(b' ');
+    }
+    put_usize(buf, payload.len());
+    buf.put_slice(CRLF);
+    buf.put_slice(payload);
+    buf.put_slice(CRLF);
+}
+
+/// Append `HPUB <subject> [reply] <header size> <total size>`, the header
+/// block `headers` and the payload.
+pub fn hpublish<B: BufMut>(
+    buf: &mut B,
+    subject: &[u8],
+    reply: Option<&[u8]>,
+    headers: &[u8],
+    payload: &[u8],
+) {
+    buf.put_slice(b"HPUB ");
+    buf.put_slice(subject);
+    buf.put_u8(b' ');
+    if let Some(reply) = reply {
+        buf.put_slice(reply);
+        buf.put_u8(b' ');
+    }
+    put_usize(buf, headers.len());
+    buf.put_u8(b' ');
+    put_usize(buf, headers.len() + payload.len());
+    buf.put_slice(CRLF);
+    buf.put_slice(headers);
+    buf.put_slice(payload);
+    buf.put_slice(CRLF);
+}
+
+/// Append `SUB <subject> [queue] <sid>`.
+pub fn subscribe<B: BufMut>(buf: &mut B, subject: &[u8], queue: Option<&[u8]>, sid: &[u8]) {
+    buf.put_slice(b"SUB ");
+    buf.put_slice(subject);
+    buf.put_u8(b' ');
+    if let Some(queue) = queue {
+        buf.put_slice(queue);
+        buf.put_u8(b' ');
+    }
+    buf.put_slice(sid);
+    buf.put_slice(CRLF);
+}
+
+/// Append `UNSUB <sid> [max_msgs]`.
+pub fn unsubscribe<B: BufMut>(buf: &mut B, sid: &[u8], max_msgs: Option<usize>) {
+    buf.put_slice(b"UNSUB ");
+    buf.put_slice(sid);
+    if let Some(max_msgs) = max_msgs {
+        buf.put_u8(b' ');
+        put_usize(buf, max_msgs);
+    }
+    buf.put_slice(CRLF);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BytesMut;
+
+    #[test]
+    fn test_msg() {
+        let mut buf = Vec::new();
+        msg(&mut buf, b"foo.bar", b"9", None, b"Hello");
+        msg(&mut buf, b"foo", b"10", Some(b"_INBOX.1"), b"");
+        assert_eq!(
+            buf,
+            b"MSG foo.bar 9 5\r\nHello\r\nMSG foo 10 _INBOX.1 0\r\n\r\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn test_hmsg() {
+        let mut headers = BytesMut::new();
+        header_block(&mut headers, &[("Key", "Value")]);
+        assert_eq!(&headers[..], b"NATS/1.0\r\nKey: Value\r\n\r\n");
+        let mut buf = BytesMut::new();
+        hmsg(&mut buf, b"foo", b"1", Some(b"bar"), &headers, b"hi");
+        assert_eq!(
+            &buf[..],
+            &b"HMSG foo 1 bar 24 26\r\nNATS/1.0\r\nKey: Value\r\n\r\nhi\r\n"[..]
+        );
+    }
+
+    #[test]
+    fn test_control() {
+        let mut buf = Vec::new();
+        info(&mut buf, &serde_json::json!({ "max_payload": 1048576 })).unwrap();
+        err(&mut buf, "Unknown Protocol Operation");
+        ok(&mut buf);
+        ping(&mut buf);
+        pong(&mut buf);
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "INFO {\"max_payload\":1048576}\r\n-ERR 'Unknown Protocol Operation'\r\n\
+             +OK\r\nPING\r\nPONG\r\n"
+        );
+    }
+}
@@ -0,0 +1,113 @@
+//! Errors sent to clients in `-ERR`, with the messages of the NATS protocol.
+//!
+//! Most of them are followed by the server closing the connection, the
+//! exceptions being an invalid subject and a permissions violation which
+//! only fail the operation.
+
+use crate::error::*;
+use crate::proto::encode;
+use bytes::BufMut;
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorResponse {
+    UnknownProtocolOperation,
+    AttemptedToConnectToRoutePort,
+    AuthorizationViolation,
+    AuthorizationTimeout,
+    InvalidClientProtocol,
+    MaximumControlLineExceeded,
+    ParserError,
+    SecureConnectionTlsRequired,
+    StaleConnection,
+    MaximumConnectionsExceeded,
+    SlowConsumer,
+    MaximumPayloadViolation,
+    InvalidSubject,
+    /// Subscribing to the subject is not allowed.
+    SubscriptionPermissionsViolation(String),
+    /// Publishing to the subject is not allowed.
+    PublishPermissionsViolation(String),
+}
+
+impl ErrorResponse {
+    /// Whether the server closes the connection after sending the error.
+    pub fn closes_connection(&self) -> bool {
+        use ErrorResponse::*;
+        !matches!(
+            self,
+            InvalidSubject | SubscriptionPermissionsViolation(_) | PublishPermissionsViolation(_)
+        )
+    }
+
+    /// Append `-ERR '<message>'` to `buf`.
+    pub fn encode<B: BufMut>(&self, buf: &mut B) {
+        encode::err(buf, &self.to_string());
+    }
+}
+
+impl Display for ErrorResponse {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        use ErrorResponse::*;
+        let message = match self {
+            UnknownProtocolOperation => "Unknown Protocol Operation",
+            AttemptedToConnectToRoutePort => "Attempted To Connect To Route Port",
+            AuthorizationViolation => "Authorization Violation",
+            AuthorizationTimeout => "Authorization Timeout",
+            InvalidClientProtocol => "Invalid Client Protocol",
+            MaximumControlLineExceeded => "Maximum Control Line Exceeded",
+            ParserError => "Parser Error",
+            SecureConnectionTlsRequired => "Secure Connection - TLS Required",
+            StaleConnection => "Stale Connection",
+            MaximumConnectionsExceeded => "Maximum Connections Exceeded",
+            SlowConsumer => "Slow Consumer",
+            MaximumPayloadViolation => "Maximum Payload Violation",
+            InvalidSubject => "Invalid Subject",
+            SubscriptionPermissionsViolation(subject) => {
+                return write!(f, "Permissions Violation for Subscription to {}", subject)
+            }
+            PublishPermissionsViolation(subject) => {
+                return write!(f, "Permissions Violation for Publish to {}", subject)
+            }
+        };
+        f.write_str(message)
+    }
+}
+
+impl From<&NError> for ErrorResponse {
+    fn from(e: &NError) -> Self {
+        match e.error_code {
+            ERROR_PARSE => ErrorResponse::UnknownProtocolOperation,
+            ERROR_MESSAGE_SIZE_TOO_LARGE => ErrorResponse::MaximumPayloadViolation,
+            ERROR_INVALID_SUBJECT => ErrorResponse::InvalidSubject,
+            ERROR_MAX_CONTROL_LINE => ErrorResponse::MaximumControlLineExceeded,
+            _ => ErrorResponse::ParserError,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let mut buf = Vec::new();
+        ErrorResponse::MaximumPayloadViolation.encode(&mut buf);
+        ErrorResponse::SubscriptionPermissionsViolation("foo.>".to_owned()).encode(&mut buf);
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "-ERR 'Maximum Payload Violation'\r\n\
+             -ERR 'Permissions Violation for Subscription to foo.>'\r\n"
+        );
+    }
+
+    #[test]
+    fn test_from_error() {
+        let e = ErrorResponse::from(&NError::new(ERROR_PARSE));
+        assert_eq!(e, ErrorResponse::UnknownProtocolOperation);
+        assert!(e.closes_connection());
+        let e = ErrorResponse::from(&NError::new(ERROR_INVALID_SUBJECT));
+        assert!(!e.closes_connection());
+    }
+}
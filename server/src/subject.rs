@@ -0,0 +1,117 @@
+//! Validation of the subjects of parsed operations.
+//!
+//! Subjects are made of non-empty tokens separated by `.`, without
+//! whitespace. Subscriptions may use wildcards: `*` matches a single token
+//! and `>` the remaining ones, so it must be the last token. Messages are
+//! published to literal subjects only.
+
+use crate::error::*;
+use crate::parser::Frame;
+
+pub(crate) const TOKEN_SEPARATOR: u8 = b'.';
+pub(crate) const SINGLE_WILDCARD: &[u8] = b"*";
+pub(crate) const FULL_WILDCARD: &[u8] = b">";
+
+/// Whether `subject` is valid for a subscription, wildcards included.
+pub fn is_valid(subject: &[u8]) -> bool {
+    let mut tokens = subject.split(|b| *b == TOKEN_SEPARATOR).peekable();
+    while let Some(token) = tokens.next() {
+        if token.is_empty() || token.iter().any(|b| b.is_ascii_whitespace()) {
+            return false;
+        }
+        let wildcard = token.contains(&b'*') || token.contains(&b'>');
+        if wildcard
+            && token != SINGLE_WILDCARD
+            && !(token == FULL_WILDCARD && tokens.peek().is_none())
+        {
+            return false;
+        }
+    }
+    true
+}
+
+/// Whether `subject` is valid and has no wildcard, as required to publish.
+pub fn is_valid_literal(subject: &[u8]) -> bool {
+    is_valid(subject)
+        && subject
+            .split(|b| *b == TOKEN_SEPARATOR)
+            .all(|token| token != SINGLE_WILDCARD && token != FULL_WILDCARD)
+}
+
+fn is_valid_publish(subject: &[u8], reply: Option<&[u8]>) -> bool {
+    is_valid_literal(subject) && reply.is_none_or(is_valid_literal)
+}
+
+/// Check the subjects of a parsed operation, failing with
+/// `ERROR_INVALID_SUBJECT`.
+pub fn check(frame: &Frame) -> Result<(), NError> {
+    let valid = match frame {
+        Frame::Pub { subject, reply, .. }
+        | Frame::HPub { subject, reply, .. }
+        | Frame::PubStream { subject, reply, .. } => is_valid_publish(subject, reply.as_deref()),
+        Frame::Sub { subject, .. } => is_valid(subject),
+        _ => true,
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(NError::new(ERROR_INVALID_SUBJECT))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{Parser, ParserOptions};
+    use bytes::BytesMut;
+
+    #[test]
+    fn test_is_valid() {
+        for subject in &["foo", "foo.bar", "foo.*", "*.bar.>", ">", "foo.*.baz"] {
+            assert!(is_valid(subject.as_bytes()), "{}", subject);
+        }
+        for subject in &[
+            "",
+            ".",
+            "foo.",
+            ".foo",
+            "foo..bar",
+            "foo.>.bar",
+            "foo*",
+            "foo.b>",
+            "a b",
+        ] {
+            assert!(!is_valid(subject.as_bytes()), "{}", subject);
+        }
+    }
+
+    #[test]
+    fn test_is_valid_literal() {
+        assert!(is_valid_literal(b"time.us.east"));
+        assert!(!is_valid_literal(b"time.*.east"));
+        assert!(!is_valid_literal(b"foo.>"));
+        assert!(!is_valid_literal(b"foo..bar"));
+    }
+
+    #[test]
+    fn test_check() {
+        let mut p = Parser::new(ParserOptions::default());
+        let mut parse = |buf: &[u8]| p.parse_bytes(&mut BytesMut::from(buf)).unwrap().unwrap();
+        let e = check(&parse(b"PUB foo.> 2\r\nhi\r\n")).unwrap_err();
+        assert_eq!(e.error_code, ERROR_INVALID_SUBJECT);
+        assert!(!e.response().closes_connection());
+        assert!(check(&parse(b"SUB foo.> 1\r\n")).is_ok());
+        assert!(check(&parse(b"PUB foo _INBOX.* 2\r\nhi\r\n")).is_err());
+
+        // Streamed payloads too.
+        for buf in &[&b"PUB foo.> 2\r\n"[..], b"PUB foo..bar 2\r\n"] {
+            let mut p = Parser::new(ParserOptions {
+                stream_threshold: Some(1),
+                ..ParserOptions::default()
+            });
+            let frame = p.parse_bytes(&mut BytesMut::from(*buf)).unwrap().unwrap();
+            assert!(matches!(frame, Frame::PubStream { .. }));
+            assert_eq!(check(&frame).unwrap_err().error_code, ERROR_INVALID_SUBJECT);
+        }
+    }
+}
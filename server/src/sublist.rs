@@ -0,0 +1,457 @@
+//! Subscriptions indexed by subject, finding those a message is delivered
+//! to.
+//!
+//! The subjects are stored in a trie with a level per token, the wildcards
+//! having their own branches so that matching a subject only follows the
+//! literal token, `*` and `>` at each level.
+//!
+//! The members of a queue group share the messages: each is delivered to
+//! only one of them, the other subscriptions all getting a copy.
+
+use crate::error::*;
+use crate::subject::{self, FULL_WILDCARD, SINGLE_WILDCARD, TOKEN_SEPARATOR};
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+
+#[derive(Debug)]
+struct Level<T> {
+    literals: HashMap<String, Node<T>>,
+    /// Branch of `*`.
+    pwc: Option<Box<Node<T>>>,
+    /// Branch of `>`, always a leaf.
+    fwc: Option<Box<Node<T>>>,
+}
+
+#[derive(Debug)]
+struct Node<T> {
+    next: Level<T>,
+    /// Subscriptions whose subject ends at this node, outside of queue
+    /// groups.
+    subs: Vec<T>,
+    /// Members of the queue groups whose subject ends at this node, by
+    /// queue.
+    qsubs: HashMap<String, Vec<T>>,
+}
+
+impl<T> Default for Level<T> {
+    fn default() -> Self {
+        Self {
+            literals: HashMap::new(),
+            pwc: None,
+            fwc: None,
+        }
+    }
+}
+
+impl<T> Default for Node<T> {
+    fn default() -> Self {
+        Self {
+            next: Level::default(),
+            subs: Vec::new(),
+            qsubs: HashMap::new(),
+        }
+    }
+}
+
+impl<T> Level<T> {
+    fn is_empty(&self) -> bool {
+        self.literals.is_empty() && self.pwc.is_none() && self.fwc.is_none()
+    }
+
+    fn node_mut(&mut self, token: &str) -> &mut Node<T> {
+        match token.as_bytes() {
+            SINGLE_WILDCARD => self.pwc.get_or_insert_with(Box::default),
+            FULL_WILDCARD => self.fwc.get_or_insert_with(Box::default),
+            _ => self.literals.entry(token.to_owned()).or_default(),
+        }
+    }
+}
+
+impl<T> Node<T> {
+    fn is_empty(&self) -> bool {
+        self.subs.is_empty() && self.qsubs.is_empty() && self.next.is_empty()
+    }
+
+    fn subs_mut(&mut self, queue: Option<&str>) -> &mut Vec<T> {
+        match queue {
+            Some(queue) => self.qsubs.entry(queue.to_owned()).or_default(),
+            None => &mut self.subs,
+        }
+    }
+
+    /// Remove `sub`, returning whether it was there.
+    fn remove(&mut self, queue: Option<&str>, sub: &T) -> bool
+    where
+        T: PartialEq,
+    {
+        let subs = match queue {
+            Some(queue) => match self.qsubs.get_mut(queue) {
+                Some(subs) => subs,
+                None => return false,
+            },
+            None => &mut self.subs,
+        };
+        let i = match subs.iter().position(|s| s == sub) {
+            Some(i) => i,
+            None => return false,
+        };
+        subs.remove(i);
+        if let Some(queue) = queue.filter(|_| subs.is_empty()) {
+            self.qsubs.remove(queue);
+        }
+        true
+    }
+
+    fn add_to(&self, matches: &mut Matches<T>)
+    where
+        T: Clone,
+    {
+        matches.subs.extend_from_slice(&self.subs);
+        for (queue, members) in &self.qsubs {
+            // Groups of the same queue on matching subjects are one group.
+            match matches.qsubs.get_mut(queue) {
+                Some(group) => group.extend_from_slice(members),
+                None => {
+                    matches.qsubs.insert(queue.clone(), members.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Subscriptions matching a subject.
+#[derive(Debug, PartialEq)]
+pub struct Matches<T> {
+    /// Subscriptions outside of queue groups.
+    pub subs: Vec<T>,
+    /// Members of the queue groups, by queue.
+    pub qsubs: HashMap<String, Vec<T>>,
+}
+
+impl<T> Default for Matches<T> {
+    fn default() -> Self {
+        Self {
+            subs: Vec::new(),
+            qsubs: HashMap::new(),
+        }
+    }
+}
+
+impl<T: Clone> Matches<T> {
+    pub fn is_empty(&self) -> bool {
+        self.subs.is_empty() && self.qsubs.is_empty()
+    }
+
+    /// Keep the subscriptions for which `f` returns `true`, dropping the
+    /// groups left empty.
+    pub fn retain<F: FnMut(&T) -> bool>(&mut self, mut f: F) {
+        self.subs.retain(&mut f);
+        self.qsubs.retain(|_, members| {
+            members.retain(&mut f);
+            !members.is_empty()
+        });
+    }
+
+    /// Subscriptions a message is delivered to: all those outside of queue
+    /// groups, and a member of each group picked at random.
+    pub fn recipients(&self) -> Vec<T> {
+        let mut recipients = self.subs.clone();
+        for members in self.qsubs.values() {
+            recipients.push(members[random_index(members.len())].clone());
+        }
+        recipients
+    }
+}
+
+thread_local! {
+    static RNG: Cell<u64> = Cell::new(RandomState::new().build_hasher().finish() | 1);
+}
+
+/// Random index below `len`, not for cryptographic use.
+fn random_index(len: usize) -> usize {
+    RNG.with(|rng| {
+        // xorshift64
+        let mut x = rng.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        rng.set(x);
+        (x % len as u64) as usize
+    })
+}
+
+/// Subscriptions of type `T` by subject, wildcards included.
+#[derive(Debug)]
+pub struct Sublist<T> {
+    root: Level<T>,
+    count: usize,
+}
+
+impl<T> Default for Sublist<T> {
+    fn default() -> Self {
+        Self {
+            root: Level::default(),
+            count: 0,
+        }
+    }
+}
+
+impl<T: Clone + PartialEq> Sublist<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of subscriptions.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Add `sub` to `subject`, as a member of `queue` if any. Fails with
+    /// `ERROR_INVALID_SUBJECT`.
+    pub fn insert(&mut self, subject: &str, queue: Option<&str>, sub: T) -> Result<(), NError> {
+        if !subject::is_valid(subject.as_bytes()) {
+            return Err(NError::new(ERROR_INVALID_SUBJECT));
+        }
+        let mut level = &mut self.root;
+        let mut tokens = tokens(subject).peekable();
+        while let Some(token) = tokens.next() {
+            let node = level.node_mut(token);
+            if tokens.peek().is_none() {
+                node.subs_mut(queue).push(sub);
+                self.count += 1;
+                return Ok(());
+            }
+            level = &mut node.next;
+        }
+        unreachable!("valid subjects have a token")
+    }
+
+    /// Remove `sub` from `subject` and `queue`, returning whether it was
+    /// there.
+    pub fn remove(&mut self, subject: &str, queue: Option<&str>, sub: &T) -> bool {
+        let tokens: Vec<&str> = tokens(subject).collect();
+        let removed = remove(&mut self.root, &tokens, queue, sub);
+        if removed {
+            self.count -= 1;
+        }
+        removed
+    }
+
+    /// Subscriptions receiving the messages published to the literal
+    /// `subject`.
+    pub fn matches(&self, subject: &str) -> Matches<T> {
+        let mut matches = Matches::default();
+        if subject::is_valid_literal(subject.as_bytes()) {
+            let tokens: Vec<&str> = tokens(subject).collect();
+            collect(&self.root, &tokens, &mut matches);
+        }
+        matches
+    }
+}
+
+fn tokens(subject: &str) -> impl Iterator<Item = &str> {
+    subject.split(TOKEN_SEPARATOR as char)
+}
+
+/// Remove `sub` from the node of `tokens` under `level`, pruning the nodes
+/// left empty.
+fn remove<T: PartialEq>(
+    level: &mut Level<T>,
+    tokens: &[&str],
+    queue: Option<&str>,
+    sub: &T,
+) -> bool {
+    let (token, rest) = match tokens.split_first() {
+        Some(split) => split,
+        None => return false,
+    };
+    let (removed, empty) = {
+        let node = match token.as_bytes() {
+            SINGLE_WILDCARD => level.pwc.as_deref_mut(),
+            FULL_WILDCARD => level.fwc.as_deref_mut(),
+            _ => level.literals.get_mut(*token),
+        };
+        let node = match node {
+            Some(node) => node,
+            None => return false,
+        };
+        let removed = if rest.is_empty() {
+            node.remove(queue, sub)
+        } else {
+            remove(&mut node.next, rest, queue, sub)
+        };
+        (removed, node.is_empty())
+    };
+    if empty {
+        match token.as_bytes() {
+            SINGLE_WILDCARD => level.pwc = None,
+            FULL_WILDCARD => level.fwc = None,
+            _ => {
+                level.literals.remove(*token);
+            }
+        }
+    }
+    removed
+}
+
+/// Add to `matches` the subscriptions under `level` matching `tokens`.
+fn collect<T: Clone>(level: &Level<T>, tokens: &[&str], matches: &mut Matches<T>) {
+    let (token, rest) = match tokens.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    // `>` matches one or more tokens, there being at least this one.
+    if let Some(fwc) = &level.fwc {
+        fwc.add_to(matches);
+    }
+    let nodes = level
+        .literals
+        .get(*token)
+        .into_iter()
+        .chain(level.pwc.as_deref());
+    for node in nodes {
+        if rest.is_empty() {
+            node.add_to(matches);
+        } else {
+            collect(&node.next, rest, matches);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut subs: Vec<u32>) -> Vec<u32> {
+        subs.sort_unstable();
+        subs
+    }
+
+    #[test]
+    fn test_matches() {
+        let mut s = Sublist::new();
+        s.insert("time.us.east", None, 1).unwrap();
+        s.insert("time.>", None, 2).unwrap();
+        s.insert("time.*.east", None, 3).unwrap();
+        s.insert("time.*", None, 4).unwrap();
+        s.insert(">", None, 5).unwrap();
+        s.insert("*.*.*", None, 6).unwrap();
+        s.insert("time.us.west", None, 7).unwrap();
+        assert_eq!(s.count(), 7);
+        assert_eq!(sorted(s.matches("time.us.east").subs), [1, 2, 3, 5, 6]);
+        assert_eq!(sorted(s.matches("time.us").subs), [2, 4, 5]);
+        assert_eq!(sorted(s.matches("time").subs), [5]);
+        assert_eq!(sorted(s.matches("time.us.east.1").subs), [2, 5]);
+        assert_eq!(sorted(s.matches("weather").subs), [5]);
+        // Only literal subjects are published to.
+        assert!(s.matches("time.*").is_empty());
+        assert!(s.matches("time..us").is_empty());
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let mut s = Sublist::new();
+        s.insert("foo", None, 1).unwrap();
+        s.insert("foo", None, 1).unwrap();
+        s.insert("foo", None, 2).unwrap();
+        assert_eq!(sorted(s.matches("foo").subs), [1, 1, 2]);
+        assert!(s.remove("foo", None, &1));
+        assert_eq!(sorted(s.matches("foo").subs), [1, 2]);
+        assert_eq!(s.count(), 2);
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut s = Sublist::new();
+        s.insert("foo.bar", None, 1).unwrap();
+        s.insert("foo.*", None, 2).unwrap();
+        s.insert("foo.>", None, 3).unwrap();
+        assert!(!s.remove("foo.bar", None, &2));
+        assert!(!s.remove("foo", None, &1));
+        assert!(!s.remove("foo.bar.baz", None, &1));
+        assert!(s.remove("foo.*", None, &2));
+        assert!(!s.remove("foo.*", None, &2));
+        assert_eq!(sorted(s.matches("foo.bar").subs), [1, 3]);
+        assert!(s.remove("foo.bar", None, &1));
+        assert!(s.remove("foo.>", None, &3));
+        assert!(s.matches("foo.bar").is_empty());
+        assert_eq!(s.count(), 0);
+        // The emptied nodes are pruned.
+        assert!(s.root.is_empty());
+    }
+
+    #[test]
+    fn test_queues() {
+        let mut s = Sublist::new();
+        s.insert("foo.bar", None, 1).unwrap();
+        s.insert("foo.bar", Some("workers"), 2).unwrap();
+        s.insert("foo.*", Some("workers"), 3).unwrap();
+        s.insert("foo.>", Some("other"), 4).unwrap();
+        s.insert("foo.baz", Some("workers"), 5).unwrap();
+        let matches = s.matches("foo.bar");
+        assert_eq!(matches.subs, [1]);
+        assert_eq!(matches.qsubs.len(), 2);
+        assert_eq!(sorted(matches.qsubs["workers"].clone()), [2, 3]);
+        assert_eq!(matches.qsubs["other"], [4]);
+
+        assert!(!s.remove("foo.bar", None, &2));
+        assert!(!s.remove("foo.bar", Some("other"), &2));
+        assert!(s.remove("foo.bar", Some("workers"), &2));
+        assert!(s.remove("foo.>", Some("other"), &4));
+        let matches = s.matches("foo.bar");
+        assert_eq!(matches.qsubs.len(), 1);
+        assert_eq!(matches.qsubs["workers"], [3]);
+    }
+
+    #[test]
+    fn test_recipients() {
+        let mut s = Sublist::new();
+        s.insert("foo", None, 0).unwrap();
+        s.insert(">", None, 1).unwrap();
+        for member in 10..13 {
+            s.insert("foo", Some("workers"), member).unwrap();
+        }
+        s.insert("*", Some("other"), 20).unwrap();
+        let matches = s.matches("foo");
+        let mut delivered = HashMap::new();
+        for _ in 0..300 {
+            let recipients = sorted(matches.recipients());
+            // Every subscription outside of groups and a member per group.
+            assert_eq!(recipients.len(), 4);
+            assert_eq!(recipients[..2], [0, 1]);
+            assert!((10..13).contains(&recipients[2]));
+            assert_eq!(recipients[3], 20);
+            *delivered.entry(recipients[2]).or_insert(0) += 1;
+        }
+        // The members share the messages.
+        assert_eq!(delivered.len(), 3);
+        assert!(delivered.values().all(|&n| n > 50), "{:?}", delivered);
+    }
+
+    #[test]
+    fn test_retain() {
+        let mut s = Sublist::new();
+        s.insert("foo", None, 1).unwrap();
+        s.insert("foo", None, 2).unwrap();
+        s.insert("foo", Some("workers"), 3).unwrap();
+        s.insert("foo", Some("workers"), 4).unwrap();
+        s.insert("foo", Some("other"), 5).unwrap();
+        let mut matches = s.matches("foo");
+        matches.retain(|&sub| sub % 2 == 0);
+        assert_eq!(matches.subs, [2]);
+        assert_eq!(matches.qsubs.len(), 1);
+        assert_eq!(matches.recipients(), [2, 4]);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let mut s = Sublist::new();
+        for subject in &["", "foo.", "foo..bar", "foo.>.bar", "fo*"] {
+            let e = s.insert(subject, None, 1).unwrap_err();
+            assert_eq!(e.error_code, ERROR_INVALID_SUBJECT);
+        }
+        assert_eq!(s.count(), 0);
+    }
+}
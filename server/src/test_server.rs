@@ -0,0 +1,80 @@
+//! Server listening on an ephemeral port of the loopback interface for the
+//! duration of a test, to test clients end to end against it.
+
+use crate::config::ServerConfig;
+use crate::net::Server;
+use std::io;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// Server running on its own thread, shut down when dropped.
+pub struct TestServer {
+    server: Arc<Server>,
+    url: String,
+    thread: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl TestServer {
+    /// Start a server with the default configuration.
+    pub fn start() -> io::Result<Self> {
+        Self::with_config(ServerConfig::default())
+    }
+
+    /// Start a server with `config`, listening on 127.0.0.1 and a port picked
+    /// by the system whatever its address.
+    pub fn with_config(config: ServerConfig) -> io::Result<Self> {
+        let config = ServerConfig {
+            host: "127.0.0.1".to_owned(),
+            port: 0,
+            ..config
+        };
+        let server = Arc::new(Server::bind(config)?);
+        let url = format!("nats://{}", server.local_addr()?);
+        let running = server.clone();
+        let thread = thread::spawn(move || running.run());
+        Ok(Self {
+            server,
+            url,
+            thread: Some(thread),
+        })
+    }
+
+    /// URL to connect to, `nats://127.0.0.1:<port>`.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn server(&self) -> &Server {
+        &self.server
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.server.shutdown();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpStream;
+
+    #[test]
+    fn test_start() {
+        let server = TestServer::start().unwrap();
+        let addr = server.url().strip_prefix("nats://").unwrap();
+        let mut reader = BufReader::new(TcpStream::connect(addr).unwrap());
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert!(line.starts_with("INFO {"), "{}", line);
+        // Its connections are closed with it.
+        drop(server);
+        line.clear();
+        assert_eq!(reader.read_line(&mut line).unwrap(), 0);
+    }
+}
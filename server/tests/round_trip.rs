@@ -0,0 +1,175 @@
+//! Random streams of valid client operations, split in random reads, must be
+//! parsed into operations encoding back to the same bytes.
+
+use proptest::collection::vec;
+use proptest::option;
+use proptest::prelude::*;
+use server::parser::{ParseResult, Parser, ParserOptions};
+use server::proto::encode;
+
+#[derive(Debug, Clone)]
+enum Op {
+    Pub {
+        subject: String,
+        reply: Option<String>,
+        payload: Vec<u8>,
+    },
+    HPub {
+        subject: String,
+        reply: Option<String>,
+        headers: Vec<(String, String)>,
+        payload: Vec<u8>,
+    },
+    Sub {
+        subject: String,
+        queue: Option<String>,
+        sid: String,
+    },
+    Unsub {
+        sid: String,
+        max_msgs: Option<usize>,
+    },
+}
+
+fn subject() -> impl Strategy<Value = String> {
+    "[a-zA-Z0-9_]{1,8}(\\.[a-zA-Z0-9_]{1,8}){0,3}"
+}
+
+fn sid() -> impl Strategy<Value = String> {
+    "[0-9]{1,6}"
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    let payload = vec(any::<u8>(), 0..300);
+    prop_oneof![
+        (subject(), option::of(subject()), payload.clone()).prop_map(
+            |(subject, reply, payload)| Op::Pub {
+                subject,
+                reply,
+                payload
+            }
+        ),
+        (
+            subject(),
+            option::of(subject()),
+            vec(("[A-Za-z][A-Za-z0-9-]{0,11}", "[!-9;-~]{0,16}"), 0..4),
+            payload
+        )
+            .prop_map(|(subject, reply, headers, payload)| Op::HPub {
+                subject,
+                reply,
+                headers,
+                payload
+            }),
+        (subject(), option::of("[a-z]{1,8}"), sid()).prop_map(|(subject, queue, sid)| Op::Sub {
+            subject,
+            queue,
+            sid
+        }),
+        (sid(), option::of(0..10_000usize)).prop_map(|(sid, max_msgs)| Op::Unsub { sid, max_msgs }),
+    ]
+}
+
+fn encode_op(op: &Op, buf: &mut Vec<u8>) {
+    match op {
+        Op::Pub {
+            subject,
+            reply,
+            payload,
+        } => encode::publish(
+            buf,
+            subject.as_bytes(),
+            reply.as_ref().map(|r| r.as_bytes()),
+            payload,
+        ),
+        Op::HPub {
+            subject,
+            reply,
+            headers,
+            payload,
+        } => {
+            let headers: Vec<(&str, &str)> = headers
+                .iter()
+                .map(|(n, v)| (n.as_str(), v.as_str()))
+                .collect();
+            let mut block = Vec::new();
+            encode::header_block(&mut block, &headers);
+            encode::hpublish(
+                buf,
+                subject.as_bytes(),
+                reply.as_ref().map(|r| r.as_bytes()),
+                &block,
+                payload,
+            )
+        }
+        Op::Sub {
+            subject,
+            queue,
+            sid,
+        } => encode::subscribe(
+            buf,
+            subject.as_bytes(),
+            queue.as_ref().map(|q| q.as_bytes()),
+            sid.as_bytes(),
+        ),
+        Op::Unsub { sid, max_msgs } => encode::unsubscribe(buf, sid.as_bytes(), *max_msgs),
+    }
+}
+
+/// Encode a parsed operation back.
+fn encode_result(res: ParseResult, buf: &mut Vec<u8>) {
+    match res {
+        ParseResult::Pub(arg) => encode::publish(
+            buf,
+            arg.subject.as_bytes(),
+            arg.reply.map(str::as_bytes),
+            arg.msg,
+        ),
+        ParseResult::HPub(arg) => {
+            let mut block = Vec::new();
+            encode::header_block(&mut block, &arg.headers);
+            assert_eq!(block.len(), arg.header_size);
+            encode::hpublish(
+                buf,
+                arg.subject.as_bytes(),
+                arg.reply.map(str::as_bytes),
+                &block,
+                arg.msg,
+            )
+        }
+        ParseResult::Sub(arg) => encode::subscribe(
+            buf,
+            arg.subject.as_bytes(),
+            arg.queue.map(str::as_bytes),
+            arg.sid.as_bytes(),
+        ),
+        ParseResult::Unsub(arg) => encode::unsubscribe(buf, arg.sid.as_bytes(), arg.max_msgs),
+        res => panic!("unexpected operation {:?}", res),
+    }
+}
+
+proptest! {
+    #[test]
+    fn round_trip(ops in vec(op(), 1..20), splits in vec(any::<prop::sample::Index>(), 0..8)) {
+        let mut stream = Vec::new();
+        for op in &ops {
+            encode_op(op, &mut stream);
+        }
+        let mut splits: Vec<usize> = splits.iter().map(|i| i.index(stream.len() + 1)).collect();
+        splits.push(stream.len());
+        splits.sort_unstable();
+
+        let mut p = Parser::new(ParserOptions::default());
+        let mut out = Vec::new();
+        let mut count = 0;
+        let mut start = 0;
+        for end in splits {
+            count += p
+                .parse_all(&stream[start..end], |res| encode_result(res, &mut out))
+                .unwrap();
+            start = end;
+        }
+        prop_assert_eq!(count, ops.len());
+        prop_assert_eq!(out, stream);
+    }
+}